@@ -125,6 +125,8 @@ pub mod coresdk {
                     is_timed_out: reason == ActivityCancelReason::TimedOut,
                     is_worker_shutdown: reason == ActivityCancelReason::WorkerShutdown,
                     is_reset: reason == ActivityCancelReason::Reset,
+                    is_workflow_completed: reason == ActivityCancelReason::WorkflowCompleted,
+                    is_deadline_approaching: reason == ActivityCancelReason::DeadlineApproaching,
                 }
             }
         }
@@ -1338,6 +1340,8 @@ pub mod coresdk {
                         retry_policy: r.retry_policy.map(fix_retry_policy),
                         priority: r.priority,
                         is_local: false,
+                        non_idempotent: false,
+                        first_attempt: r.attempt <= 1,
                     },
                 )),
             }
@@ -118,6 +118,12 @@ where
             .map(|ap| ap + self.unused_claimants.load(Ordering::Acquire))
     }
 
+    /// The number of permits which have been acquired but not yet marked as used
+    #[cfg(test)]
+    pub(crate) fn unused_claimants(&self) -> usize {
+        self.unused_claimants.load(Ordering::Acquire)
+    }
+
     pub(crate) async fn acquire_owned(&self) -> OwnedMeteredSemPermit<SK> {
         if let Some(max) = self.max_permits {
             self.extant_permits
@@ -38,18 +38,29 @@ pub use crate::worker::client::{
     PollActivityOptions, PollOptions, PollWorkflowOptions, WorkerClient, WorkflowTaskCompletion,
 };
 pub use pollers::{Client, ClientOptions, ClientTlsOptions, RetryOptions, TlsOptions};
+pub use protosext::LACloseTimeouts;
 pub use temporalio_common::protos::TaskToken;
 pub use url::Url;
 pub use worker::{
-    ActivitySlotKind, CompleteActivityError, CompleteNexusError, CompleteWfError,
-    FixedSizeSlotSupplier, LocalActivitySlotKind, NexusSlotKind, PollError, PollerBehavior,
-    ResourceBasedSlotsOptions, ResourceBasedSlotsOptionsBuilder, ResourceBasedTuner,
-    ResourceSlotOptions, SlotInfo, SlotInfoTrait, SlotKind, SlotKindType, SlotMarkUsedContext,
-    SlotReleaseContext, SlotReservationContext, SlotSupplier, SlotSupplierOptions,
-    SlotSupplierPermit, TunerBuilder, TunerHolder, TunerHolderOptions, TunerHolderOptionsBuilder,
-    Worker, WorkerConfig, WorkerConfigBuilder, WorkerTuner, WorkerValidationError,
-    WorkerVersioningStrategy, WorkflowErrorType, WorkflowSlotKind,
+    ActivitySlotKind, AttemptDispatchHook, BackingOffLaDiagnostics, BackoffFn,
+    CircuitBreakerOptions, CompleteActivityError, CompleteNexusError, CompleteWfError,
+    ContextPropagator, DefaultFailureClassifier, DispatchRateLimit, ExecutingLAId,
+    ExtendTimeoutError, FailureClassification, FailureClassifier, FixedSizeSlotSupplier,
+    InMemoryLaStateStore, LaDiagnostics, LaEvent, LaLifetimeStats, LaStateStore,
+    LaTimeoutDiagnostics, LocalActivityError, LocalActivityExecutionResult,
+    LocalActivityManagerOptions, LocalActivitySlotKind, LocalExecutor, NexusSlotKind,
+    NoopContextPropagator, OutstandingLaDiagnostics, PayloadRedactor, PersistedLa,
+    PersistedLaPhase, PersistedLaState, PollError, PollerBehavior, ResourceBasedSlotsOptions,
+    ResourceBasedSlotsOptionsBuilder, ResourceBasedTuner, ResourceSlotOptions, ResultInterceptor,
+    RetryDispatchJitter, RetryingAttemptFailure, RunLaMetrics, SlotInfo, SlotInfoTrait, SlotKind,
+    SlotKindType, SlotMarkUsedContext, SlotReleaseContext, SlotReservationContext, SlotSupplier,
+    SlotSupplierOptions, SlotSupplierPermit, TimeoutInfo, TunerBuilder, TunerHolder,
+    TunerHolderOptions, TunerHolderOptionsBuilder, UnknownTokenPolicy, Worker, WorkerConfig,
+    WorkerConfigBuilder, WorkerTuner, WorkerValidationError, WorkerVersioningStrategy,
+    WorkflowErrorType, WorkflowSlotKind,
 };
+#[cfg(any(feature = "test-utilities", test))]
+pub use worker::{ChaosConfig, DispatchObserver, LocalActivityProgress};
 
 use crate::{
     replay::{HistoryForReplay, ReplayWorkerInput},
@@ -306,9 +306,7 @@ impl TryFrom<activity_execution_result::Status> for LocalActivityExecutionResult
 }
 
 /// Validated version of [ScheduleLocalActivity]. See it for field docs.
-/// One or both of `schedule_to_close_timeout` and `start_to_close_timeout` are guaranteed to exist.
 #[derive(Debug, Clone)]
-#[cfg_attr(test, derive(Default))]
 pub(crate) struct ValidScheduleLA {
     pub(crate) seq: u32,
     pub(crate) activity_id: String,
@@ -318,18 +316,49 @@ pub(crate) struct ValidScheduleLA {
     pub(crate) headers: HashMap<String, Payload>,
     pub(crate) arguments: Vec<Payload>,
     pub(crate) schedule_to_start_timeout: Option<Duration>,
-    pub(crate) close_timeouts: LACloseTimeouts,
+    /// If set, a schedule-to-start timeout is retried rather than resolving the activity
+    pub(crate) retry_schedule_to_start_timeout: bool,
+    /// If set, attempts which are cancelled or time out are never retried
+    pub(crate) non_idempotent: bool,
+    /// If set, local retries get their own schedule-to-close window instead of sharing one
+    pub(crate) reset_schedule_time_on_retry: bool,
+    /// If false, final start-to-close and schedule-to-close timeouts are reported to workflows as
+    /// cancellations. Unset means true.
+    pub(crate) report_timeouts: Option<bool>,
+    /// Unset if lang left it empty
+    pub(crate) correlation_id: Option<String>,
+    /// Absolute time by which the activity times out, on top of any schedule-to-close timeout
+    pub(crate) deadline: Option<SystemTime>,
+    /// Unset if lang specified neither close timeout, in which case the local activity manager
+    /// falls back to a default for the activity type, or fails the activity if there is none.
+    pub(crate) close_timeouts: Option<LACloseTimeouts>,
     pub(crate) retry_policy: ValidatedRetryPolicy,
     pub(crate) local_retry_threshold: Duration,
     pub(crate) cancellation_type: ActivityCancellationType,
     pub(crate) user_metadata: Option<UserMetadata>,
+    /// How long to wait before dispatching the first attempt
+    pub(crate) start_delay: Option<Duration>,
+    /// Backoffs longer than this always use a lang-side timer
+    pub(crate) max_local_backoff: Option<Duration>,
+    /// Set if the retry policy lang provided was malformed, in which case the activity must be
+    /// failed rather than run with a policy the user didn't ask for
+    pub(crate) retry_policy_error: Option<String>,
 }
 
+/// The close timeouts of a local activity, at least one of which is always set
 #[derive(Debug, Clone, Copy)]
-pub(crate) enum LACloseTimeouts {
+pub enum LACloseTimeouts {
+    /// Only a schedule-to-close timeout
     ScheduleOnly(Duration),
+    /// Only a start-to-close timeout
     StartOnly(Duration),
-    Both { sched: Duration, start: Duration },
+    /// Both timeouts. Start-to-close should not be longer than schedule-to-close.
+    Both {
+        /// Schedule-to-close timeout
+        sched: Duration,
+        /// Start-to-close timeout
+        start: Duration,
+    },
 }
 
 impl LACloseTimeouts {
@@ -345,9 +374,33 @@ impl LACloseTimeouts {
 }
 
 #[cfg(test)]
-impl Default for LACloseTimeouts {
+impl Default for ValidScheduleLA {
     fn default() -> Self {
-        LACloseTimeouts::ScheduleOnly(Duration::from_secs(100))
+        Self {
+            seq: Default::default(),
+            activity_id: Default::default(),
+            activity_type: Default::default(),
+            attempt: Default::default(),
+            original_schedule_time: Default::default(),
+            headers: Default::default(),
+            arguments: Default::default(),
+            schedule_to_start_timeout: Default::default(),
+            retry_schedule_to_start_timeout: Default::default(),
+            non_idempotent: Default::default(),
+            reset_schedule_time_on_retry: Default::default(),
+            report_timeouts: Default::default(),
+            correlation_id: Default::default(),
+            deadline: Default::default(),
+            // Activities without close timeouts are failed unless their type has a default
+            close_timeouts: Some(LACloseTimeouts::ScheduleOnly(Duration::from_secs(100))),
+            retry_policy: Default::default(),
+            local_retry_threshold: Default::default(),
+            cancellation_type: Default::default(),
+            user_metadata: Default::default(),
+            start_delay: Default::default(),
+            max_local_backoff: Default::default(),
+            retry_policy_error: Default::default(),
+        }
     }
 }
 
@@ -363,6 +416,13 @@ impl ValidScheduleLA {
                     .map_err(|_| anyhow!("Could not convert original_schedule_time"))
             })
             .transpose()?;
+        let deadline = v
+            .deadline
+            .map(|x| {
+                x.try_into()
+                    .map_err(|_| anyhow!("Could not convert deadline"))
+            })
+            .transpose()?;
         let sched_to_close = v
             .schedule_to_close_timeout
             .map(|x| {
@@ -393,30 +453,33 @@ impl ValidScheduleLA {
                 })
                 .transpose()?,
         ) {
-            (Some(sch), None) => LACloseTimeouts::ScheduleOnly(sch),
-            (None, Some(start)) => LACloseTimeouts::StartOnly(start),
+            (Some(sch), None) => Some(LACloseTimeouts::ScheduleOnly(sch)),
+            (None, Some(start)) => Some(LACloseTimeouts::StartOnly(start)),
             (Some(sched), Some(mut start)) => {
                 // Clamp start-to-close if larger than schedule-to-close
                 if start > sched {
                     start = sched;
                 }
-                LACloseTimeouts::Both { sched, start }
-            }
-            (None, None) => {
-                return Err(anyhow!(
-                    "One or both of schedule_to_close or start_to_close timeouts must be set for \
-                     local activities"
-                ));
+                Some(LACloseTimeouts::Both { sched, start })
             }
+            // The local activity manager may have a default for the activity type
+            (None, None) => None,
         };
-        let retry_policy =
-            ValidatedRetryPolicy::from_proto_with_defaults(v.retry_policy.unwrap_or_default());
+        let retry_policy = v.retry_policy.unwrap_or_default();
+        let retry_policy_error = ValidatedRetryPolicy::malformed_reason(&retry_policy);
+        let retry_policy = ValidatedRetryPolicy::from_proto_with_defaults(retry_policy);
         let local_retry_threshold = v
             .local_retry_threshold
             .try_into_or_none()
             .unwrap_or_else(|| Duration::from_secs(60));
         let cancellation_type = ActivityCancellationType::try_from(v.cancellation_type)
             .unwrap_or(ActivityCancellationType::WaitCancellationCompleted);
+        // Attempts scheduled after a timer backoff have already waited
+        let start_delay = if v.attempt > 1 {
+            None
+        } else {
+            v.start_delay.try_into_or_none()
+        };
         Ok(ValidScheduleLA {
             seq: v.seq,
             activity_id: v.activity_id,
@@ -426,11 +489,20 @@ impl ValidScheduleLA {
             headers: v.headers,
             arguments: v.arguments,
             schedule_to_start_timeout,
+            retry_schedule_to_start_timeout: v.retry_schedule_to_start_timeout,
+            non_idempotent: v.non_idempotent,
+            reset_schedule_time_on_retry: v.reset_schedule_time_on_retry,
+            report_timeouts: v.report_timeouts,
+            correlation_id: Some(v.correlation_id).filter(|c| !c.is_empty()),
+            deadline,
             close_timeouts,
             retry_policy,
             local_retry_threshold,
             cancellation_type,
             user_metadata,
+            start_delay,
+            max_local_backoff: v.max_local_backoff.try_into_or_none(),
+            retry_policy_error,
         })
     }
 }
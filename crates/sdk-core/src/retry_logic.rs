@@ -60,6 +60,47 @@ impl ValidatedRetryPolicy {
         }
     }
 
+    /// Strictly checks a retry policy, returning a description of the first problem found, if any.
+    /// Unset fields are fine, since they have defaults, but explicitly set values which
+    /// [Self::from_proto_with_defaults] would have to silently replace are not.
+    pub(crate) fn malformed_reason(retry_policy: &RetryPolicy) -> Option<String> {
+        let is_negative = |d: &prost_types::Duration| d.seconds < 0 || d.nanos < 0;
+        if retry_policy.backoff_coefficient != 0.0 && retry_policy.backoff_coefficient < 1.0 {
+            return Some(format!(
+                "backoff_coefficient must be >= 1, got {}",
+                retry_policy.backoff_coefficient
+            ));
+        }
+        if let Some(i) = retry_policy
+            .initial_interval
+            .as_ref()
+            .filter(|d| is_negative(d))
+        {
+            return Some(format!("initial_interval must not be negative, got {i:?}"));
+        }
+        if let Some(m) = retry_policy
+            .maximum_interval
+            .as_ref()
+            .filter(|d| is_negative(d))
+        {
+            return Some(format!("maximum_interval must not be negative, got {m:?}"));
+        }
+        if let (Some(initial), Some(max)) = (
+            retry_policy
+                .initial_interval
+                .and_then(|i| Duration::try_from(i).ok()),
+            retry_policy
+                .maximum_interval
+                .and_then(|i| Duration::try_from(i).ok()),
+        ) && max < initial
+        {
+            return Some(format!(
+                "maximum_interval ({max:?}) must be >= initial_interval ({initial:?})"
+            ));
+        }
+        None
+    }
+
     /// Ask this retry policy if a retry should be performed. Caller provides the current attempt
     /// number - the first attempt should start at 1.
     ///
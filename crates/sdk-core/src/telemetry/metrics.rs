@@ -51,6 +51,10 @@ struct Instruments {
     la_exec_latency: HistogramDuration,
     la_exec_succeeded_latency: HistogramDuration,
     la_total: Counter,
+    la_permit_wait_latency: HistogramDuration,
+    la_duplicate_enqueue: Counter,
+    la_backoff_latency: HistogramDuration,
+    la_permit_waiters: Gauge,
     nexus_poll_no_task: Counter,
     nexus_task_schedule_to_start_latency: HistogramDuration,
     nexus_task_e2e_latency: HistogramDuration,
@@ -107,6 +111,12 @@ impl MetricsContext {
         }
     }
 
+    /// Derive a context whose metrics are additionally labelled with the activity type. Workers
+    /// only ever see a bounded set of activity types, so this doesn't blow up label cardinality.
+    pub(crate) fn with_activity_type(&self, ty: impl Into<String>) -> Self {
+        self.with_new_attrs([activity_type(ty.into())])
+    }
+
     /// Extend an existing metrics context with new attributes
     pub(crate) fn with_new_attrs(
         &self,
@@ -233,6 +243,27 @@ impl MetricsContext {
         self.instruments.la_total.adds(1);
     }
 
+    /// Record how long a local activity request waited for a slot permit after it was received
+    pub(crate) fn la_permit_wait_latency(&self, dur: Duration) {
+        self.instruments.la_permit_wait_latency.records(dur);
+    }
+
+    /// A local activity was not enqueued because it was already queued or executing
+    pub(crate) fn la_duplicate_enqueue(&self) {
+        self.instruments.la_duplicate_enqueue.adds(1);
+    }
+
+    /// Record how long a local activity waited between a failed attempt and its retry being
+    /// dispatched
+    pub(crate) fn la_backoff_latency(&self, dur: Duration) {
+        self.instruments.la_backoff_latency.records(dur);
+    }
+
+    /// Record the number of local activity requests currently waiting for a slot permit
+    pub(crate) fn la_permit_waiters(&self, num: usize) {
+        self.instruments.la_permit_waiters.records(num as u64);
+    }
+
     /// A nexus long poll timed out
     pub(crate) fn nexus_poll_timeout(&self) {
         self.instruments.nexus_poll_no_task.adds(1);
@@ -441,6 +472,34 @@ impl Instruments {
                 description: "Count of local activities executed".into(),
                 unit: "".into(),
             }),
+            la_permit_wait_latency: meter.histogram_duration(MetricParameters {
+                name: "local_activity_slot_wait_latency".into(),
+                unit: "duration".into(),
+                description:
+                    "Histogram of time local activities spent waiting for a slot to be available"
+                        .into(),
+            }),
+            la_duplicate_enqueue: meter.counter(MetricParameters {
+                name: "local_activity_duplicate_enqueue".into(),
+                description:
+                    "Count of local activity schedule requests dropped as duplicates of one already \
+                     queued or executing"
+                        .into(),
+                unit: "".into(),
+            }),
+            la_backoff_latency: meter.histogram_duration(MetricParameters {
+                name: "local_activity_backoff_latency".into(),
+                unit: "duration".into(),
+                description:
+                    "Histogram of time local activities spent backing off before being retried"
+                        .into(),
+            }),
+            la_permit_waiters: meter.gauge(MetricParameters {
+                name: "local_activity_slot_waiters".into(),
+                description: "Number of local activities waiting for a slot to be available"
+                    .into(),
+                unit: "".into(),
+            }),
             nexus_poll_no_task: meter.counter(MetricParameters {
                 name: "nexus_poll_no_task".into(),
                 description: "Count of nexus task queue poll timeouts (no new task)".into(),
@@ -556,6 +615,14 @@ impl Instruments {
         self.la_exec_succeeded_latency
             .update_attributes(new_attributes.clone());
         self.la_total.update_attributes(new_attributes.clone());
+        self.la_permit_wait_latency
+            .update_attributes(new_attributes.clone());
+        self.la_duplicate_enqueue
+            .update_attributes(new_attributes.clone());
+        self.la_backoff_latency
+            .update_attributes(new_attributes.clone());
+        self.la_permit_waiters
+            .update_attributes(new_attributes.clone());
         self.nexus_poll_no_task
             .update_attributes(new_attributes.clone());
         self.nexus_task_schedule_to_start_latency
@@ -1090,7 +1157,7 @@ mod tests {
     use super::*;
     use std::any::Any;
     use temporalio_common::telemetry::{
-        TelemetryOptions,
+        TaskQueueLabelStrategy, TelemetryOptions,
         metrics::core::{BufferInstrumentRef, CustomMetricAttributes},
         telemetry_init,
     };
@@ -1155,7 +1222,7 @@ mod tests {
         a2.set(Arc::new(DummyCustomAttrs(2))).unwrap();
         // Verify all metrics are created. This number will need to get updated any time a metric
         // is added.
-        let num_metrics = 35;
+        let num_metrics = 39;
         #[allow(clippy::needless_range_loop)] // Sorry clippy, this reads easier.
         for metric_num in 2..=num_metrics + 1 {
             let hole = assert_matches!(&events[metric_num],
@@ -1201,6 +1268,57 @@ mod tests {
         );
     }
 
+    #[test]
+    fn activity_type_child_context_labels_recordings() {
+        let call_buffer = Arc::new(MetricsCallBuffer::new(100));
+        let mc = MetricsContext::top_level_with_meter(
+            "foo".to_string(),
+            "q".to_string(),
+            Some(TemporalMeter::new(
+                call_buffer.clone(),
+                Default::default(),
+                TaskQueueLabelStrategy::UseNormal,
+            )),
+        );
+        for event in call_buffer.retrieve() {
+            match event {
+                MetricEvent::Create { populate_into, .. } => {
+                    populate_into.set(Arc::new(DummyInstrumentRef(0))).unwrap()
+                }
+                MetricEvent::CreateAttributes { populate_into, .. } => {
+                    populate_into.set(Arc::new(DummyCustomAttrs(0))).unwrap()
+                }
+                MetricEvent::Update { .. } => {}
+            }
+        }
+
+        let child = mc.with_activity_type("my_act");
+        child.la_executed();
+        let events = call_buffer.retrieve();
+        let attrs = assert_matches!(
+            &events[0],
+            MetricEvent::CreateAttributes {
+                populate_into,
+                append_from: Some(_),
+                attributes,
+            }
+            if attributes == &[activity_type("my_act".to_string())]
+            => populate_into
+        );
+        attrs.set(Arc::new(DummyCustomAttrs(1))).unwrap();
+        assert_matches!(
+            &events[1],
+            MetricEvent::Update { attributes, update: MetricUpdateVal::Delta(1), .. }
+            if DummyCustomAttrs::as_id(attributes) == 1
+        );
+        // The parent is unaffected
+        mc.la_executed();
+        assert_matches!(
+            &call_buffer.retrieve()[0],
+            MetricEvent::Update { attributes, .. } if DummyCustomAttrs::as_id(attributes) == 0
+        );
+    }
+
     #[test]
     fn metric_buffer() {
         let call_buffer = MetricsCallBuffer::new(10);
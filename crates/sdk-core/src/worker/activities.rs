@@ -1,9 +1,21 @@
 mod activity_heartbeat_manager;
 mod local_activities;
 
+pub use local_activities::{
+    AttemptDispatchHook, BackingOffLaDiagnostics, BackoffFn, CircuitBreakerOptions,
+    ContextPropagator, DefaultFailureClassifier, DispatchRateLimit, ExecutingLAId,
+    ExtendTimeoutError, FailureClassification, FailureClassifier, InMemoryLaStateStore,
+    LaDiagnostics, LaEvent, LaLifetimeStats, LaStateStore, LaTimeoutDiagnostics,
+    LocalActivityError, LocalActivityExecutionResult, LocalActivityManagerOptions, LocalExecutor,
+    NoopContextPropagator, OutstandingLaDiagnostics, PayloadRedactor, PersistedLa,
+    PersistedLaPhase, PersistedLaState, ResultInterceptor, RetryDispatchJitter,
+    RetryingAttemptFailure, RunLaMetrics, TimeoutInfo, UnknownTokenPolicy,
+};
+#[cfg(any(feature = "test-utilities", test))]
+pub use local_activities::{ChaosConfig, DispatchObserver, LocalActivityProgress};
 pub(crate) use local_activities::{
-    ExecutingLAId, LACompleteAction, LocalActRequest, LocalActivityExecutionResult,
-    LocalActivityManager, LocalActivityResolution, NewLocalAct, NextPendingLAAction,
+    LACompleteAction, LocalActRequest, LocalActivityManager, LocalActivityResolution, NewLocalAct,
+    NextPendingLAAction,
 };
 
 use crate::{
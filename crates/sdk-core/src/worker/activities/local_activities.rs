@@ -1,38 +1,52 @@
 use crate::{
     MetricsContext, TaskToken,
     abstractions::{MeteredPermitDealer, OwnedMeteredSemPermit, UsedMeteredSemPermit, dbg_panic},
-    protosext::ValidScheduleLA,
-    telemetry::metrics::{activity_type, should_record_failure_metric, workflow_type},
+    protosext::{LACloseTimeouts, ValidScheduleLA},
+    telemetry::metrics::{should_record_failure_metric, workflow_type},
     worker::{LocalActivitySlotKind, workflow::HeartbeatTimeoutMsg},
 };
 use futures_util::{
-    Stream, StreamExt, future, future::AbortRegistration, stream, stream::BoxStream,
+    Stream, StreamExt, future,
+    future::AbortRegistration,
+    stream,
+    stream::{BoxStream, PollNext},
 };
+use lru::LruCache;
 use parking_lot::{Mutex, MutexGuard};
+use prost::Message;
+#[cfg(any(feature = "test-utilities", test))]
+use rand::Rng;
+use rand::{SeedableRng, rngs::StdRng};
 use std::{
-    collections::{HashMap, hash_map::Entry},
+    collections::{HashMap, HashSet, VecDeque, hash_map::Entry},
     fmt::{Debug, Formatter},
-    num::NonZero,
+    panic::{AssertUnwindSafe, catch_unwind},
     pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
     task::{Context, Poll},
     time::{Duration, Instant, SystemTime},
 };
 use temporalio_common::protos::{
     coresdk::{
-        LocalActivitySlotInfo,
+        AsJsonPayloadExt, LocalActivitySlotInfo,
         activity_result::{Cancellation, Failure as ActFail, Success},
         activity_task::{ActivityCancelReason, ActivityTask, Start, activity_task},
     },
     temporal::api::{
-        common::v1::WorkflowExecution,
+        common::v1::{Payload, WorkflowExecution},
         enums::v1::TimeoutType,
-        failure::v1::{Failure as APIFailure, TimeoutFailureInfo, failure},
+        failure::v1::{ApplicationFailureInfo, Failure as APIFailure, TimeoutFailureInfo, failure},
     },
 };
 use tokio::{
+    runtime::Handle,
     sync::{
-        Notify,
+        Notify, broadcast,
         mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+        oneshot, watch,
     },
     task::JoinHandle,
     time::sleep,
@@ -40,6 +54,23 @@ use tokio::{
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use tokio_util::sync::CancellationToken;
 
+mod persistence;
+mod retry;
+mod serialization;
+mod timeouts;
+
+pub use persistence::{
+    InMemoryLaStateStore, LaStateStore, PersistedLa, PersistedLaPhase, PersistedLaState,
+};
+pub use retry::{BackoffFn, RetryDispatchJitter};
+pub(crate) use timeouts::DEFAULT_CLOCK_SKEW_TOLERANCE;
+pub use timeouts::{ExtendTimeoutError, TimeoutInfo};
+
+use persistence::LaStoreOp;
+use retry::{PendingRetry, RetryBackoff};
+use serialization::SerializedRun;
+use timeouts::TimeoutBag;
+
 #[allow(clippy::large_enum_variant)] // Timeouts are relatively rare
 #[derive(Debug)]
 pub(crate) enum NextPendingLAAction {
@@ -52,22 +83,40 @@ pub(crate) enum NextPendingLAAction {
 #[derive(Debug)]
 struct LocalInFlightActInfo {
     la_info: NewLocalAct,
-    dispatch_time: Instant,
+    /// Tokio's, so paused time in tests applies to it
+    dispatch_time: tokio::time::Instant,
     attempt: u32,
+    /// The most recently recorded heartbeat details, carried over into the next attempt if this
+    /// one is retried.
+    last_heartbeat_details: Vec<Payload>,
+    /// Set once a cancel for this attempt has been handed to lang, so repeats can be dropped
+    cancel_dispatched: bool,
     _permit: UsedMeteredSemPermit<LocalActivitySlotKind>,
 }
 
+/// How an attempt at a local activity ended
 #[derive(Debug, Clone)]
-pub(crate) enum LocalActivityExecutionResult {
+pub enum LocalActivityExecutionResult {
+    /// The activity succeeded
     Completed(Success),
+    /// The activity failed
     Failed(ActFail),
+    /// The activity did not finish within one of its timeouts
     TimedOut(ActFail),
+    /// The activity was cancelled
     Cancelled(Cancellation),
 }
 impl LocalActivityExecutionResult {
     pub(crate) fn empty_cancel() -> Self {
         Self::Cancelled(Cancellation::from_details(None))
     }
+    /// A failure that the retry policy will never retry, for activities that core refuses to
+    /// dispatch in the first place.
+    pub(crate) fn non_retryable_failure(message: String) -> Self {
+        Self::Failed(ActFail {
+            failure: Some(APIFailure::application_failure(message, true)),
+        })
+    }
     pub(crate) fn timeout(tt: TimeoutType) -> Self {
         Self::TimedOut(ActFail {
             failure: Some(APIFailure {
@@ -110,6 +159,38 @@ pub(crate) struct LocalActivityResolution {
     pub(crate) attempt: u32,
     pub(crate) backoff: Option<prost_types::Duration>,
     pub(crate) original_schedule_time: Option<SystemTime>,
+    /// Echoed from [NewLocalAct::correlation_id]
+    pub(crate) correlation_id: Option<String>,
+    /// The close timeouts which were actually in force, for explaining when a timeout fired
+    pub(crate) effective_timeouts: EffectiveTimeouts,
+}
+
+/// Close timeouts as they were actually applied to an activity, which can differ from how they were
+/// configured. Unset timeouts, and activities resolved before any timers were set, have `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct EffectiveTimeouts {
+    /// What remained of the schedule-to-close timeout once time which had already passed since
+    /// the original schedule time was subtracted. Doesn't include any start delay, which it
+    /// is extended by.
+    pub(crate) schedule_to_close: Option<Duration>,
+    /// The start-to-close timeout of the last attempt, including any extension it was given
+    pub(crate) start_to_close: Option<Duration>,
+}
+
+impl LocalActivityResolution {
+    /// Builds a resolution for an activity which is being resolved before it was ever dispatched
+    pub(crate) fn immediate(act: &NewLocalAct, result: LocalActivityExecutionResult) -> Self {
+        Self {
+            seq: act.schedule_cmd.seq,
+            result,
+            runtime: Duration::ZERO,
+            attempt: act.schedule_cmd.attempt,
+            backoff: None,
+            original_schedule_time: act.schedule_cmd.original_schedule_time,
+            correlation_id: act.correlation_id.clone(),
+            effective_timeouts: Default::default(),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -118,6 +199,35 @@ pub(crate) struct NewLocalAct {
     pub(crate) workflow_type: String,
     pub(crate) workflow_exec_info: WorkflowExecution,
     pub(crate) schedule_time: SystemTime,
+    /// Opaque identifier for tying the activity to external traces. Passed to lang in the
+    /// [LA_CORRELATION_ID_HEADER] header of every attempt, and included in every resolution.
+    pub(crate) correlation_id: Option<String>,
+    /// Build id of the worker which scheduled the activity, if it is versioned. Passed to lang in
+    /// the [LA_BUILD_ID_HEADER] header of every attempt, so it can refuse to run activities
+    /// scheduled by an incompatible version, as the server does for normal activities.
+    pub(crate) build_id: Option<String>,
+}
+
+/// Header which carries [NewLocalAct::correlation_id] (JSON encoded) to lang
+pub(crate) const LA_CORRELATION_ID_HEADER: &str = "__temporal_la_correlation_id";
+/// Header which carries [NewLocalAct::build_id] (JSON encoded) to lang
+pub(crate) const LA_BUILD_ID_HEADER: &str = "__temporal_la_build_id";
+
+#[cfg(test)]
+impl Default for NewLocalAct {
+    fn default() -> Self {
+        Self {
+            schedule_cmd: Default::default(),
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            correlation_id: None,
+            build_id: None,
+        }
+    }
 }
 
 impl Debug for NewLocalAct {
@@ -137,6 +247,11 @@ pub(crate) enum LocalActRequest {
     Cancel(ExecutingLAId),
     #[from(ignore)]
     CancelAllInRun(String),
+    /// Cancel all LAs for the run because the workflow is completing. Unlike
+    /// [LocalActRequest::CancelAllInRun], none of them will be retried regardless of how they
+    /// resolve.
+    #[from(ignore)]
+    CancelForWorkflowCompletion(String),
     StartHeartbeatTimeout {
         send_on_elapse: HeartbeatTimeoutMsg,
         deadline: Instant,
@@ -148,48 +263,767 @@ pub(crate) enum LocalActRequest {
     IndicateWorkflowTaskCompleted(String),
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
-pub(crate) struct ExecutingLAId {
-    pub(crate) run_id: String,
-    pub(crate) seq_num: u32,
+/// Identifies a local activity by the workflow run which scheduled it
+#[derive(Debug, Clone, Eq, PartialEq, Hash, serde::Serialize)]
+pub struct ExecutingLAId {
+    /// The run id of the workflow which scheduled the activity
+    pub run_id: String,
+    /// The sequence number the workflow scheduled the activity with
+    pub seq_num: u32,
+}
+
+/// A step in the lifecycle of a local activity. See
+/// `Worker::subscribe_local_activity_events`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LaEvent {
+    /// The activity was accepted and is waiting to be dispatched
+    Queued(ExecutingLAId),
+    /// An attempt was handed to lang
+    Dispatched {
+        /// The activity the attempt belongs to
+        id: ExecutingLAId,
+        /// The attempt's number, starting from 1
+        attempt: u32,
+    },
+    /// An attempt failed or timed out, and the next one will be dispatched after backing off
+    /// within core
+    BackingOff {
+        /// The activity the attempt belongs to
+        id: ExecutingLAId,
+        /// The number of the attempt which failed
+        failed_attempt: u32,
+        /// How long until the next attempt is dispatched
+        backoff: Duration,
+    },
+    /// The activity is no longer tracked, and its resolution is to be reported to the workflow
+    Resolved {
+        /// The resolved activity
+        id: ExecutingLAId,
+        /// The number of the attempt which produced the resolution
+        attempt: u32,
+    },
+}
+
+/// How many events a subscriber can fall behind by before it starts missing them
+const LA_EVENT_CAPACITY: usize = 1024;
+
+/// Intermediate output reported by an executing local activity. See
+/// [crate::Worker::record_local_activity_progress].
+#[cfg(any(feature = "test-utilities", test))]
+#[derive(Debug, Clone)]
+pub struct LocalActivityProgress {
+    /// The activity which reported it
+    pub id: ExecutingLAId,
+    /// The attempt which reported it
+    pub attempt: u32,
+    /// Whatever the activity reported
+    pub payload: Payload,
+}
+
+/// A failed local activity attempt which is being retried locally. See
+/// [LocalActivityManagerOptions::retrying_failures_tx].
+#[derive(Debug, Clone)]
+pub struct RetryingAttemptFailure {
+    /// The activity which failed
+    pub id: ExecutingLAId,
+    /// The attempt which failed
+    pub attempt: u32,
+    /// How the attempt failed or timed out
+    pub result: LocalActivityExecutionResult,
+    /// How long until the next attempt
+    pub backoff: Duration,
+}
+
+/// A point in time view of everything the manager is tracking, meant to be dumped (ex: as JSON)
+/// into support bundles. See `Worker::local_activity_diagnostics`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LaDiagnostics {
+    /// Attempts currently handed out to lang
+    pub outstanding: Vec<OutstandingLaDiagnostics>,
+    /// Activities waiting to retry (or to start, if they had a start delay)
+    pub backing_off: Vec<BackingOffLaDiagnostics>,
+    /// Close timeouts of every tracked activity which has any
+    pub timeouts: Vec<LaTimeoutDiagnostics>,
+    /// Requests waiting in the dispatch queue, retries included. Only an estimate, since anything
+    /// received from the queue but not yet dispatched isn't counted.
+    pub queue_depth: usize,
+    /// Free local activity slots, if the slot supplier knows
+    pub available_slots: Option<usize>,
+}
+
+/// An attempt which is currently handed out to lang, as part of [LaDiagnostics]
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct OutstandingLaDiagnostics {
+    /// Which activity the attempt belongs to
+    pub id: ExecutingLAId,
+    /// The activity's type name
+    pub activity_type: String,
+    /// The attempt number
+    pub attempt: u32,
+    /// Time since the attempt was dispatched
+    pub elapsed: Duration,
+}
+
+/// An activity waiting out a backoff, as part of [LaDiagnostics]
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BackingOffLaDiagnostics {
+    /// Which activity is backing off
+    pub id: ExecutingLAId,
+    /// The attempt which failed and caused the backoff
+    pub failed_attempt: u32,
+    /// Time until the activity is sent to be dispatched again
+    pub remaining: Duration,
+}
+
+/// The close timeouts of one tracked activity, as part of [LaDiagnostics]
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LaTimeoutDiagnostics {
+    /// Which activity the timeouts belong to
+    pub id: ExecutingLAId,
+    /// The timeouts themselves. Flattened into this object when serialized.
+    #[serde(flatten)]
+    pub timeouts: TimeoutInfo,
+}
+
+/// The outcome of [LocalActivityManager::enqueue]
+#[derive(Debug, Default)]
+pub(crate) struct EnqueueResult {
+    /// Resolutions for activities which could be resolved without being executed
+    pub(crate) immediate_resolutions: Vec<LocalActivityResolution>,
+    /// New activity requests which were ignored, and why
+    pub(crate) dropped: Vec<(ExecutingLAId, EnqueueDropReason)>,
+}
+
+/// Reasons the manager may refuse an operation on a local activity, or unexpected conditions it
+/// handles rather than panicking over, so that one misbehaving activity can't take down the whole
+/// worker
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum LocalActivityError {
+    /// The backoff chosen for a retry is too long to be sent to lang as a timer
+    #[error("Local activity backoff of {0:?} does not fit into a proto duration")]
+    BackoffOutOfRange(Duration),
+    /// No outstanding attempt exists for the targeted local activity
+    #[error("No outstanding local activity with id {0:?}")]
+    NotOutstanding(ExecutingLAId),
+    /// A retry policy was rejected, for the same reasons it would be when scheduling an activity
+    #[error("Local activity retry policy is invalid: {0}")]
+    InvalidRetryPolicy(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EnqueueDropReason {
+    /// The activity is waiting to be dispatched, including while backing off between attempts
+    AlreadyQueued,
+    /// An attempt at the activity is currently executing
+    AlreadyExecuting,
+}
+
+/// Describes what the local activity manager waited on, and what it gave up on, while shutting
+/// down.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ShutdownSummary {
+    /// Attempts which finished while the wait was in progress, whether lang completed them or
+    /// they timed out. Ones abandoned by [LocalActivityManager::abort_all] aren't counted.
+    pub(crate) completed_during_shutdown: usize,
+    /// Resolutions of the activities that were backing off locally, which were resolved as
+    /// cancelled rather than retried
+    pub(crate) aborted_backoffs: Vec<LocalActivityResolution>,
+}
+
+/// Included (JSON encoded) as the cancellation details of activities which were cancelled while
+/// waiting to retry, rather than while an attempt was running.
+pub(crate) const CANCELLED_DURING_BACKOFF_DETAILS: &str = "cancelled_during_backoff";
+
+/// Turns a payload into what is logged in its place. Payloads may hold sensitive data, so this
+/// should not expose their contents unless that is known to be safe.
+pub type PayloadRedactor = Arc<dyn Fn(&Payload) -> String + Send + Sync>;
+
+/// The default [PayloadRedactor], which only shows a payload's size and metadata keys
+pub(crate) fn redact_payload(payload: &Payload) -> String {
+    let mut keys: Vec<_> = payload.metadata.keys().map(String::as_str).collect();
+    keys.sort_unstable();
+    format!(
+        "Payload({} bytes, metadata keys: [{}])",
+        payload.data.len(),
+        keys.join(", ")
+    )
+}
+
+/// Told about each dispatched attempt: which activity, its attempt number, and whether it's a retry
+pub type AttemptDispatchHook = Arc<dyn Fn(&ExecutingLAId, u32, bool) + Send + Sync>;
+
+/// Shown every activity task just before it is handed out to lang
+#[cfg(any(feature = "test-utilities", test))]
+pub type DispatchObserver = Arc<dyn Fn(&ActivityTask) + Send + Sync>;
+
+/// Rewrites an attempt's result before the manager acts on it
+pub type ResultInterceptor =
+    Arc<dyn Fn(LocalActivityExecutionResult) -> LocalActivityExecutionResult + Send + Sync>;
+
+/// Runs something that records metrics, returning `None` if it panicked. Metrics backends may be
+/// third party code, and one which panics shouldn't take down the worker, especially since
+/// recording often happens while the manager's lock is held.
+fn guard_metrics<T>(record: impl FnOnce() -> T) -> Option<T> {
+    catch_unwind(AssertUnwindSafe(record))
+        .inspect_err(|e| {
+            let msg = e
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| e.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("<non-string panic>");
+            error!(panic = msg, "Recording local activity metrics panicked");
+        })
+        .ok()
+}
+
+/// Optional configuration for how a worker runs local activities. The defaults are what a normal
+/// worker uses.
+#[derive(Clone, Default)]
+#[non_exhaustive]
+pub struct LocalActivityManagerOptions {
+    /// If set, local activities whose arguments total more than this many bytes are failed
+    /// immediately instead of being dispatched, since their results would eventually need to fit
+    /// into workflow history alongside them.
+    pub max_input_size: Option<usize>,
+    /// If set, successful results larger than this many bytes are turned into non-retryable
+    /// failures before being reported, rather than being written into workflow history.
+    pub max_result_size: Option<usize>,
+    /// If set, only activities of these types are dispatched. Any other type is failed immediately,
+    /// as lang has no way to run it.
+    pub allowed_activity_types: Option<HashSet<String>>,
+    /// Activities of these types are never dispatched, and are failed immediately instead. Takes
+    /// precedence over `allowed_activity_types`.
+    pub denied_activity_types: HashSet<String>,
+    /// Maximum number of concurrently executing local activities for specific activity types,
+    /// across all runs. Types not present are limited only by the overall slot supplier.
+    pub max_concurrent_per_type: HashMap<String, usize>,
+    /// Called with every activity task just before it is handed out to lang. Purely
+    /// observational - useful for recording the exact dispatch stream in tests.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub dispatch_observer: Option<DispatchObserver>,
+    /// Called with the activity, its attempt number, and whether it is a retry, whenever an
+    /// attempt is dispatched. Retries are told apart by how they were queued rather than by
+    /// attempt number, since lang may set the first attempt's number explicitly.
+    pub attempt_dispatch_hook: Option<AttemptDispatchHook>,
+    /// If set, an activity which lang was told to cancel but has not completed within this long
+    /// is resolved as cancelled anyway, so that it can't stay outstanding forever.
+    pub cancel_ack_timeout: Option<Duration>,
+    /// If set, worker shutdown waits at most this long for executing local activities to finish.
+    /// Any still executing after that are abandoned, so a hung activity can't hold up shutdown
+    /// forever. By default shutdown waits for all of them.
+    pub shutdown_grace_period: Option<Duration>,
+    /// If set, all tasks the manager spawns internally, like backoff and timeout timers, run on
+    /// this runtime instead of the ambient one, so that they cannot be starved by other work.
+    pub runtime: Option<Handle>,
+    /// If set, every failed attempt which is about to be retried locally is also sent here. The
+    /// workflow still never sees these failures, so this is purely for observability.
+    pub retrying_failures_tx: Option<UnboundedSender<RetryingAttemptFailure>>,
+    /// Where progress reported with [crate::Worker::record_local_activity_progress] is sent.
+    /// Progress is discarded if this isn't set.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub progress_tx: Option<UnboundedSender<LocalActivityProgress>>,
+    /// How far in the future an activity's schedule time may be, relative to our clock, before a
+    /// warning about clock skew is logged. Future schedule times are always treated as "now" for
+    /// the purposes of timeouts. Defaults to one second.
+    pub clock_skew_tolerance: Option<Duration>,
+    /// If set, an activity which has already been retried locally this many times will have all
+    /// further retries backed off using a lang-side timer. Guards against an activity with
+    /// unlimited attempts and short backoffs spinning in core forever.
+    pub max_local_retries: Option<u32>,
+    /// Backoffs sent to lang as timers are clamped to this, with a warning, rather than being
+    /// rejected for being too long. Can't be more than a proto duration can hold, which is also
+    /// the default.
+    pub max_timer_backoff: Option<Duration>,
+    /// If set, lang is sent a cancel with the deadline-approaching reason this long before an
+    /// activity's schedule-to-close timeout fires, so it can wind down before being timed out.
+    /// The timeout itself is unaffected.
+    pub deadline_warning_lead: Option<Duration>,
+    /// If set, retries performed locally always wait at least this long, however short the retry
+    /// policy's interval is. Keeps policies with tiny intervals from hammering whatever the
+    /// activity calls. Backoffs handed to lang as timers are unaffected.
+    pub min_local_backoff: Option<Duration>,
+    /// If set, bookkeeping about tracked activities is persisted here and restored when the
+    /// manager is created, so it can survive a worker restart. Activities the workflow schedules
+    /// again afterwards carry on from their recorded attempt and backoff.
+    pub state_store: Option<Arc<dyn LaStateStore>>,
+    /// Close timeouts to use for activities of the given types when lang didn't specify any
+    pub default_close_timeouts_per_type: HashMap<String, LACloseTimeouts>,
+    /// Applied to every result an activity completes with before anything else looks at it,
+    /// including the retry decision. Allows things like redacting payloads or treating certain
+    /// failures as successes without touching every activity.
+    pub result_interceptor: Option<ResultInterceptor>,
+    /// Consulted for every failed or timed out attempt before its retry policy is. Defaults to
+    /// [DefaultFailureClassifier].
+    pub failure_classifier: Option<Arc<dyn FailureClassifier>>,
+    /// Moves tracing context into dispatched attempts and out of their results. Defaults to
+    /// [NoopContextPropagator].
+    pub context_propagator: Option<Arc<dyn ContextPropagator>>,
+    /// If set, gets the chance to run each activity itself before it's dispatched to lang
+    pub local_executor: Option<Arc<dyn LocalExecutor>>,
+    /// If set, decides the backoff between attempts instead of the retry policy's interval and
+    /// coefficient. It is only asked once the retry policy would retry at all, so maximum attempts
+    /// and non-retryable errors are still honored.
+    pub backoff_fn: Option<BackoffFn>,
+    /// What to do when asked to complete an activity using a task token that was never issued
+    pub unknown_token_policy: UnknownTokenPolicy,
+    /// Completions using the token of an attempt which has since been retried are always rejected
+    /// as stale while the activity is tracked. If this is true, they keep
+    /// being rejected after it resolves, for as long as any activity of its run is still tracked,
+    /// so a stale attempt finishing late can't be mistaken for anything else.
+    pub strict_attempt_order: bool,
+    /// If true, the arguments of queued activities and the results they complete with are logged
+    /// at trace level, with every payload rendered by `payload_redactor`.
+    pub log_payloads: bool,
+    /// Renders payloads when `log_payloads` is on. Defaults to showing only their size and
+    /// metadata keys.
+    pub payload_redactor: Option<PayloadRedactor>,
+    /// If true, no timeout timers are run, and timeouts are never decided by the clock. Instead
+    /// they only happen when fed in with [crate::Worker::force_local_activity_timeout]. Meant for
+    /// replaying workflows, where the outcome of every timeout is already known from history.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub replay_mode: bool,
+    /// If set, at most this many new activities may sit in the dispatch queue waiting to be
+    /// handed to lang. Enqueueing past the limit evicts the oldest of them, resolving it with a
+    /// schedule-to-start timeout, rather than letting the queue grow without bound.
+    pub max_queue_depth: Option<usize>,
+    /// If set, limits how quickly activities are dispatched across the whole manager. Activities
+    /// over the limit wait like a backoff, without holding a slot, until they may go.
+    pub dispatch_rate_limit: Option<DispatchRateLimit>,
+    /// If set, each local retry waits a random extra amount after its backoff before being sent
+    /// for dispatch, so retries whose backoffs end together don't all arrive at once.
+    pub retry_dispatch_jitter: Option<RetryDispatchJitter>,
+    /// If set, activity types which keep failing stop being dispatched for a while, and instead
+    /// fail immediately, to give whatever they depend on a chance to recover.
+    pub circuit_breaker: Option<CircuitBreakerOptions>,
+    /// If set, used as the details of cancellations core produces by itself which would otherwise
+    /// carry none (ex: cancelling an activity before it was dispatched). Cancels during a backoff
+    /// keep their own details, so they stay distinguishable.
+    pub default_cancel_details: Option<Payload>,
+    /// If set, dispatches are randomly delayed or made to fail. Never use this outside of testing.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub chaos: Option<ChaosConfig>,
+}
+
+impl LocalActivityManagerOptions {
+    /// See [Self::replay_mode]. Always false unless test utilities are enabled.
+    fn replaying(&self) -> bool {
+        #[cfg(any(feature = "test-utilities", test))]
+        return self.replay_mode;
+        #[cfg(not(any(feature = "test-utilities", test)))]
+        false
+    }
+
+    /// Checks that the options can be used to run local activities
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if let Some(limit) = self.dispatch_rate_limit.as_ref() {
+            limit.validate()?;
+        }
+        if let Some((act_type, _)) = self.max_concurrent_per_type.iter().find(|(_, l)| **l == 0) {
+            return Err(format!(
+                "Local activity concurrency limit for type '{act_type}' must be > 0"
+            ));
+        }
+        if self
+            .circuit_breaker
+            .is_some_and(|cb| cb.failure_threshold == 0)
+        {
+            return Err("Local activity circuit breaker failure threshold must be > 0".to_string());
+        }
+        #[cfg(any(feature = "test-utilities", test))]
+        if let Some(chaos) = self.chaos.as_ref() {
+            let probabilities = [
+                chaos.delay_probability,
+                chaos.failure_probability,
+                chaos.timeout_probability,
+            ];
+            if probabilities.iter().any(|p| !(0.0..=1.0).contains(p)) {
+                return Err(
+                    "Local activity chaos probabilities must be between 0 and 1".to_string()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Makes some local activity dispatches misbehave on purpose, to check that workflows cope with
+/// unreliable activities. Each dispatch is first considered for a delay, then (once it is actually
+/// dispatched) for failure, then for timing out. Probabilities must be between 0 and 1.
+#[cfg(any(feature = "test-utilities", test))]
+#[derive(Debug, Clone, Default)]
+pub struct ChaosConfig {
+    /// Chance that a dispatch is held back before reaching lang
+    pub delay_probability: f64,
+    /// Delays are chosen uniformly between zero and this
+    pub max_delay: Duration,
+    /// Chance that an attempt fails (retryably) instead of reaching lang
+    pub failure_probability: f64,
+    /// Chance that an attempt times out (start-to-close) instead of reaching lang
+    pub timeout_probability: f64,
+    /// Seeds the random choices, so a misbehaving run can be reproduced
+    pub seed: u64,
+}
+
+/// A token bucket limit on how fast local activities are dispatched
+#[derive(Debug, Clone, Copy)]
+pub struct DispatchRateLimit {
+    /// Sustained dispatches per second
+    pub per_second: f64,
+    /// How many dispatches may happen back to back after a quiet period
+    pub burst: u32,
+}
+
+impl DispatchRateLimit {
+    /// Validates the limit
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if !self.per_second.is_finite() || self.per_second <= 0.0 {
+            return Err(format!(
+                "Local activity dispatch rate limit must be positive and finite, got {}",
+                self.per_second
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Configures the per activity type circuit breaker
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreakerOptions {
+    /// How many attempts of a type must fail in a row for its circuit to open
+    pub failure_threshold: u32,
+    /// Failures only count as being in a row if they all happen within this long of the first
+    pub window: Duration,
+    /// How long an open circuit fails dispatches before letting them through again
+    pub cooldown: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CircuitBreaker {
+    Closed {
+        consecutive_failures: u32,
+        first_failure_at: tokio::time::Instant,
+    },
+    Open {
+        until: tokio::time::Instant,
+    },
+    /// The cooldown has passed and dispatches go through again. The next attempt to finish decides
+    /// whether the circuit closes or opens right back up.
+    HalfOpen,
+}
+
+impl CircuitBreaker {
+    /// Returns true if dispatches of the type must be short-circuited
+    fn is_open(&mut self) -> bool {
+        if let Self::Open { until } = *self {
+            if tokio::time::Instant::now() < until {
+                return true;
+            }
+            *self = Self::HalfOpen;
+        }
+        false
+    }
+
+    fn record_failure(&mut self, opts: &CircuitBreakerOptions) {
+        let now = tokio::time::Instant::now();
+        let open = Self::Open {
+            until: now + opts.cooldown,
+        };
+        *self = match *self {
+            Self::Closed {
+                consecutive_failures,
+                first_failure_at,
+            } => {
+                let (consecutive_failures, first_failure_at) =
+                    if consecutive_failures > 0 && now - first_failure_at <= opts.window {
+                        (consecutive_failures + 1, first_failure_at)
+                    } else {
+                        (1, now)
+                    };
+                if consecutive_failures >= opts.failure_threshold {
+                    open
+                } else {
+                    Self::Closed {
+                        consecutive_failures,
+                        first_failure_at,
+                    }
+                }
+            }
+            Self::HalfOpen => open,
+            // Attempts dispatched before the circuit opened may still fail while it is open, which
+            // shouldn't extend the cooldown.
+            o @ Self::Open { .. } => o,
+        };
+    }
+
+    fn record_success(&mut self) {
+        if !matches!(self, Self::Open { .. }) {
+            *self = Self::default();
+        }
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::Closed {
+            consecutive_failures: 0,
+            first_failure_at: tokio::time::Instant::now(),
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: tokio::time::Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &DispatchRateLimit) -> Self {
+        Self {
+            tokens: limit.burst.max(1) as f64,
+            refilled_at: tokio::time::Instant::now(),
+        }
+    }
+
+    /// Returns how long until there will be a token, or None if there is one now. It isn't taken
+    /// until the activity is dispatched, so there's nothing to give back if it never is.
+    fn wait_for_token(&mut self, limit: &DispatchRateLimit) -> Option<Duration> {
+        let now = tokio::time::Instant::now();
+        let refill = now.duration_since(self.refilled_at).as_secs_f64() * limit.per_second;
+        self.tokens = (self.tokens + refill).min(limit.burst.max(1) as f64);
+        self.refilled_at = now;
+        (self.tokens < 1.0).then(|| Duration::from_secs_f64((1.0 - self.tokens) / limit.per_second))
+    }
+
+    /// Two dispatches may have been let through for the same token, in which case this goes into
+    /// debt, which later waits pay off
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+#[cfg(any(feature = "test-utilities", test))]
+struct ChaosState {
+    rng: StdRng,
+    /// Activities whose current dispatch was already delayed, so delays don't compound
+    delayed: HashSet<ExecutingLAId>,
+}
+
+/// How local activity completions are treated when their task token was never issued. Tokens
+/// which were issued, but whose activity is no longer tracked (ex: it timed out), are not affected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)] // Reads better at the configuration site
+pub enum UnknownTokenPolicy {
+    /// Drop the completion without saying anything
+    IgnoreUnknown,
+    /// Like `IgnoreUnknown`, but log a warning
+    #[default]
+    LogUnknown,
+    /// Shut the worker down, since lang inventing tokens points at a bug serious enough to stop
+    /// over
+    ErrorUnknown,
+}
+
+/// Lets failures be ruled out of retrying based on their shape, before the activity's retry
+/// policy (which only understands application failures) gets a say.
+pub trait FailureClassifier: Send + Sync {
+    /// Decides whether a failed attempt may be retried at all
+    fn classify(&self, failure: &APIFailure) -> FailureClassification;
+}
+
+/// The verdict of a [FailureClassifier]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureClassification {
+    /// Leave the decision to the retry policy
+    DeferToRetryPolicy,
+    /// Never retry, regardless of the retry policy
+    NonRetryable,
+}
+
+/// Carries tracing context (ex: an OpenTelemetry span context) across the boundary between core
+/// and lang, so traces continue through local activities without lang having to do anything
+pub trait ContextPropagator: Send + Sync {
+    /// Adds the current context to the headers of an attempt which is about to be dispatched
+    fn inject(&self, _id: &ExecutingLAId, _headers: &mut HashMap<String, Payload>) {}
+    /// Continues the context carried by the result of a completed attempt, if any
+    fn extract(&self, _id: &ExecutingLAId, _result: &LocalActivityExecutionResult) {}
+}
+
+/// Propagates nothing
+pub struct NoopContextPropagator;
+
+impl ContextPropagator for NoopContextPropagator {}
+
+/// Defers every failure to the retry policy, meaning anything which isn't an application failure
+/// marked non-retryable (or with a non-retryable error type) is retried.
+pub struct DefaultFailureClassifier;
+
+impl FailureClassifier for DefaultFailureClassifier {
+    fn classify(&self, _: &APIFailure) -> FailureClassification {
+        FailureClassification::DeferToRetryPolicy
+    }
+}
+
+/// Runs some local activities somewhere other than lang, ex: in a subprocess. Consulted just before
+/// each attempt would be dispatched.
+pub trait LocalExecutor: Send + Sync {
+    /// Returns a final result to claim the activity, in which case it is reported as-is (with no
+    /// retries) instead of being dispatched. Returning `None` leaves it to lang. Called while the
+    /// manager's lock is held, so anything long-running must be handed off rather than waited on
+    /// here.
+    fn try_execute(
+        &self,
+        id: &ExecutingLAId,
+        activity_type: &str,
+        arguments: &[Payload],
+        attempt: u32,
+    ) -> Option<LocalActivityExecutionResult>;
 }
 
 pub(crate) struct LocalActivityManager {
     /// Just so we can provide activity tasks the same namespace as the worker
     namespace: String,
+    opts: LocalActivityManagerOptions,
     /// Sink for new activity execution requests
-    act_req_tx: UnboundedSender<NewOrRetry>,
+    req_tx: RequestSender,
+    /// Used directly only for reporting on slots. Permits are obtained as part of receiving new
+    /// requests.
+    permit_dealer: MeteredPermitDealer<LocalActivitySlotKind>,
     /// Cancels need a different queue since they should be taken first, and don't take a permit
     cancels_req_tx: UnboundedSender<CancelOrTimeout>,
+    /// Cancels taken off the cancel queue by [LocalActivityManager::flush_cancels] when shutdown
+    /// starts draining. Handed to lang ahead of everything else.
+    flushed_cancels_tx: UnboundedSender<ActivityTask>,
+    /// Tokens of attempts with a cancel sitting in the cancel queue, which can't be inspected. See
+    /// [LocalActivityManager::pending_cancels].
+    queued_cancels: Mutex<HashSet<TaskToken>>,
     /// For the emission of heartbeat timeouts, back into the workflow machines. This channel
     /// needs to come in from above us, because we cannot rely on callers getting the next
     /// activation as a way to deliver heartbeats.
     heartbeat_timeout_tx: UnboundedSender<HeartbeatTimeoutMsg>,
     /// Wakes every time a complete is processed
     complete_notify: Notify,
+    /// Publishes the number of outstanding activities whenever a complete is processed, and when
+    /// shutdown starts draining. See [LocalActivityManager::shutdown_progress].
+    outstanding_count: watch::Sender<usize>,
+    /// See [LocalActivityManager::subscribe_events]
+    events: broadcast::Sender<LaEvent>,
     /// Set once workflows have finished shutting down, and thus we know we will no longer receive
     /// any requests to spawn new LAs
     workflows_have_shut_down: CancellationToken,
+    /// Set once shutdown has started waiting for outstanding tasks to drain. After this point no
+    /// new local backoffs may be started, since their retries could never be dispatched, and new
+    /// activities are rejected for the same reason.
+    drain_started: CancellationToken,
 
     rcvs: tokio::sync::Mutex<RcvChans>,
+    /// Shared with [RcvChans] so pending cancels can be checked for without waiting on `rcvs`,
+    /// which is held for as long as someone is waiting on the next pending action.
+    cancels_req_rx: Arc<Mutex<UnboundedReceiver<CancelOrTimeout>>>,
     shutdown_complete_tok: CancellationToken,
+    /// Number of requests which have been taken from the queue but are waiting for a permit
+    #[cfg(any(feature = "test-utilities", test))]
+    permit_waiters: Arc<AtomicUsize>,
     dat: Mutex<LAMData>,
     /// Note that these metrics do *not* include the `worker_type` label, as every metric
     /// emitted here is already specific to local activities via the metric name.
     metrics: MetricsContext,
+    /// Children of `metrics` for each activity type seen, see
+    /// [LocalActivityManager::metrics_for_activity_type]
+    activity_type_metrics: Mutex<HashMap<String, MetricsContext>>,
+    lifetime_counters: LifetimeCounters,
+    /// Present when [LocalActivityManagerOptions::state_store] is set. Feeds the task which
+    /// applies changes to the store, so that its IO happens outside of `dat`'s lock.
+    state_store_tx: Option<UnboundedSender<LaStoreOp>>,
+    spawner: TaskSpawner,
+}
+
+/// Spawns the manager's internal tasks (backoffs, timeouts, etc) either onto a dedicated runtime,
+/// or onto whichever runtime is ambient if none was provided.
+#[derive(Clone, Default)]
+struct TaskSpawner(Option<Handle>);
+
+impl TaskSpawner {
+    fn spawn<F>(&self, fut: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        match self.0.as_ref() {
+            Some(handle) => handle.spawn(fut),
+            None => tokio::spawn(fut),
+        }
+    }
+}
+
+#[derive(Default)]
+struct LifetimeCounters {
+    dispatched: AtomicU64,
+    completed: AtomicU64,
+    failed_terminal: AtomicU64,
+    retried: AtomicU64,
+}
+
+/// Cumulative counts of local activity outcomes over the lifetime of a worker. See
+/// `Worker::local_activity_lifetime_stats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LaLifetimeStats {
+    /// Attempts handed out to lang, including retries
+    pub dispatched: u64,
+    /// Attempts which completed successfully
+    pub completed: u64,
+    /// Attempts which failed or timed out and were reported without being retried
+    pub failed_terminal: u64,
+    /// Attempts which failed or timed out and will be retried, whether locally or via a timer
+    pub retried: u64,
+}
+
+/// Aggregated local activity metrics for a single workflow run. See
+/// `Worker::local_activity_run_metrics`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RunLaMetrics {
+    /// Attempts currently executing
+    pub outstanding: usize,
+    /// Attempts handed out to lang, including retries
+    pub dispatched: u64,
+    /// Attempts which failed or timed out and will be retried, whether locally or via a timer
+    pub retried: u64,
+    /// Time spent waiting in local backoffs before retries were dispatched
+    pub backoff_time: Duration,
 }
 
 struct LocalActivityInfo {
     task_token: TaskToken,
-    /// Tasks for the current backoff until the next retry, if any.
-    backing_off_task: Option<JoinHandle<()>>,
+    /// The current backoff until the next retry, if any.
+    backing_off_task: Option<LocalBackoff>,
     /// Tasks / info about timeouts associated with this LA. May be empty for very brief periods
     /// while the LA id has been generated, but it has not yet been scheduled.
     timeout_bag: Option<TimeoutBag>,
     /// True once the first workflow task this LA started in has elapsed
     first_wft_has_ended: bool,
+    /// Set when the activity must not be retried again, regardless of its retry policy
+    retries_disabled: bool,
+    /// Set when only the current attempt was cancelled, by [LocalActivityManager::abort_attempt]
+    attempt_aborted: bool,
+    /// Set once lang has been told to cancel the current attempt
+    cancel_requested: bool,
     /// Attempts at executing this LA during the current WFT
     attempts_in_wft: usize,
+    /// Number of times this LA has been retried via a local backoff (as opposed to a timer)
+    local_retries: u32,
+    correlation_id: Option<String>,
+    /// See [ValidScheduleLA::report_timeouts]
+    report_timeouts: bool,
+}
+
+struct LocalBackoff {
+    handle: JoinHandle<()>,
+    /// The attempt which failed and caused this backoff, or the upcoming attempt if this is an
+    /// initial start delay
+    failed_attempt: u32,
+    /// When the activity will be sent for dispatch again
+    retry_at: Instant,
+    /// When the activity started waiting. Tokio's, so paused time in tests applies to it.
+    started_at: tokio::time::Instant,
 }
 
 struct LAMData {
@@ -197,13 +1031,96 @@ struct LAMData {
     la_info: HashMap<ExecutingLAId, LocalActivityInfo>,
     /// Activities that have been issued to lang but not yet completed
     outstanding_activity_tasks: HashMap<TaskToken, LocalInFlightActInfo>,
+    /// Number of outstanding activities for each type with a concurrency limit
+    executing_per_limited_type: HashMap<String, usize>,
+    /// Requests which arrived while their type was at its concurrency limit
+    parked_per_limited_type: HashMap<String, VecDeque<NewOrRetry>>,
+    /// Attempts which finished after shutdown started waiting on outstanding ones. See
+    /// [ShutdownSummary::completed_during_shutdown].
+    finished_while_draining: usize,
+    /// Resolutions of the backoffs aborted when shutdown started draining. See
+    /// [ShutdownSummary::aborted_backoffs].
+    aborted_backoffs: Vec<LocalActivityResolution>,
+    /// Tokens of attempts which failed and were replaced by a retry's new token, along with the
+    /// attempt they were for, so that late completions using them can be told apart from
+    /// completions of unknown activities
+    superseded_tokens: HashMap<TaskToken, (ExecutingLAId, u32)>,
+    /// Runs whose activities must execute one at a time, in seq order. See
+    /// [LocalActivityManager::serialize_run].
+    serialized_runs: HashMap<String, SerializedRun>,
+    /// Callers waiting for the final resolution of specific activities
+    resolution_waiters: HashMap<ExecutingLAId, Vec<oneshot::Sender<LocalActivityResolution>>>,
+    /// Per-run counters, kept until all of the run's activities have resolved. The outstanding
+    /// count is computed on demand rather than kept here.
+    run_metrics: HashMap<String, RunLaMetrics>,
+    /// Activities which were tracked before a restart and haven't been scheduled again since, as
+    /// restored from the state store
+    recovered: HashMap<ExecutingLAId, PersistedLa>,
+    /// Cancellation tokens scoped to runs, along with the task which watches each one. See
+    /// [LocalActivityManager::run_cancellation_token].
+    run_tokens: HashMap<String, (CancellationToken, JoinHandle<()>)>,
+    /// New activities sent to the dispatch queue and not yet received from it, least recently
+    /// queued first. The queue itself is a channel, so its order has to be tracked separately for
+    /// eviction. Only tracked when [LocalActivityManagerOptions::max_queue_depth] is set.
+    queued_new: LruCache<ExecutingLAId, QueuedNew>,
+    /// Present when [LocalActivityManagerOptions::dispatch_rate_limit] is set
+    rate_limiter: Option<TokenBucket>,
+    /// Circuit breaker state for each activity type which has finished an attempt, when
+    /// [LocalActivityManagerOptions::circuit_breaker] is set
+    circuit_breakers: HashMap<String, CircuitBreaker>,
+    /// Present when [LocalActivityManagerOptions::retry_dispatch_jitter] is set
+    retry_jitter_rng: Option<StdRng>,
+    #[cfg(any(feature = "test-utilities", test))]
+    chaos: Option<ChaosState>,
     next_tt_num: u32,
 }
 
+/// An entry in [LAMData::queued_new], with what's needed to resolve the activity if it's evicted
+struct QueuedNew {
+    attempt: u32,
+    original_schedule_time: Option<SystemTime>,
+}
+
 impl LAMData {
     fn gen_next_token(&mut self) -> TaskToken {
-        self.next_tt_num += 1;
-        TaskToken::new_local_activity_token(self.next_tt_num.to_le_bytes())
+        Self::issue_token(&mut self.next_tt_num)
+    }
+
+    /// Split out of [Self::gen_next_token] so a token can be issued while other fields are borrowed
+    fn issue_token(next_tt_num: &mut u32) -> TaskToken {
+        *next_tt_num += 1;
+        TaskToken::new_local_activity_token(next_tt_num.to_le_bytes())
+    }
+
+    /// Returns true if the token could have come from [Self::gen_next_token]
+    fn was_issued(&self, task_token: &TaskToken) -> bool {
+        let Some(num) = task_token.0.last_chunk().map(|b| u32::from_le_bytes(*b)) else {
+            return false;
+        };
+        (1..=self.next_tt_num).contains(&num)
+            && *task_token == TaskToken::new_local_activity_token(num.to_le_bytes())
+    }
+
+    fn run_metrics_mut(&mut self, run_id: &str) -> &mut RunLaMetrics {
+        self.run_metrics.entry(run_id.to_string()).or_default()
+    }
+
+    /// Hands a final resolution to anyone waiting on the activity it belongs to
+    fn notify_resolution_waiters(
+        &mut self,
+        id: &ExecutingLAId,
+        resolution: &LocalActivityResolution,
+    ) {
+        // Lang schedules the next attempt once the backoff timer fires, and the waiters keep
+        // waiting for that one
+        if resolution.backoff.is_some() {
+            return;
+        }
+        if let Some(waiters) = self.resolution_waiters.remove(id) {
+            for waiter in waiters {
+                let _ = waiter.send(resolution.clone());
+            }
+        }
     }
 }
 
@@ -213,51 +1130,155 @@ impl LocalActivityManager {
         permit_dealer: MeteredPermitDealer<LocalActivitySlotKind>,
         heartbeat_timeout_tx: UnboundedSender<HeartbeatTimeoutMsg>,
         metrics_context: MetricsContext,
-    ) -> Self {
+        opts: LocalActivityManagerOptions,
+    ) -> Result<Self, String> {
+        opts.validate()?;
+        let spawner = TaskSpawner(opts.runtime.clone());
         let (act_req_tx, act_req_rx) = unbounded_channel();
         let (cancels_req_tx, cancels_req_rx) = unbounded_channel();
+        let cancels_req_rx = Arc::new(Mutex::new(cancels_req_rx));
+        let (flushed_cancels_tx, flushed_cancels_rx) = unbounded_channel();
         let shutdown_complete_tok = CancellationToken::new();
-        Self {
+        let permit_waiters = Arc::new(AtomicUsize::new(0));
+        let recovered = opts
+            .state_store
+            .as_ref()
+            .map(|store| store.load())
+            .unwrap_or_default();
+        let state_store_tx = opts.state_store.clone().map(|store| {
+            let (tx, mut rx) = unbounded_channel();
+            spawner.spawn(async move {
+                while let Some(op) = rx.recv().await {
+                    match op {
+                        LaStoreOp::Record(id, la, next_token_num) => {
+                            store.record(&id, la, next_token_num)
+                        }
+                        LaStoreOp::Remove(id) => store.remove(&id),
+                        LaStoreOp::Flush(done) => {
+                            let _ = done.send(());
+                        }
+                    }
+                }
+            });
+            tx
+        });
+        let rate_limiter = opts.dispatch_rate_limit.as_ref().map(TokenBucket::new);
+        let retry_jitter_rng = opts.retry_dispatch_jitter.as_ref().map(|j| {
+            j.seed
+                .map_or_else(StdRng::from_os_rng, StdRng::seed_from_u64)
+        });
+        #[cfg(any(feature = "test-utilities", test))]
+        let chaos = opts.chaos.as_ref().map(|c| ChaosState {
+            rng: StdRng::seed_from_u64(c.seed),
+            delayed: Default::default(),
+        });
+        Ok(Self {
             namespace,
+            opts,
             rcvs: tokio::sync::Mutex::new(RcvChans::new(
                 act_req_rx,
-                permit_dealer,
-                cancels_req_rx,
+                permit_dealer.clone(),
+                cancels_req_rx.clone(),
+                flushed_cancels_rx,
                 shutdown_complete_tok.clone(),
+                metrics_context.clone(),
+                permit_waiters.clone(),
             )),
-            act_req_tx,
+            #[cfg(any(feature = "test-utilities", test))]
+            permit_waiters,
+            req_tx: RequestSender {
+                chan: act_req_tx,
+                queued: Default::default(),
+            },
+            permit_dealer,
             cancels_req_tx,
+            flushed_cancels_tx,
+            queued_cancels: Default::default(),
+            cancels_req_rx,
             heartbeat_timeout_tx,
             complete_notify: Notify::new(),
+            outstanding_count: watch::Sender::new(0),
+            events: broadcast::Sender::new(LA_EVENT_CAPACITY),
             shutdown_complete_tok,
             dat: Mutex::new(LAMData {
                 outstanding_activity_tasks: Default::default(),
+                executing_per_limited_type: Default::default(),
+                parked_per_limited_type: Default::default(),
+                finished_while_draining: 0,
+                aborted_backoffs: vec![],
+                superseded_tokens: Default::default(),
+                serialized_runs: Default::default(),
+                resolution_waiters: Default::default(),
+                run_metrics: Default::default(),
+                recovered: recovered.activities,
+                run_tokens: Default::default(),
+                queued_new: LruCache::unbounded(),
+                rate_limiter,
+                circuit_breakers: Default::default(),
+                retry_jitter_rng,
+                #[cfg(any(feature = "test-utilities", test))]
+                chaos,
                 la_info: Default::default(),
-                next_tt_num: 0,
+                next_tt_num: recovered.next_token_num,
             }),
             workflows_have_shut_down: Default::default(),
+            drain_started: Default::default(),
             metrics: metrics_context,
-        }
+            activity_type_metrics: Default::default(),
+            lifetime_counters: Default::default(),
+            state_store_tx,
+            spawner,
+        })
     }
 
     #[cfg(test)]
     fn test(max_concurrent: usize) -> Self {
-        use crate::worker::tuner::FixedSizeSlotSupplier;
-        use std::sync::Arc;
+        Self::test_with_opts(max_concurrent, Default::default())
+    }
+
+    #[cfg(test)]
+    fn test_with_opts(max_concurrent: usize, opts: LocalActivityManagerOptions) -> Self {
+        Self::test_with_metrics(max_concurrent, opts, MetricsContext::no_op())
+    }
 
-        let ss = Arc::new(FixedSizeSlotSupplier::new(max_concurrent));
+    #[cfg(test)]
+    fn test_with_metrics(
+        max_concurrent: usize,
+        opts: LocalActivityManagerOptions,
+        metrics: MetricsContext,
+    ) -> Self {
         let (hb_tx, _hb_rx) = unbounded_channel();
         Self::new(
             "fake_ns".to_string(),
-            MeteredPermitDealer::new(
-                ss,
-                MetricsContext::no_op(),
-                None,
-                Arc::new(Default::default()),
-                None,
-            ),
+            Self::test_permit_dealer(max_concurrent),
             hb_tx,
+            metrics,
+            opts,
+        )
+        .expect("Test local activity manager options must be valid")
+    }
+
+    #[cfg(test)]
+    fn test_on_runtime(max_concurrent: usize, runtime: Handle) -> Self {
+        Self::test_with_opts(
+            max_concurrent,
+            LocalActivityManagerOptions {
+                runtime: Some(runtime),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[cfg(test)]
+    fn test_permit_dealer(max_concurrent: usize) -> MeteredPermitDealer<LocalActivitySlotKind> {
+        use crate::worker::tuner::FixedSizeSlotSupplier;
+
+        MeteredPermitDealer::new(
+            Arc::new(FixedSizeSlotSupplier::new(max_concurrent)),
             MetricsContext::no_op(),
+            None,
+            Arc::new(Default::default()),
+            None,
         )
     }
 
@@ -266,6 +1287,156 @@ impl LocalActivityManager {
         self.dat.lock().outstanding_activity_tasks.len()
     }
 
+    pub(crate) fn lifetime_stats(&self) -> LaLifetimeStats {
+        let c = &self.lifetime_counters;
+        LaLifetimeStats {
+            dispatched: c.dispatched.load(Ordering::Relaxed),
+            completed: c.completed.load(Ordering::Relaxed),
+            failed_terminal: c.failed_terminal.load(Ordering::Relaxed),
+            retried: c.retried.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns aggregated local activity metrics for one workflow run, for debugging specific
+    /// executions. Counters are discarded once the run completes or is evicted, after which this
+    /// returns all zeroes.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn run_metrics(&self, run_id: &str) -> RunLaMetrics {
+        let dlock = self.dat.lock();
+        RunLaMetrics {
+            outstanding: dlock
+                .outstanding_activity_tasks
+                .values()
+                .filter(|i| i.la_info.workflow_exec_info.run_id == run_id)
+                .count(),
+            ..dlock.run_metrics.get(run_id).copied().unwrap_or_default()
+        }
+    }
+
+    /// Returns every activity currently waiting in a local backoff (or initial start delay), along
+    /// with how long until it will be dispatched again.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn backing_off(&self) -> Vec<(ExecutingLAId, Duration)> {
+        self.dat
+            .lock()
+            .la_info
+            .iter()
+            .filter_map(|(id, lai)| {
+                lai.backing_off_task.as_ref().map(|b| {
+                    (
+                        id.clone(),
+                        b.retry_at.saturating_duration_since(Instant::now()),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Returns how many activities of each type are currently dispatched to lang
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn in_flight_by_type(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for info in self.dat.lock().outstanding_activity_tasks.values() {
+            *counts
+                .entry(info.la_info.schedule_cmd.activity_type.clone())
+                .or_default() += 1;
+        }
+        counts
+    }
+
+    /// Returns the activity which has been dispatched to lang the longest, along with how long ago
+    /// that was
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn longest_running(&self) -> Option<(ExecutingLAId, Duration)> {
+        self.dat
+            .lock()
+            .outstanding_activity_tasks
+            .values()
+            .min_by_key(|info| info.dispatch_time)
+            .map(|info| {
+                (
+                    ExecutingLAId {
+                        run_id: info.la_info.workflow_exec_info.run_id.clone(),
+                        seq_num: info.la_info.schedule_cmd.seq,
+                    },
+                    info.dispatch_time.elapsed(),
+                )
+            })
+    }
+
+    /// Cancels the [longest running](Self::longest_running) activity if it has been running for
+    /// more than `threshold`, returning its id if so. Meant to be called periodically by a watchdog
+    /// looking for stuck activities. The cancel goes to lang like any other. If the attempt
+    /// finished and went into a backoff in the meantime, the cancel resolves the activity right
+    /// away, and that resolution is returned too so it can be handed to the workflow.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn cancel_longest_running_over(
+        &self,
+        threshold: Duration,
+    ) -> Option<(ExecutingLAId, Option<LocalActivityResolution>)> {
+        let (id, running_for) = self.longest_running()?;
+        if running_for <= threshold {
+            return None;
+        }
+        warn!(?id, ?running_for, "Cancelling long running local activity");
+        let immediate = self.cancel_by_id(id.clone());
+        Some((id, immediate))
+    }
+
+    /// Captures everything the manager is tracking, for attaching to bug reports
+    pub(crate) fn diagnostic_snapshot(&self) -> LaDiagnostics {
+        let dat = self.dat.lock();
+        let id_order = |id: &ExecutingLAId| (id.run_id.clone(), id.seq_num);
+        let mut outstanding: Vec<_> = dat
+            .outstanding_activity_tasks
+            .values()
+            .map(|info| OutstandingLaDiagnostics {
+                id: ExecutingLAId {
+                    run_id: info.la_info.workflow_exec_info.run_id.clone(),
+                    seq_num: info.la_info.schedule_cmd.seq,
+                },
+                activity_type: info.la_info.schedule_cmd.activity_type.clone(),
+                attempt: info.attempt,
+                elapsed: info.dispatch_time.elapsed(),
+            })
+            .collect();
+        outstanding.sort_by_key(|o| id_order(&o.id));
+        let mut backing_off: Vec<_> = dat
+            .la_info
+            .iter()
+            .filter_map(|(id, lai)| {
+                let b = lai.backing_off_task.as_ref()?;
+                Some(BackingOffLaDiagnostics {
+                    id: id.clone(),
+                    failed_attempt: b.failed_attempt,
+                    remaining: b.retry_at.saturating_duration_since(Instant::now()),
+                })
+            })
+            .collect();
+        backing_off.sort_by_key(|b| id_order(&b.id));
+        let mut timeouts: Vec<_> = dat
+            .la_info
+            .iter()
+            .filter_map(|(id, lai)| {
+                let info = lai.timeout_bag.as_ref()?.info();
+                (info.schedule_to_close.is_some() || info.start_to_close.is_some()).then(|| {
+                    LaTimeoutDiagnostics {
+                        id: id.clone(),
+                        timeouts: info,
+                    }
+                })
+            })
+            .collect();
+        timeouts.sort_by_key(|t| id_order(&t.id));
+        LaDiagnostics {
+            outstanding,
+            backing_off,
+            timeouts,
+            queue_depth: self.req_tx.queued(),
+            available_slots: self.permit_dealer.available_permits(),
+        }
+    }
+
     #[cfg(test)]
     fn num_in_backoff(&self) -> usize {
         self.dat
@@ -276,36 +1447,167 @@ impl LocalActivityManager {
             .count()
     }
 
-    pub(crate) fn enqueue(
-        &self,
-        reqs: impl IntoIterator<Item = LocalActRequest>,
-    ) -> Vec<LocalActivityResolution> {
+    pub(crate) fn enqueue(&self, reqs: impl IntoIterator<Item = LocalActRequest>) -> EnqueueResult {
         if self.workflows_have_shut_down.is_cancelled() {
             dbg_panic!("Tried to enqueue local activity after workflows were shut down");
-            return vec![];
+            return Default::default();
         }
+        let reqs: Vec<_> = reqs.into_iter().collect();
+        let cancelled_in_batch = self.cancelled_in_same_batch(&reqs);
         let mut immediate_resolutions = vec![];
+        let mut dropped = vec![];
+        let mut touched_serialized_runs = HashSet::new();
         for req in reqs {
             match req {
-                LocalActRequest::New(act) => {
-                    debug!(local_activity=?act, "Queuing local activity");
-                    let id = ExecutingLAId {
+                LocalActRequest::New(act)
+                    if cancelled_in_batch.contains(&ExecutingLAId {
+                        run_id: act.workflow_exec_info.run_id.clone(),
+                        seq_num: act.schedule_cmd.seq,
+                    }) =>
+                {
+                    debug!(local_activity=?act,
+                           "Local activity cancelled in the same batch it was scheduled in");
+                    immediate_resolutions.push(LocalActivityResolution::immediate(
+                        &act,
+                        self.empty_cancel(),
+                    ));
+                }
+                // Already resolved along with the new request above
+                LocalActRequest::Cancel(id) if cancelled_in_batch.contains(&id) => {}
+                LocalActRequest::New(act)
+                    if self.drain_started.is_cancelled()
+                        || self.shutdown_complete_tok.is_cancelled() =>
+                {
+                    // Shutdown only waits on what's already outstanding, so this could be left
+                    // waiting for a dispatch that never comes
+                    debug!(local_activity=?act, "Rejecting local activity during shutdown");
+                    immediate_resolutions.push(LocalActivityResolution::immediate(
+                        &act,
+                        LocalActivityExecutionResult::non_retryable_failure(
+                            "Local activity was not started because the worker is shutting down"
+                                .to_string(),
+                        ),
+                    ));
+                }
+                LocalActRequest::New(mut act) => {
+                    debug!(local_activity=?act, "Queuing local activity");
+                    if let Some(arguments) = self.loggable_payloads(&act.schedule_cmd.arguments) {
+                        trace!(activity_type=%act.schedule_cmd.activity_type,
+                               seq=act.schedule_cmd.seq, ?arguments, "Local activity arguments");
+                    }
+                    if act.schedule_cmd.close_timeouts.is_none() {
+                        act.schedule_cmd.close_timeouts = self
+                            .opts
+                            .default_close_timeouts_per_type
+                            .get(&act.schedule_cmd.activity_type)
+                            .copied();
+                    }
+                    let id = ExecutingLAId {
                         run_id: act.workflow_exec_info.run_id.clone(),
                         seq_num: act.schedule_cmd.seq,
                     };
                     let mut dlock = self.dat.lock();
-                    let tt = dlock.gen_next_token();
-                    match dlock.la_info.entry(id) {
+                    let dat = &mut *dlock;
+                    match dat.la_info.entry(id) {
                         Entry::Occupied(o) => {
                             // Do not queue local activities which are in fact already executing.
                             // This can happen during evictions.
-                            debug!(
-                                "Tried to queue already-executing local activity {:?}",
-                                o.key()
-                            );
+                            debug!(activity_type=%act.schedule_cmd.activity_type,
+                                   "Tried to queue already-executing local activity {:?}",
+                                   o.key());
+                            // A high rate of these often points to nondeterminism in a workflow
+                            guard_metrics(|| {
+                                self.metrics_for_activity_type(&act.schedule_cmd.activity_type)
+                                    .la_duplicate_enqueue()
+                            });
+                            let reason = if dat
+                                .outstanding_activity_tasks
+                                .contains_key(&o.get().task_token)
+                            {
+                                EnqueueDropReason::AlreadyExecuting
+                            } else {
+                                EnqueueDropReason::AlreadyQueued
+                            };
+                            dropped.push((o.key().clone(), reason));
                             continue;
                         }
                         Entry::Vacant(ve) => {
+                            if act.schedule_cmd.close_timeouts.is_none() {
+                                immediate_resolutions.push(LocalActivityResolution::immediate(
+                                    &act,
+                                    LocalActivityExecutionResult::non_retryable_failure(
+                                        "One or both of schedule_to_close or start_to_close \
+                                         timeouts must be set for local activities, and there is \
+                                         no default for this activity type"
+                                            .to_string(),
+                                    ),
+                                ));
+                                continue;
+                            }
+                            if let Some(err) = &act.schedule_cmd.retry_policy_error {
+                                immediate_resolutions.push(LocalActivityResolution::immediate(
+                                    &act,
+                                    LocalActivityExecutionResult::non_retryable_failure(format!(
+                                        "Local activity retry policy is invalid: {err}"
+                                    )),
+                                ));
+                                continue;
+                            }
+                            if !self.activity_type_allowed(&act.schedule_cmd.activity_type) {
+                                immediate_resolutions.push(LocalActivityResolution::immediate(
+                                    &act,
+                                    LocalActivityExecutionResult::non_retryable_failure(format!(
+                                        "Local activity type '{}' is not registered or not \
+                                         allowed on this worker",
+                                        act.schedule_cmd.activity_type
+                                    )),
+                                ));
+                                continue;
+                            }
+                            if let Some(max) = self.opts.max_input_size {
+                                let input_size: usize = act
+                                    .schedule_cmd
+                                    .arguments
+                                    .iter()
+                                    .map(|p| p.encoded_len())
+                                    .sum();
+                                if input_size > max {
+                                    immediate_resolutions.push(LocalActivityResolution::immediate(
+                                        &act,
+                                        LocalActivityExecutionResult::non_retryable_failure(
+                                            format!(
+                                                "Local activity input size of {input_size} bytes \
+                                                 exceeds the maximum of {max} bytes"
+                                            ),
+                                        ),
+                                    ));
+                                    continue;
+                                }
+                            }
+                            // Only issued once the activity is known to be new, so duplicates and
+                            // rejected schedules don't use up token numbers
+                            let tt = LAMData::issue_token(&mut dat.next_tt_num);
+                            let tt = match dat.recovered.remove(ve.key()) {
+                                Some(recovered) => match recovered.resume(&mut act, tt) {
+                                    Some(tt) => tt,
+                                    None => {
+                                        self.unpersist(ve.key());
+                                        immediate_resolutions.push(
+                                            LocalActivityResolution::immediate(
+                                                &act,
+                                                LocalActivityExecutionResult::non_retryable_failure(
+                                                    "Non-idempotent local activity was executing \
+                                                     when the worker stopped, so it won't be run \
+                                                     again"
+                                                        .to_string(),
+                                                ),
+                                            ),
+                                        );
+                                        continue;
+                                    }
+                                },
+                                None => tt,
+                            };
                             // Insert the task token now, before we may or may not dispatch the
                             // activity, so we can enforce idempotency. Prevents two identical LAs
                             // ending up in the queue at once.
@@ -314,19 +1616,86 @@ impl LocalActivityManager {
                                 backing_off_task: None,
                                 timeout_bag: None,
                                 first_wft_has_ended: false,
+                                retries_disabled: false,
+                                attempt_aborted: false,
+                                cancel_requested: false,
                                 attempts_in_wft: 0,
+                                local_retries: 0,
+                                correlation_id: act.correlation_id.clone(),
+                                report_timeouts: act.schedule_cmd.report_timeouts.unwrap_or(true),
                             });
 
                             // Set up timeouts for the new activity
-                            match TimeoutBag::new(&act, self.cancels_req_tx.clone()) {
-                                Ok(tb) => {
+                            match TimeoutBag::new(
+                                &act,
+                                act.schedule_cmd
+                                    .original_schedule_time
+                                    .unwrap_or(act.schedule_time),
+                                self.cancels_req_tx.clone().into(),
+                                self.opts
+                                    .clock_skew_tolerance
+                                    .unwrap_or(DEFAULT_CLOCK_SKEW_TOLERANCE),
+                                self.opts.replaying(),
+                                self.spawner.clone(),
+                            ) {
+                                Ok(mut tb) => {
+                                    if let Some(lead) = self.opts.deadline_warning_lead {
+                                        tb.warn_before_deadline(
+                                            lead,
+                                            ExecutingLAId {
+                                                run_id: act.workflow_exec_info.run_id.clone(),
+                                                seq_num: act.schedule_cmd.seq,
+                                            },
+                                        );
+                                    }
                                     lai.timeout_bag = Some(tb);
+                                    let id = ExecutingLAId {
+                                        run_id: act.workflow_exec_info.run_id.clone(),
+                                        seq_num: act.schedule_cmd.seq,
+                                    };
+                                    self.persist(
+                                        &id,
+                                        PersistedLa {
+                                            task_token: lai.task_token.clone(),
+                                            attempt: act.schedule_cmd.attempt.max(1),
+                                            phase: PersistedLaPhase::Queued,
+                                        },
+                                        dat.next_tt_num,
+                                    );
+                                    self.emit(LaEvent::Queued(id.clone()));
 
-                                    self.act_req_tx.send(NewOrRetry::New(act)).expect(
-                                        "Receive half of LA request channel cannot be dropped",
+                                    let run_id = &act.workflow_exec_info.run_id;
+                                    if let Some(sr) = dat.serialized_runs.get_mut(run_id) {
+                                        // Everything in the batch is held until the end, so that
+                                        // the lowest seq goes first no matter the batch's order
+                                        touched_serialized_runs.insert(run_id.clone());
+                                        sr.waiting.insert(act.schedule_cmd.seq, act);
+                                    } else {
+                                        self.submit_new(dat, &id, act);
+                                    }
+                                }
+                                Err(mut res) if act.schedule_cmd.report_timeouts == Some(false) => {
+                                    debug!(
+                                        seq = res.seq,
+                                        "Not reporting local activity timeout to workflow"
                                     );
+                                    res.result = self.unreported_timeout(res.result);
+                                    let id = ExecutingLAId {
+                                        run_id: act.workflow_exec_info.run_id.clone(),
+                                        seq_num: act.schedule_cmd.seq,
+                                    };
+                                    dat.la_info.remove(&id);
+                                    self.unpersist(&id);
+                                    immediate_resolutions.push(res);
+                                }
+                                Err(res) => {
+                                    // Whatever was recovered for it is stale now
+                                    self.unpersist(&ExecutingLAId {
+                                        run_id: act.workflow_exec_info.run_id.clone(),
+                                        seq_num: act.schedule_cmd.seq,
+                                    });
+                                    immediate_resolutions.push(res)
                                 }
-                                Err(res) => immediate_resolutions.push(res),
                             }
                         }
                     }
@@ -337,7 +1706,7 @@ impl LocalActivityManager {
                     abort_reg,
                 } => {
                     let chan = self.heartbeat_timeout_tx.clone();
-                    tokio::spawn(future::Abortable::new(
+                    self.spawner.spawn(future::Abortable::new(
                         async move {
                             tokio::time::sleep_until(deadline.into()).await;
                             let _ = chan.send(send_on_elapse);
@@ -346,28 +1715,18 @@ impl LocalActivityManager {
                     ));
                 }
                 LocalActRequest::Cancel(id) => {
-                    debug!(id=?id, "Cancelling local activity");
-                    let mut dlock = self.dat.lock();
-                    if let Some(lai) = dlock.la_info.get_mut(&id)
-                        && let Some(immediate_res) = self.cancel_one_la(id.seq_num, lai)
-                    {
-                        immediate_resolutions.push(immediate_res);
-                    }
+                    immediate_resolutions.extend(self.cancel_by_id(id));
                 }
                 LocalActRequest::CancelAllInRun(run_id) => {
                     debug!(run_id=%run_id, "Cancelling all local activities for run");
-                    let mut dlock = self.dat.lock();
-                    // Even if we've got 100k+ LAs this should only take a ms or two. Not worth
-                    // adding another map to keep in sync.
-                    let las_for_run = dlock
-                        .la_info
-                        .iter_mut()
-                        .filter(|(id, _)| id.run_id == run_id);
-                    for (laid, lainf) in las_for_run {
-                        if let Some(immediate_res) = self.cancel_one_la(laid.seq_num, lainf) {
-                            immediate_resolutions.push(immediate_res);
-                        }
-                    }
+                    immediate_resolutions
+                        .extend(self.cancel_run(&run_id, ActivityCancelReason::Cancelled));
+                }
+                LocalActRequest::CancelForWorkflowCompletion(run_id) => {
+                    debug!(run_id=%run_id,
+                           "Cancelling all local activities for run due to workflow completion");
+                    immediate_resolutions
+                        .extend(self.cancel_run(&run_id, ActivityCancelReason::WorkflowCompleted));
                 }
                 LocalActRequest::IndicateWorkflowTaskCompleted(run_id) => {
                     let mut dlock = self.dat.lock();
@@ -382,61 +1741,526 @@ impl LocalActivityManager {
                 }
             }
         }
-        immediate_resolutions
+        if !touched_serialized_runs.is_empty() {
+            let mut dlock = self.dat.lock();
+            for run_id in touched_serialized_runs {
+                self.advance_serialized_run(&mut dlock, &run_id);
+            }
+        }
+        immediate_resolutions.extend(self.evict_over_queue_depth());
+        EnqueueResult {
+            immediate_resolutions,
+            dropped,
+        }
+    }
+
+    /// Finds activities which are both newly scheduled and then immediately cancelled within one
+    /// batch of requests. Those are resolved as cancelled right away, rather than racing the
+    /// dispatch of the new request against its cancel.
+    fn cancelled_in_same_batch(&self, reqs: &[LocalActRequest]) -> HashSet<ExecutingLAId> {
+        let dlock = self.dat.lock();
+        let mut new_ids = HashSet::new();
+        let mut cancelled = HashSet::new();
+        for req in reqs {
+            match req {
+                // Requests for already-tracked activities are dropped as duplicates, so a cancel
+                // in the batch applies to the existing activity as usual
+                LocalActRequest::New(act) => {
+                    let id = ExecutingLAId {
+                        run_id: act.workflow_exec_info.run_id.clone(),
+                        seq_num: act.schedule_cmd.seq,
+                    };
+                    if !dlock.la_info.contains_key(&id) {
+                        new_ids.insert(id);
+                    }
+                }
+                LocalActRequest::Cancel(id) if new_ids.contains(id) => {
+                    cancelled.insert(id.clone());
+                }
+                _ => {}
+            }
+        }
+        cancelled
+    }
+
+    /// Sends a newly tracked activity on its way to the dispatch queue. If the queue is gone, which
+    /// only happens while the manager is being torn down, the activity stops being tracked since
+    /// nothing would ever dispatch it.
+    fn submit_new(&self, dat: &mut LAMData, id: &ExecutingLAId, act: NewLocalAct) {
+        let Some(lai) = dat.la_info.get_mut(id) else {
+            return;
+        };
+        if let Some(delay) = act.schedule_cmd.start_delay {
+            // Waits like a backoff does, so that no permit is held and cancels resolve
+            // immediately.
+            let attempt = act.schedule_cmd.attempt;
+            let req_tx = self.req_tx.clone();
+            let handle = self.spawner.spawn(async move {
+                sleep(delay).await;
+                req_tx.send(NewOrRetry::New(act));
+            });
+            lai.backing_off_task = Some(LocalBackoff {
+                handle,
+                failed_attempt: attempt,
+                retry_at: Instant::now() + delay,
+                started_at: tokio::time::Instant::now(),
+            });
+        } else {
+            if self.opts.max_queue_depth.is_some() {
+                dat.queued_new.put(
+                    id.clone(),
+                    QueuedNew {
+                        attempt: act.schedule_cmd.attempt,
+                        original_schedule_time: act.schedule_cmd.original_schedule_time,
+                    },
+                );
+            }
+            if !self.req_tx.try_send(NewOrRetry::New(act)) {
+                warn!(local_activity=?id,
+                      "Dropping local activity request, since the request queue is closed");
+                dat.queued_new.pop(id);
+                dat.la_info.remove(id);
+                dat.resolution_waiters.remove(id);
+                self.unpersist(id);
+            }
+        }
+    }
+
+    /// Evicts the oldest queued new activities while there are more than
+    /// [LocalActivityManagerOptions::max_queue_depth] of them, returning their resolutions. Their
+    /// requests are dropped once they come out of the queue, since they're no longer tracked.
+    fn evict_over_queue_depth(&self) -> Vec<LocalActivityResolution> {
+        let Some(max) = self.opts.max_queue_depth else {
+            return vec![];
+        };
+        let mut dlock = self.dat.lock();
+        let dat = &mut *dlock;
+        let mut evicted = vec![];
+        while dat.queued_new.len() > max {
+            let Some((id, q)) = dat.queued_new.pop_lru() else {
+                break;
+            };
+            let Some(lai) = dat.la_info.remove(&id) else {
+                continue;
+            };
+            if let Some(b) = lai.backing_off_task.as_ref() {
+                b.handle.abort();
+            }
+            warn!(id=?id, max_queue_depth=max,
+                  "Local activity queue is full, evicting the oldest queued activity");
+            let resolution = LocalActivityResolution {
+                seq: id.seq_num,
+                result: LocalActivityExecutionResult::timeout(TimeoutType::ScheduleToStart),
+                runtime: Duration::ZERO,
+                attempt: q.attempt,
+                backoff: None,
+                original_schedule_time: q.original_schedule_time,
+                correlation_id: lai.correlation_id,
+                effective_timeouts: lai
+                    .timeout_bag
+                    .as_ref()
+                    .map(TimeoutBag::effective)
+                    .unwrap_or_default(),
+            };
+            self.activity_resolved(dat, &id, &resolution);
+            evicted.push(resolution);
+        }
+        evicted
+    }
+
+    /// Returns a cancellation token scoped to the provided run, creating it if needed. Cancelling it
+    /// cancels every local activity of the run, as if [LocalActRequest::CancelAllInRun] had been
+    /// enqueued: executing attempts are sent cancels, and anything queued or backing off is
+    /// resolved as cancelled by [Self::next_pending]. The token is forgotten once the run has no
+    /// tracked activities left or is evicted, after which this hands out a new one.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn run_cancellation_token(&self, run_id: &str) -> CancellationToken {
+        let mut dlock = self.dat.lock();
+        if let Some((tok, _)) = dlock.run_tokens.get(run_id) {
+            return tok.clone();
+        }
+        let tok = CancellationToken::new();
+        let watched = tok.clone();
+        let chan = self.cancels_req_tx.clone();
+        let owned_run_id = run_id.to_string();
+        let watcher = self.spawner.spawn(async move {
+            watched.cancelled().await;
+            // Goes through the cancel channel so the work happens where resolutions can be
+            // handed back to the workflow
+            let _ = chan.send(CancelOrTimeout::RunCancelled(owned_run_id));
+        });
+        dlock
+            .run_tokens
+            .insert(run_id.to_string(), (tok.clone(), watcher));
+        tok
+    }
+
+    /// Cancels every activity of the run, then tears down the run's own state. The order matters:
+    /// cancelling first lets anyone waiting on the activities see their resolutions. Returns the
+    /// resolutions of the activities which could be resolved right away.
+    fn cancel_run(
+        &self,
+        run_id: &str,
+        reason: ActivityCancelReason,
+    ) -> Vec<LocalActivityResolution> {
+        let resolutions = self.cancel_all_in_run(run_id, reason);
+        self.stop_serializing_run(run_id);
+        self.forget_run(run_id);
+        resolutions
+    }
+
+    /// Forgets per-run state which outlives the run's individual activities, once they've all
+    /// been cancelled
+    fn forget_run(&self, run_id: &str) {
+        let mut dlock = self.dat.lock();
+        if let Some((_, watcher)) = dlock.run_tokens.remove(run_id) {
+            watcher.abort();
+        }
+        dlock
+            .superseded_tokens
+            .retain(|_, (id, _)| id.run_id != run_id);
+        dlock.run_metrics.remove(run_id);
+        // Anyone still waiting through a timer backoff would otherwise wait forever. Activities
+        // still being tracked (ex: waiting on lang to acknowledge a cancel) will resolve later.
+        let dat = &mut *dlock;
+        dat.resolution_waiters
+            .retain(|id, _| id.run_id != run_id || dat.la_info.contains_key(id));
+        let stale: Vec<_> = dlock
+            .recovered
+            .keys()
+            .filter(|id| id.run_id == run_id)
+            .cloned()
+            .collect();
+        for id in stale {
+            dlock.recovered.remove(&id);
+            self.unpersist(&id);
+        }
+    }
+
+    /// Must be called with every final resolution of an activity, however it was produced
+    fn activity_resolved(
+        &self,
+        dat: &mut LAMData,
+        id: &ExecutingLAId,
+        resolution: &LocalActivityResolution,
+    ) {
+        self.unpersist(id);
+        self.activity_resolved_until_restart(dat, id, resolution);
+    }
+
+    /// Like [Self::activity_resolved], but keeps the activity's persisted state. For activities
+    /// which only resolve because the worker is stopping, and which the workflow will schedule
+    /// again once it restarts.
+    fn activity_resolved_until_restart(
+        &self,
+        dat: &mut LAMData,
+        id: &ExecutingLAId,
+        resolution: &LocalActivityResolution,
+    ) {
+        self.emit(LaEvent::Resolved {
+            id: id.clone(),
+            attempt: resolution.attempt,
+        });
+        // Anything resolved while queued no longer counts against the queue depth limit
+        dat.queued_new.pop(id);
+        dat.notify_resolution_waiters(id, resolution);
+        #[cfg(any(feature = "test-utilities", test))]
+        if let Some(chaos) = dat.chaos.as_mut() {
+            chaos.delayed.remove(id);
+        }
+        if let Some(sr) = dat.serialized_runs.get_mut(&id.run_id) {
+            sr.waiting.remove(&id.seq_num);
+            if sr.active == Some(id.seq_num) {
+                sr.active = None;
+                self.advance_serialized_run(dat, &id.run_id);
+            }
+        }
+        // Only looked for when needed, since it means going over everything tracked
+        let run_done = (self.opts.strict_attempt_order || dat.run_tokens.contains_key(&id.run_id))
+            && !dat
+                .la_info
+                .keys()
+                .any(|tracked| tracked.run_id == id.run_id);
+        if !self.opts.strict_attempt_order {
+            dat.superseded_tokens.retain(|_, (sid, _)| sid != id);
+        } else if run_done {
+            // Late attempts of a run with nothing left tracked can't be confused with anything
+            dat.superseded_tokens
+                .retain(|_, (sid, _)| sid.run_id != id.run_id);
+        }
+        // The run's token has nothing left to cancel, and its watcher would otherwise live until
+        // the run is evicted
+        if run_done && let Some((_, watcher)) = dat.run_tokens.remove(&id.run_id) {
+            watcher.abort();
+        }
+    }
+
+    /// Drains every cancel waiting to be dispatched without blocking, and returns the cancel tasks
+    /// for the caller to deliver to lang. Used when shutdown starts draining, so that lang gets a
+    /// chance to clean up rather than the cancels sitting behind new requests, or being dropped if
+    /// shutdown times out. Any timeouts encountered while draining are put back to be handled as
+    /// usual.
+    fn flush_cancels(&self) -> Vec<ActivityTask> {
+        let drained: Vec<_> = {
+            let mut rx = self.cancels_req_rx.lock();
+            std::iter::from_fn(|| rx.try_recv().ok()).collect()
+        };
+        let mut tasks = vec![];
+        for c in drained {
+            match c {
+                CancelOrTimeout::Cancel(c) => tasks.extend(self.dispatchable_cancel(c)),
+                other => {
+                    self.send_cancel_or_timeout(other);
+                }
+            }
+        }
+        tasks
+    }
+
+    fn dispatchable_cancel(&self, c: ActivityTask) -> Option<ActivityTask> {
+        self.queued_cancels.lock().remove(c.task_token.as_slice());
+        let mut dlock = self.dat.lock();
+        let dat = &mut *dlock;
+        let id = match dat
+            .outstanding_activity_tasks
+            .get_mut(c.task_token.as_slice())
+        {
+            // Don't dispatch cancels for things we've already stopped tracking
+            None => return None,
+            // Workflows can ask to cancel the same activity many times in a row (ex: while
+            // replaying), but lang only needs to hear about it once per attempt
+            Some(info) if info.cancel_dispatched => {
+                debug!(task_token=%TaskToken(c.task_token.clone()),
+                       "Dropping repeat cancel of local activity");
+                return None;
+            }
+            Some(info) => {
+                info.cancel_dispatched = true;
+                ExecutingLAId {
+                    run_id: info.la_info.workflow_exec_info.run_id.clone(),
+                    seq_num: info.la_info.schedule_cmd.seq,
+                }
+            }
+        };
+        if let Some(window) = self.opts.cancel_ack_timeout
+            && let Some(tb) = dat
+                .la_info
+                .get_mut(&id)
+                .and_then(|lai| lai.timeout_bag.as_mut())
+        {
+            tb.await_cancel_ack(window, TaskToken(c.task_token.clone()));
+        }
+        Some(c)
+    }
+
+    /// Returns the number of activity requests which are ready to be dispatched, but are waiting
+    /// for a slot to free up
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn pending_waiters(&self) -> usize {
+        self.permit_waiters.load(Ordering::Acquire)
+    }
+
+    /// Checks that every permit handed out for local activities is accounted for: it is either
+    /// attached to an outstanding task, or has been acquired but not yet used (eagerly by the
+    /// request stream, or by a parked-for-dispatch request). Panics if any permit has leaked.
+    #[cfg(test)]
+    pub(crate) fn assert_permit_accounting(&self) {
+        let dat = self.dat.lock();
+        let extant = *self.permit_dealer.get_extant_count_rcv().borrow();
+        let acquired_unused = self.permit_dealer.unused_claimants();
+        assert_eq!(
+            dat.outstanding_activity_tasks.len() + acquired_unused,
+            extant,
+            "Local activity permits leaked: {} outstanding tasks, {acquired_unused} acquired but \
+             unused permits, {extant} permits handed out",
+            dat.outstanding_activity_tasks.len(),
+        );
+    }
+
+    /// Drops the receive halves of the request channels and closes the cancel channel, as happens
+    /// when the manager is partially torn down
+    #[cfg(test)]
+    async fn close_receivers(&self) {
+        *self.rcvs.lock().await = RcvChans::new(
+            unbounded_channel().1,
+            self.permit_dealer.clone(),
+            self.cancels_req_rx.clone(),
+            unbounded_channel().1,
+            self.shutdown_complete_tok.clone(),
+            MetricsContext::no_op(),
+            self.permit_waiters.clone(),
+        );
+        self.cancels_req_rx.lock().close();
+    }
+
+    /// Waits for the manager to have nothing left to do, then checks that nothing was left behind:
+    /// no activity is tracked anywhere and every permit has gone back to the dealer. Tests should
+    /// call this once they have taken every action they expect from [Self::next_pending], since it
+    /// closes the request stream.
+    #[cfg(test)]
+    pub(crate) async fn drain_and_assert_clean(&self) {
+        let mut outstanding = self.outstanding_count.subscribe();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            // With nobody calling next_pending, only outstanding attempts finishing can move
+            // things along
+            while self.has_pending_work() {
+                outstanding
+                    .changed()
+                    .await
+                    .expect("Manager owns the sender");
+            }
+        })
+        .await
+        .expect("Local activity manager never became idle");
+        // Hands back the permit the stream may have acquired ahead of the next request
+        self.rcvs.lock().await.inner = stream::empty().boxed();
+        let dat = self.dat.lock();
+        assert!(dat.la_info.is_empty(), "Leaked: {:?}", dat.la_info.keys());
+        assert!(dat.outstanding_activity_tasks.is_empty());
+        assert!(dat.executing_per_limited_type.values().all(|n| *n == 0));
+        assert!(dat.parked_per_limited_type.values().all(VecDeque::is_empty));
+        assert!(
+            dat.serialized_runs
+                .values()
+                .all(|sr| sr.waiting.is_empty() && sr.active.is_none())
+        );
+        assert!(dat.resolution_waiters.is_empty());
+        assert!(dat.queued_new.is_empty());
+        assert!(dat.superseded_tokens.is_empty());
+        assert!(self.queued_cancels.lock().is_empty());
+        assert_eq!(self.permit_dealer.unused_claimants(), 0);
+        assert_eq!(*self.permit_dealer.get_extant_count_rcv().borrow(), 0);
     }
 
     /// Returns the next pending local-activity related action, or None if shutdown has initiated
     /// and there are no more remaining actions to take.
     pub(crate) async fn next_pending(&self) -> Option<NextPendingLAAction> {
-        let (new_or_retry, permit) = match self.rcvs.lock().await.next().await? {
-            NewOrCancel::Cancel(c) => {
-                return match c {
-                    CancelOrTimeout::Cancel(c) => {
-                        if self
-                            .dat
-                            .lock()
-                            .outstanding_activity_tasks
-                            .contains_key(c.task_token.as_slice())
-                        {
-                            Some(NextPendingLAAction::Dispatch(c))
-                        } else {
-                            // Don't dispatch cancels for things we've already stopped tracking
+        let next = self.next_pending_action().await;
+        #[cfg(any(feature = "test-utilities", test))]
+        if let Some(NextPendingLAAction::Dispatch(task)) = next.as_ref()
+            && let Some(observer) = self.opts.dispatch_observer.as_ref()
+        {
+            observer(task);
+        }
+        next
+    }
+
+    async fn next_pending_action(&self) -> Option<NextPendingLAAction> {
+        let (new_or_retry, permit) = loop {
+            match self.rcvs.lock().await.next().await? {
+                NewOrCancel::FlushedCancel(task) => {
+                    return Some(NextPendingLAAction::Dispatch(task));
+                }
+                NewOrCancel::Cancel(c) => {
+                    return match c {
+                        CancelOrTimeout::Cancel(c) => self
+                            .dispatchable_cancel(c)
+                            .map(NextPendingLAAction::Dispatch),
+                        CancelOrTimeout::Timeout { run_id, resolution } => {
+                            self.handle_timeout(run_id, resolution)
+                        }
+                        #[cfg(any(feature = "test-utilities", test))]
+                        CancelOrTimeout::RunCancelled(run_id) => {
+                            debug!(run_id=%run_id,
+                                   "Run cancellation token cancelled, cancelling its local \
+                                    activities");
+                            let resolutions =
+                                self.cancel_run(&run_id, ActivityCancelReason::Cancelled);
+                            // Only one action can be returned at a time, so each resolution is
+                            // queued up to be reported on its own
+                            for resolution in resolutions {
+                                self.send_cancel_or_timeout(CancelOrTimeout::Resolved {
+                                    run_id: run_id.clone(),
+                                    resolution,
+                                });
+                            }
                             None
                         }
-                    }
-                    CancelOrTimeout::Timeout { run_id, resolution } => {
-                        let tt = self
-                            .dat
-                            .lock()
-                            .la_info
-                            .get(&ExecutingLAId {
+                        CancelOrTimeout::DeadlineApproaching(id) => {
+                            let dat = self.dat.lock();
+                            // Only an executing attempt has anything to wind down
+                            dat.la_info
+                                .get(&id)
+                                .map(|lai| &lai.task_token)
+                                .filter(|tt| dat.outstanding_activity_tasks.contains_key(*tt))
+                                .map(|tt| {
+                                    debug!(?id, "Local activity deadline approaching");
+                                    // Not a real cancel, so it's exempt from coalescing and the
+                                    // cancel acknowledgement timeout
+                                    let reason = ActivityCancelReason::DeadlineApproaching;
+                                    NextPendingLAAction::Dispatch(ActivityTask::cancel_from_ids(
+                                        tt.0.clone(),
+                                        reason,
+                                        ActivityTask::primary_reason_to_cancellation_details(
+                                            reason,
+                                        ),
+                                    ))
+                                })
+                        }
+                        CancelOrTimeout::Resolved { run_id, resolution } => Some(
+                            NextPendingLAAction::Autocomplete(LACompleteAction::Report {
                                 run_id,
-                                seq_num: resolution.seq,
-                            })
-                            .as_ref()
-                            .map(|lai| lai.task_token.clone());
-                        if let Some(task_token) = tt {
-                            Some(NextPendingLAAction::Autocomplete(
-                                self.complete(&task_token, resolution.result),
-                            ))
-                        } else {
-                            // This timeout is for a no-longer-tracked activity, so, whatever
-                            None
+                                resolution,
+                                task: None,
+                            }),
+                        ),
+                        CancelOrTimeout::CancelAckTimeout(tt) => {
+                            if self.dat.lock().outstanding_activity_tasks.contains_key(&tt) {
+                                warn!(task_token=%tt,
+                                      "Lang did not complete cancelled local activity in time, \
+                                       resolving it as cancelled");
+                                Some(NextPendingLAAction::Autocomplete(
+                                    self.complete(&tt, self.empty_cancel()),
+                                ))
+                            } else {
+                                None
+                            }
                         }
+                    };
+                }
+                NewOrCancel::New(n, perm) => {
+                    self.req_tx.received();
+                    // Parked requests are re-sent once their type has capacity again. The permit is
+                    // released so other activity types can make progress in the meantime.
+                    if let Some(n) = self.park_if_type_at_capacity(n) {
+                        break (n, perm);
                     }
-                };
+                }
             }
-            NewOrCancel::New(n, perm) => (n, perm),
         };
-
         // It is important that there are no await points after receiving from the channel, as
-        // it would mean dropping this future would cause us to drop the activity request.
-        let (new_la, attempt) = match new_or_retry {
+        // it would mean dropping this future would cause us to drop the activity request. Permits
+        // are safe across drops of this future: one being acquired eagerly lives in the request
+        // stream's state (not this future), and once received it is owned by `permit`, which is
+        // either moved into the outstanding task map or released on any early return.
+        #[cfg(any(feature = "test-utilities", test))]
+        let new_or_retry = self.chaos_delay(new_or_retry)?;
+        let new_or_retry = self.rate_limit(new_or_retry)?;
+
+        let is_new = matches!(new_or_retry, NewOrRetry::New(_));
+        // Lang schedules the next attempt itself after a timer backoff, so a new request can still
+        // be a retry if it says so
+        let first_attempt = match &new_or_retry {
+            NewOrRetry::New(n) => n.schedule_cmd.attempt <= 1,
+            NewOrRetry::Retry { .. } => false,
+        };
+        let (new_la, attempt, heartbeat_details, attempt_scheduled_time) = match new_or_retry {
             NewOrRetry::New(n) => {
                 let explicit_attempt_num_or_1 = n.schedule_cmd.attempt.max(1);
-                (n, explicit_attempt_num_or_1)
+                let schedule_time = n.schedule_time;
+                (n, explicit_attempt_num_or_1, vec![], schedule_time)
             }
-            NewOrRetry::Retry { in_flight, attempt } => (in_flight, attempt),
+            NewOrRetry::Retry {
+                in_flight,
+                attempt,
+                heartbeat_details,
+                attempt_scheduled_time,
+            } => (
+                in_flight,
+                attempt,
+                heartbeat_details,
+                attempt_scheduled_time,
+            ),
         };
         let la_info_for_in_flight_map = new_la.clone();
         let id = ExecutingLAId {
@@ -447,60 +2271,282 @@ impl LocalActivityManager {
         let sa = new_la.schedule_cmd;
 
         let mut dat = self.dat.lock();
+        if is_new {
+            dat.queued_new.pop(&id);
+        }
         // If this request originated from a local backoff task, clear the entry for it. We
         // don't await the handle because we know it must already be done, and there's no
         // meaningful value.
-        dat.la_info
+        let finished_backoff = dat
+            .la_info
             .get_mut(&id)
-            .map(|lai| lai.backing_off_task.take());
+            .and_then(|lai| lai.backing_off_task.take());
+        // New requests only wait like this for a start delay, which isn't a backoff
+        if !is_new && let Some(b) = finished_backoff {
+            let backoff_time = b.started_at.elapsed();
+            dat.run_metrics_mut(&id.run_id).backoff_time += backoff_time;
+            guard_metrics(|| {
+                self.metrics_for_activity_type(&sa.activity_type)
+                    .la_backoff_latency(backoff_time)
+            });
+        }
+        if !dat.la_info.contains_key(&id) {
+            // The activity stopped being tracked while its request sat in the queue. Bail before
+            // touching any bookkeeping so dropping the request also returns its permit.
+            debug!(
+                ?id,
+                "Dropping queued local activity request which is no longer tracked"
+            );
+            return None;
+        }
 
         // If this task sat in the queue for too long, return a timeout for it instead
-        if let Some(s2s) = sa.schedule_to_start_timeout.as_ref() {
-            let sat_for = new_la.schedule_time.elapsed().unwrap_or_default();
+        if !self.opts.replaying()
+            && let Some(s2s) = sa.schedule_to_start_timeout.as_ref()
+        {
+            let sat_for = new_la
+                .schedule_time
+                .elapsed()
+                .unwrap_or_default()
+                .saturating_sub(sa.start_delay.unwrap_or_default());
             if sat_for > *s2s {
+                let timeout = LocalActivityExecutionResult::timeout(TimeoutType::ScheduleToStart);
+                // Held to the same rules as timeouts of attempts which did start
+                let retry_backoff = if sa.retry_schedule_to_start_timeout
+                    && !self.retries_forbidden(&id, &sa, dat.la_info.get(&id), true)
+                    && !matches!(&timeout, LocalActivityExecutionResult::TimedOut(ActFail {
+                        failure: Some(f),
+                    }) if self.is_non_retryable(f))
+                {
+                    self.retry_backoff(&sa.retry_policy, attempt, None)
+                } else {
+                    None
+                };
+                let local_retries = dat.la_info.get(&id).map_or(0, |lai| lai.local_retries);
+                let timer_backoff = match retry_backoff
+                    .map(|b| self.backoff_for_retry(&id, &sa, b, local_retries))
+                {
+                    Some(RetryBackoff::Local(backoff)) => {
+                        debug!(id=?id, attempt,
+                               "Retrying local activity after schedule-to-start timeout");
+                        // Any start delay has been served by now
+                        let mut schedule_cmd = sa;
+                        schedule_cmd.start_delay = None;
+                        // Still tracked, since that was checked under this same lock
+                        let task_token = dat.la_info[&id].task_token.clone();
+                        let backing_off = self.schedule_retry(
+                            &mut dat,
+                            &id,
+                            &task_token,
+                            PendingRetry {
+                                in_flight: NewLocalAct {
+                                    schedule_cmd,
+                                    ..new_la
+                                },
+                                failed_attempt: attempt,
+                                heartbeat_details,
+                                // The next attempt gets a whole schedule-to-start window of its own
+                                reset_schedule_time: true,
+                            },
+                            &timeout,
+                            backoff,
+                        );
+                        if let Some(lai) = dat.la_info.get_mut(&id) {
+                            lai.local_retries += 1;
+                            lai.backing_off_task = Some(backing_off);
+                        }
+                        return Some(NextPendingLAAction::Autocomplete(
+                            LACompleteAction::WillBeRetried(None),
+                        ));
+                    }
+                    Some(RetryBackoff::Timer(timer)) => timer,
+                    None => None,
+                };
+                if timer_backoff.is_some() {
+                    debug!(id=?id, attempt,
+                           "Local activity timed out before starting, will retry after a timer");
+                    self.count_retry(&mut dat, &id.run_id);
+                } else {
+                    self.lifetime_counters
+                        .failed_terminal
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+                let resolution = LocalActivityResolution {
+                    seq: sa.seq,
+                    result: timeout,
+                    runtime: sat_for,
+                    attempt,
+                    backoff: timer_backoff,
+                    original_schedule_time: orig_sched_time,
+                    correlation_id: new_la.correlation_id,
+                    effective_timeouts: dat
+                        .la_info
+                        .get(&id)
+                        .and_then(|lai| lai.timeout_bag.as_ref())
+                        .map(TimeoutBag::effective)
+                        .unwrap_or_default(),
+                };
+                dat.la_info.remove(&id);
+                self.activity_resolved(&mut dat, &id, &resolution);
                 return Some(NextPendingLAAction::Autocomplete(
                     LACompleteAction::Report {
                         run_id: new_la.workflow_exec_info.run_id,
-                        resolution: LocalActivityResolution {
-                            seq: sa.seq,
-                            result: LocalActivityExecutionResult::timeout(
-                                TimeoutType::ScheduleToStart,
-                            ),
-                            runtime: sat_for,
-                            attempt,
-                            backoff: None,
-                            original_schedule_time: orig_sched_time,
-                        },
+                        resolution,
                         task: None,
                     },
                 ));
             }
         }
 
-        let la_info = dat.la_info.get_mut(&id).expect("Activity must exist");
+        if self.opts.circuit_breaker.is_some()
+            && dat
+                .circuit_breakers
+                .get_mut(&sa.activity_type)
+                .is_some_and(CircuitBreaker::is_open)
+        {
+            debug!(?id, activity_type=%sa.activity_type,
+                   "Circuit is open, failing local activity without dispatching it");
+            let resolution = LocalActivityResolution {
+                seq: sa.seq,
+                result: LocalActivityExecutionResult::non_retryable_failure(format!(
+                    "Circuit breaker is open for local activity type '{}'",
+                    sa.activity_type
+                )),
+                runtime: Duration::ZERO,
+                attempt,
+                backoff: None,
+                original_schedule_time: orig_sched_time,
+                correlation_id: new_la.correlation_id,
+                effective_timeouts: dat
+                    .la_info
+                    .get(&id)
+                    .and_then(|lai| lai.timeout_bag.as_ref())
+                    .map(TimeoutBag::effective)
+                    .unwrap_or_default(),
+            };
+            dat.la_info.remove(&id);
+            self.activity_resolved(&mut dat, &id, &resolution);
+            return Some(NextPendingLAAction::Autocomplete(
+                LACompleteAction::Report {
+                    run_id: id.run_id,
+                    resolution,
+                    task: None,
+                },
+            ));
+        }
+
+        if let Some(executor) = self.opts.local_executor.as_ref()
+            && let Some(result) =
+                executor.try_execute(&id, &sa.activity_type, &sa.arguments, attempt)
+        {
+            debug!(?id, "Local activity was run by the local executor");
+            let resolution = LocalActivityResolution {
+                attempt,
+                ..LocalActivityResolution::immediate(&la_info_for_in_flight_map, result)
+            };
+            dat.la_info.remove(&id);
+            self.activity_resolved(&mut dat, &id, &resolution);
+            return Some(NextPendingLAAction::Autocomplete(
+                LACompleteAction::Report {
+                    run_id: id.run_id,
+                    resolution,
+                    task: None,
+                },
+            ));
+        }
+
+        if !dat.la_info.contains_key(&id) {
+            // Checked above under the same lock, but dropping one request beats taking down the
+            // worker. The permit goes back with it.
+            dbg_panic!("Local activity {id:?} stopped being tracked while being dispatched");
+            return None;
+        }
+        if self
+            .opts
+            .max_concurrent_per_type
+            .contains_key(&sa.activity_type)
+        {
+            *dat.executing_per_limited_type
+                .entry(sa.activity_type.clone())
+                .or_default() += 1;
+        }
+        let la_info = dat.la_info.get_mut(&id)?;
         let tt = la_info.task_token.clone();
         if let Some(to) = la_info.timeout_bag.as_mut() {
             to.mark_started();
         }
+        self.persist(
+            &id,
+            PersistedLa {
+                task_token: tt.clone(),
+                attempt,
+                phase: PersistedLaPhase::InFlight,
+            },
+            dat.next_tt_num,
+        );
+        if let Some(bucket) = dat.rate_limiter.as_mut() {
+            bucket.take();
+        }
         dat.outstanding_activity_tasks.insert(
             tt.clone(),
             LocalInFlightActInfo {
                 la_info: la_info_for_in_flight_map,
-                dispatch_time: Instant::now(),
+                dispatch_time: tokio::time::Instant::now(),
                 attempt,
+                last_heartbeat_details: heartbeat_details.clone(),
+                cancel_dispatched: false,
                 _permit: permit.into_used(LocalActivitySlotInfo {
                     activity_type: sa.activity_type.clone(),
                 }),
             },
         );
 
-        let (schedule_to_close, start_to_close) = sa.close_timeouts.into_sched_and_start();
-        self.metrics
-            .with_new_attrs([
-                activity_type(sa.activity_type.clone()),
-                workflow_type(new_la.workflow_type.clone()),
-            ])
-            .la_executed();
+        let (schedule_to_close, start_to_close) = sa
+            .close_timeouts
+            .map(LACloseTimeouts::into_sched_and_start)
+            .unwrap_or_default();
+        guard_metrics(|| {
+            self.metrics_for_activity_type(&sa.activity_type)
+                .with_new_attrs([workflow_type(new_la.workflow_type.clone())])
+                .la_executed()
+        });
+        self.lifetime_counters
+            .dispatched
+            .fetch_add(1, Ordering::Relaxed);
+        dat.run_metrics_mut(&id.run_id).dispatched += 1;
+        #[cfg(any(feature = "test-utilities", test))]
+        if let Some(result) = self.chaos_outcome(&mut dat) {
+            debug!(task_token=%tt, "Chaos mode resolving local activity attempt without running it");
+            return Some(NextPendingLAAction::Autocomplete(
+                self.complete_locked(&mut dat, &tt, result),
+            ));
+        }
+        drop(dat);
+        self.emit(LaEvent::Dispatched {
+            id: id.clone(),
+            attempt,
+        });
+        if let Some(hook) = self.opts.attempt_dispatch_hook.as_ref() {
+            hook(&id, attempt, !is_new);
+        }
+        let mut header_fields = sa.headers;
+        self.context_propagator().inject(&id, &mut header_fields);
+        if let Some(correlation_id) = new_la.correlation_id {
+            header_fields.insert(
+                LA_CORRELATION_ID_HEADER.to_string(),
+                correlation_id
+                    .as_json_payload()
+                    .expect("Serializing a string cannot fail"),
+            );
+        }
+        if let Some(build_id) = new_la.build_id {
+            header_fields.insert(
+                LA_BUILD_ID_HEADER.to_string(),
+                build_id
+                    .as_json_payload()
+                    .expect("Serializing a string cannot fail"),
+            );
+        }
         Some(NextPendingLAAction::Dispatch(ActivityTask {
             task_token: tt.0,
             variant: Some(activity_task::Variant::Start(Start {
@@ -509,11 +2555,11 @@ impl LocalActivityManager {
                 workflow_execution: Some(new_la.workflow_exec_info),
                 activity_id: sa.activity_id,
                 activity_type: sa.activity_type,
-                header_fields: sa.headers,
+                header_fields,
                 input: sa.arguments,
-                heartbeat_details: vec![],
+                heartbeat_details,
                 scheduled_time: Some(new_la.schedule_time.into()),
-                current_attempt_scheduled_time: Some(new_la.schedule_time.into()),
+                current_attempt_scheduled_time: Some(attempt_scheduled_time.into()),
                 started_time: Some(SystemTime::now().into()),
                 attempt,
                 schedule_to_close_timeout: schedule_to_close
@@ -527,40 +2573,311 @@ impl LocalActivityManager {
                 retry_policy: Some(sa.retry_policy.into()),
                 priority: Some(Default::default()),
                 is_local: true,
+                non_idempotent: sa.non_idempotent,
+                first_attempt,
             })),
         }))
     }
 
+    /// Returns the request if the dispatch rate limit allows it to go now. Otherwise it is put back
+    /// to try again once the limiter should have a token for it. The token is taken at dispatch.
+    fn rate_limit(&self, req: NewOrRetry) -> Option<NewOrRetry> {
+        let Some(limit) = self.opts.dispatch_rate_limit.as_ref() else {
+            return Some(req);
+        };
+        let la = req.la();
+        let id = ExecutingLAId {
+            run_id: la.workflow_exec_info.run_id.clone(),
+            seq_num: la.schedule_cmd.seq,
+        };
+        let mut dlock = self.dat.lock();
+        let dat = &mut *dlock;
+        let (Some(bucket), Some(lai)) = (dat.rate_limiter.as_mut(), dat.la_info.get_mut(&id))
+        else {
+            // Untracked requests are dropped by the usual path
+            return Some(req);
+        };
+        match bucket.wait_for_token(limit) {
+            None => Some(req),
+            Some(wait) => {
+                trace!(?id, ?wait, "Local activity dispatch is rate limited");
+                self.requeue_after(lai, req, wait);
+                None
+            }
+        }
+    }
+
+    /// Sends the request back to the dispatch queue after a delay. It waits like a backoff does,
+    /// so no permit is held in the meantime and cancels resolve it immediately.
+    fn requeue_after(&self, lai: &mut LocalActivityInfo, req: NewOrRetry, delay: Duration) {
+        let attempt = match &req {
+            NewOrRetry::New(n) => n.schedule_cmd.attempt.max(1),
+            NewOrRetry::Retry { attempt, .. } => *attempt,
+        };
+        let req_tx = self.req_tx.clone();
+        let handle = self.spawner.spawn(async move {
+            sleep(delay).await;
+            req_tx.send(req);
+        });
+        // A request held back on its way out of a backoff is still waiting on that backoff
+        let started_at = lai
+            .backing_off_task
+            .as_ref()
+            .map_or_else(tokio::time::Instant::now, |b| b.started_at);
+        lai.backing_off_task = Some(LocalBackoff {
+            handle,
+            failed_attempt: attempt,
+            retry_at: Instant::now() + delay,
+            started_at,
+        });
+    }
+
+    /// Returns the request if it should be dispatched now. Otherwise chaos mode has decided to hold
+    /// it back, which is done like a backoff so that no permit is held and cancels resolve at once.
+    #[cfg(any(feature = "test-utilities", test))]
+    fn chaos_delay(&self, req: NewOrRetry) -> Option<NewOrRetry> {
+        let Some(cfg) = self.opts.chaos.as_ref() else {
+            return Some(req);
+        };
+        let la = req.la();
+        let id = ExecutingLAId {
+            run_id: la.workflow_exec_info.run_id.clone(),
+            seq_num: la.schedule_cmd.seq,
+        };
+        let mut dlock = self.dat.lock();
+        let dat = &mut *dlock;
+        let (Some(chaos), Some(lai)) = (dat.chaos.as_mut(), dat.la_info.get_mut(&id)) else {
+            // Untracked requests are dropped by the usual path
+            return Some(req);
+        };
+        if chaos.delayed.remove(&id) || !chaos.rng.random_bool(cfg.delay_probability) {
+            return Some(req);
+        }
+        chaos.delayed.insert(id.clone());
+        let delay = cfg.max_delay.mul_f64(chaos.rng.random());
+        debug!(?id, ?delay, "Chaos mode delaying local activity dispatch");
+        self.requeue_after(lai, req, delay);
+        None
+    }
+
+    /// Decides whether chaos mode replaces a dispatched attempt with a failure or timeout
+    #[cfg(any(feature = "test-utilities", test))]
+    fn chaos_outcome(&self, dat: &mut LAMData) -> Option<LocalActivityExecutionResult> {
+        let cfg = self.opts.chaos.as_ref()?;
+        let rng = &mut dat.chaos.as_mut()?.rng;
+        if rng.random_bool(cfg.failure_probability) {
+            Some(LocalActivityExecutionResult::Failed(ActFail {
+                failure: Some(APIFailure::application_failure(
+                    "Failure injected by local activity chaos mode".to_string(),
+                    false,
+                )),
+            }))
+        } else if rng.random_bool(cfg.timeout_probability) {
+            Some(LocalActivityExecutionResult::timeout(
+                TimeoutType::StartToClose,
+            ))
+        } else {
+            None
+        }
+    }
+
     /// Mark a local activity as having completed
     pub(crate) fn complete(
         &self,
         task_token: &TaskToken,
         status: LocalActivityExecutionResult,
     ) -> LACompleteAction {
+        self.log_result_payloads(task_token, &status);
+        let status = self.intercept_result(status);
         let mut dlock = self.dat.lock();
-        if let Some(info) = dlock.outstanding_activity_tasks.remove(task_token) {
-            if self.workflows_have_shut_down.is_cancelled() {
-                // If workflows are already shut down, the results of all this don't matter.
-                // Just say we're done if there's nothing outstanding any more.
-                self.set_shutdown_complete_if_ready(&mut dlock);
-            }
+        self.complete_locked(&mut dlock, task_token, status)
+    }
 
-            let exec_id = ExecutingLAId {
+    /// Break-glass resolution of an activity lang will never complete, ex: because the process
+    /// running it crashed. The attempt is failed with the provided failure and reported to the
+    /// workflow as final, regardless of its retry policy. The result interceptor is not applied.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn force_fail(
+        &self,
+        task_token: &TaskToken,
+        failure: APIFailure,
+    ) -> LACompleteAction {
+        let mut dlock = self.dat.lock();
+        let id = dlock
+            .outstanding_activity_tasks
+            .get(task_token)
+            .map(|info| ExecutingLAId {
                 run_id: info.la_info.workflow_exec_info.run_id.clone(),
                 seq_num: info.la_info.schedule_cmd.seq,
-            };
-            let maybe_old_lai = dlock.la_info.remove(&exec_id);
-            if let Some(ref oldlai) = maybe_old_lai
-                && let Some(ref bot) = oldlai.backing_off_task
-            {
-                dbg_panic!("Just-resolved LA should not have backoff task");
-                bot.abort();
-            }
-
-            let la_metrics = self.metrics.with_new_attrs([
-                activity_type(info.la_info.schedule_cmd.activity_type.clone()),
-                workflow_type(info.la_info.workflow_type.clone()),
-            ]);
+            });
+        if let Some(lai) = id.as_ref().and_then(|id| dlock.la_info.get_mut(id)) {
+            warn!(id=?id, "Force failing local activity");
+            lai.retries_disabled = true;
+        }
+        self.complete_locked(
+            &mut dlock,
+            task_token,
+            LocalActivityExecutionResult::Failed(ActFail {
+                failure: Some(failure),
+            }),
+        )
+    }
+
+    /// Returns the metrics context for activities of the given type. They're cached, since deriving
+    /// one re-binds every instrument, and there are only ever as many as there are activity types.
+    fn metrics_for_activity_type(&self, activity_type: &str) -> MetricsContext {
+        self.activity_type_metrics
+            .lock()
+            .entry(activity_type.to_string())
+            .or_insert_with(|| {
+                // If the backend can't even build the instruments, stop giving it the chance
+                guard_metrics(|| self.metrics.with_activity_type(activity_type))
+                    .unwrap_or_else(MetricsContext::no_op)
+            })
+            .clone()
+    }
+
+    /// Whether the configured [FailureClassifier] rules the failure out of being retried
+    fn is_non_retryable(&self, failure: &APIFailure) -> bool {
+        self.opts
+            .failure_classifier
+            .as_deref()
+            .unwrap_or(&DefaultFailureClassifier)
+            .classify(failure)
+            == FailureClassification::NonRetryable
+    }
+
+    fn context_propagator(&self) -> &dyn ContextPropagator {
+        self.opts
+            .context_propagator
+            .as_deref()
+            .unwrap_or(&NoopContextPropagator)
+    }
+
+    fn activity_type_allowed(&self, activity_type: &str) -> bool {
+        !self.opts.denied_activity_types.contains(activity_type)
+            && self
+                .opts
+                .allowed_activity_types
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(activity_type))
+    }
+
+    /// Renders payloads for logging, or returns `None` if payload logging is off
+    fn loggable_payloads<'a>(
+        &self,
+        payloads: impl IntoIterator<Item = &'a Payload>,
+    ) -> Option<Vec<String>> {
+        if !self.opts.log_payloads {
+            return None;
+        }
+        let redactor: &dyn Fn(&Payload) -> String = match &self.opts.payload_redactor {
+            Some(r) => r.as_ref(),
+            None => &redact_payload,
+        };
+        Some(payloads.into_iter().map(redactor).collect())
+    }
+
+    fn log_result_payloads(&self, task_token: &TaskToken, status: &LocalActivityExecutionResult) {
+        // Only successes carry a result payload
+        let LocalActivityExecutionResult::Completed(success) = status else {
+            return;
+        };
+        if let Some(result) = self.loggable_payloads(success.result.as_ref()) {
+            trace!(task_token=%task_token, ?result, "Local activity result");
+        }
+    }
+
+    fn intercept_result(
+        &self,
+        status: LocalActivityExecutionResult,
+    ) -> LocalActivityExecutionResult {
+        let status = match &self.opts.result_interceptor {
+            Some(interceptor) => interceptor(status),
+            None => status,
+        };
+        // Checked after interception, since that's what would end up in history
+        if let Some(max) = self.opts.max_result_size
+            && let LocalActivityExecutionResult::Completed(success) = &status
+        {
+            let result_size = success.result.as_ref().map_or(0, |p| p.encoded_len());
+            if result_size > max {
+                return LocalActivityExecutionResult::non_retryable_failure(format!(
+                    "Local activity result size of {result_size} bytes exceeds the maximum of \
+                     {max} bytes"
+                ));
+            }
+        }
+        status
+    }
+
+    fn complete_locked(
+        &self,
+        dlock: &mut MutexGuard<LAMData>,
+        task_token: &TaskToken,
+        status: LocalActivityExecutionResult,
+    ) -> LACompleteAction {
+        let action = self.complete_locked_inner(dlock, task_token, status);
+        if let LACompleteAction::Report {
+            run_id, resolution, ..
+        } = &action
+        {
+            self.activity_resolved(
+                dlock,
+                &ExecutingLAId {
+                    run_id: run_id.clone(),
+                    seq_num: resolution.seq,
+                },
+                resolution,
+            );
+        }
+        action
+    }
+
+    fn complete_locked_inner(
+        &self,
+        dlock: &mut MutexGuard<LAMData>,
+        task_token: &TaskToken,
+        mut status: LocalActivityExecutionResult,
+    ) -> LACompleteAction {
+        if let Some(info) = dlock.outstanding_activity_tasks.remove(task_token) {
+            if self.drain_started.is_cancelled() {
+                dlock.finished_while_draining += 1;
+            }
+            // Shutdown waits on the outstanding map draining, so it must be woken no matter which
+            // path (reporting, local retry, or timer backoff) this completion ends up taking.
+            self.outstanding_changed(dlock);
+            self.release_type_capacity(dlock, &info.la_info.schedule_cmd.activity_type);
+            if self.workflows_have_shut_down.is_cancelled() {
+                // If workflows are already shut down, the results of all this don't matter.
+                // Just say we're done if there's nothing outstanding any more.
+                self.set_shutdown_complete_if_ready(dlock);
+            }
+
+            let exec_id = ExecutingLAId {
+                run_id: info.la_info.workflow_exec_info.run_id.clone(),
+                seq_num: info.la_info.schedule_cmd.seq,
+            };
+            self.context_propagator().extract(&exec_id, &status);
+            let mut maybe_old_lai = dlock.la_info.remove(&exec_id);
+            if let Some(tb) = maybe_old_lai
+                .as_mut()
+                .and_then(|lai| lai.timeout_bag.as_mut())
+            {
+                tb.attempt_finished();
+            }
+            if let Some(ref oldlai) = maybe_old_lai
+                && let Some(ref bot) = oldlai.backing_off_task
+            {
+                dbg_panic!("Just-resolved LA should not have backoff task");
+                bot.handle.abort();
+            }
+
+            let la_metrics = guard_metrics(|| {
+                self.metrics_for_activity_type(&info.la_info.schedule_cmd.activity_type)
+                    .with_new_attrs([workflow_type(info.la_info.workflow_type.clone())])
+            })
+            .unwrap_or_else(MetricsContext::no_op);
 
             enum Outcome {
                 FailurePath { backoff: Option<Duration> },
@@ -568,30 +2885,61 @@ impl LocalActivityManager {
             }
             macro_rules! calc_backoff {
                 ($fail: ident) => {
-                    info.la_info.schedule_cmd.retry_policy.should_retry(
-                        info.attempt.try_into().unwrap_or(NonZero::<u32>::MIN),
-                        $fail
-                            .failure
-                            .as_ref()
-                            .and_then(|f| f.maybe_application_failure()),
-                    )
+                    if $fail
+                        .failure
+                        .as_ref()
+                        .is_some_and(|f| self.is_non_retryable(f))
+                    {
+                        None
+                    } else {
+                        self.retry_backoff(
+                            &info.la_info.schedule_cmd.retry_policy,
+                            info.attempt,
+                            $fail
+                                .failure
+                                .as_ref()
+                                .and_then(|f| f.maybe_application_failure()),
+                        )
+                    }
                 };
             }
 
+            let cut_short = maybe_old_lai
+                .as_ref()
+                .is_some_and(|lai| lai.cancel_requested || lai.attempt_aborted);
+            if matches!(status, LocalActivityExecutionResult::Cancelled(_))
+                && maybe_old_lai
+                    .as_ref()
+                    .is_some_and(|lai| lai.attempt_aborted)
+            {
+                // Only the attempt was cancelled, so it's up to the retry policy what happens next
+                status = LocalActivityExecutionResult::Failed(ActFail {
+                    failure: Some(APIFailure {
+                        message: "Local activity attempt was aborted".to_string(),
+                        failure_info: Some(failure::FailureInfo::ApplicationFailureInfo(
+                            ApplicationFailureInfo {
+                                r#type: "AttemptAborted".to_string(),
+                                ..Default::default()
+                            },
+                        )),
+                        ..Default::default()
+                    }),
+                });
+            }
             let mut is_timeout = false;
             let runtime = info.dispatch_time.elapsed();
-            la_metrics.la_exec_latency(runtime);
-            let outcome = match &status {
+            guard_metrics(|| la_metrics.la_exec_latency(runtime));
+            let mut outcome = match &status {
                 LocalActivityExecutionResult::Failed(fail) => {
                     if should_record_failure_metric(&fail.failure) {
-                        la_metrics.la_execution_failed()
+                        guard_metrics(|| la_metrics.la_execution_failed());
                     }
                     Outcome::FailurePath {
                         backoff: calc_backoff!(fail),
                     }
                 }
                 LocalActivityExecutionResult::TimedOut(fail) => {
-                    la_metrics.la_execution_failed();
+                    guard_metrics(|| la_metrics.la_execution_failed());
                     is_timeout = true;
                     // Start to close timeouts are retryable, other timeout types aren't.
                     if matches!(status.get_timeout_type(), Some(TimeoutType::StartToClose)) {
@@ -603,21 +2951,45 @@ impl LocalActivityManager {
                     }
                 }
                 LocalActivityExecutionResult::Completed(_) => {
+                    self.lifetime_counters
+                        .completed
+                        .fetch_add(1, Ordering::Relaxed);
                     if let Some(rt) = info
                         .la_info
                         .schedule_cmd
                         .original_schedule_time
                         .and_then(|t| t.elapsed().ok())
                     {
-                        la_metrics.la_exec_succeeded_latency(rt);
+                        guard_metrics(|| la_metrics.la_exec_succeeded_latency(rt));
                     }
                     Outcome::JustReport
                 }
                 LocalActivityExecutionResult::Cancelled { .. } => {
-                    la_metrics.la_execution_cancelled();
+                    guard_metrics(|| la_metrics.la_execution_cancelled());
                     Outcome::JustReport
                 }
             };
+            if let Some(cb_opts) = self.opts.circuit_breaker.as_ref() {
+                let breaker = dlock
+                    .circuit_breakers
+                    .entry(info.la_info.schedule_cmd.activity_type.clone())
+                    .or_default();
+                match &status {
+                    LocalActivityExecutionResult::Failed(_)
+                    | LocalActivityExecutionResult::TimedOut(_) => breaker.record_failure(cb_opts),
+                    LocalActivityExecutionResult::Completed(_) => breaker.record_success(),
+                    // Cancellation says nothing about the health of the activity's dependencies
+                    LocalActivityExecutionResult::Cancelled { .. } => {}
+                }
+            }
+            if self.retries_forbidden(
+                &exec_id,
+                &info.la_info.schedule_cmd,
+                maybe_old_lai.as_ref(),
+                cut_short || is_timeout,
+            ) {
+                outcome = Outcome::JustReport;
+            }
 
             let mut resolution = LocalActivityResolution {
                 seq: info.la_info.schedule_cmd.seq,
@@ -626,6 +2998,12 @@ impl LocalActivityManager {
                 attempt: info.attempt,
                 backoff: None,
                 original_schedule_time: info.la_info.schedule_cmd.original_schedule_time,
+                correlation_id: info.la_info.correlation_id.clone(),
+                effective_timeouts: maybe_old_lai
+                    .as_ref()
+                    .and_then(|lai| lai.timeout_bag.as_ref())
+                    .map(TimeoutBag::effective)
+                    .unwrap_or_default(),
             };
             // We want to generate a cancel task if the reason for failure was a timeout.
             let task = if is_timeout {
@@ -644,8 +3022,13 @@ impl LocalActivityManager {
                 Outcome::FailurePath { backoff } => {
                     if let Some(backoff_dur) = backoff {
                         let fail_or_timeout = if is_timeout { "timed out" } else { "failed" };
-                        let will_use_timer =
-                            backoff_dur > info.la_info.schedule_cmd.local_retry_threshold;
+                        let retry_backoff = self.backoff_for_retry(
+                            &exec_id,
+                            &info.la_info.schedule_cmd,
+                            backoff_dur,
+                            maybe_old_lai.as_ref().map_or(0, |old| old.local_retries),
+                        );
+                        let will_use_timer = matches!(retry_backoff, RetryBackoff::Timer(_));
                         debug!(run_id = %info.la_info.workflow_exec_info.run_id,
                                seq_num = %info.la_info.schedule_cmd.seq,
                                attempt = %info.attempt,
@@ -654,53 +3037,143 @@ impl LocalActivityManager {
                             fail_or_timeout,
                             backoff_dur
                         );
-                        if will_use_timer {
-                            // This la needs to write a failure marker, and then we will tell lang how
-                            // long of a timer to schedule to back off for. We do this because there are
-                            // no other situations where core generates "internal" commands so it is
-                            // much simpler for lang to reply with the timer / next LA command than to
-                            // do it internally. Plus, this backoff hack we'd like to eliminate
-                            // eventually.
-                            resolution.backoff =
-                                Some(backoff_dur.try_into().expect("backoff fits into proto"));
+                        let backoff_dur = match retry_backoff {
+                            RetryBackoff::Timer(timer) => {
+                                // This la needs to write a failure marker, and then we will tell
+                                // lang how long of a timer to schedule to back off for. We do this
+                                // because there are no other situations where core generates
+                                // "internal" commands so it is much simpler for lang to reply with
+                                // the timer / next LA command than to do it internally. Plus, this
+                                // backoff hack we'd like to eliminate eventually.
+                                resolution.backoff = timer;
+                                if resolution.backoff.is_some() {
+                                    self.count_retry(dlock, &exec_id.run_id);
+                                } else {
+                                    // Reported as final after all
+                                    self.lifetime_counters
+                                        .failed_terminal
+                                        .fetch_add(1, Ordering::Relaxed);
+                                }
+                                return LACompleteAction::Report {
+                                    run_id: info.la_info.workflow_exec_info.run_id,
+                                    resolution,
+                                    task,
+                                };
+                            }
+                            RetryBackoff::Local(backoff_dur) => backoff_dur,
+                        };
+                        if self.drain_started.is_cancelled() {
+                            // The retry could never be dispatched, so resolve it the same way as
+                            // the backoffs aborted when draining began
+                            debug!(run_id = %info.la_info.workflow_exec_info.run_id,
+                                   seq_num = %info.la_info.schedule_cmd.seq,
+                                   "Cancelling local activity retry since shutdown is draining");
+                            resolution.result = self.cancelled_during_backoff();
                             return LACompleteAction::Report {
                                 run_id: info.la_info.workflow_exec_info.run_id,
                                 resolution,
                                 task,
                             };
                         }
+                        let reset_schedule_time =
+                            info.la_info.schedule_cmd.reset_schedule_time_on_retry;
+                        let timeout_bag = if reset_schedule_time {
+                            // The window starts once the backoff is over, as it would for a start
+                            // delay
+                            let mut next_attempt = info.la_info.clone();
+                            next_attempt.schedule_cmd.start_delay = Some(backoff_dur);
+                            match TimeoutBag::new(
+                                &next_attempt,
+                                SystemTime::now(),
+                                self.cancels_req_tx.clone().into(),
+                                self.opts
+                                    .clock_skew_tolerance
+                                    .unwrap_or(DEFAULT_CLOCK_SKEW_TOLERANCE),
+                                self.opts.replaying(),
+                                self.spawner.clone(),
+                            ) {
+                                Ok(mut tb) => {
+                                    if let Some(lead) = self.opts.deadline_warning_lead {
+                                        tb.warn_before_deadline(lead, exec_id.clone());
+                                    }
+                                    Some(tb)
+                                }
+                                // A fresh window can only be used up already by an absolute
+                                // deadline the backoff would run past, so the retry could never
+                                // be dispatched
+                                Err(timed_out) => {
+                                    debug!(run_id = %info.la_info.workflow_exec_info.run_id,
+                                           seq_num = %info.la_info.schedule_cmd.seq,
+                                           "Local activity deadline passes before its retry, \
+                                            resolving it as timed out");
+                                    resolution.result = timed_out.result;
+                                    resolution.effective_timeouts = timed_out.effective_timeouts;
+                                    return LACompleteAction::Report {
+                                        run_id: info.la_info.workflow_exec_info.run_id,
+                                        resolution,
+                                        task,
+                                    };
+                                }
+                            }
+                        } else {
+                            maybe_old_lai
+                                .as_mut()
+                                .and_then(|old| old.timeout_bag.take())
+                        };
                         // Immediately create a new task token for the to-be-retried LA
                         let tt = dlock.gen_next_token();
-                        // Send the retry request after waiting the backoff duration
-                        let send_chan = self.act_req_tx.clone();
-                        let jh = tokio::spawn(async move {
-                            tokio::time::sleep(backoff_dur).await;
-
-                            send_chan
-                                .send(NewOrRetry::Retry {
-                                    in_flight: info.la_info,
-                                    attempt: info.attempt + 1,
-                                })
-                                .expect("Receive half of LA request channel cannot be dropped");
-                        });
+                        let dat = &mut **dlock;
+                        if !self.opts.strict_attempt_order {
+                            dat.superseded_tokens
+                                .retain(|_, (id, _)| dat.la_info.contains_key(id));
+                        }
+                        dat.superseded_tokens
+                            .insert(task_token.clone(), (exec_id.clone(), info.attempt));
+                        let backing_off = self.schedule_retry(
+                            dlock,
+                            &exec_id,
+                            &tt,
+                            PendingRetry {
+                                in_flight: info.la_info,
+                                failed_attempt: info.attempt,
+                                heartbeat_details: info.last_heartbeat_details,
+                                reset_schedule_time,
+                            },
+                            &resolution.result,
+                            backoff_dur,
+                        );
                         dlock.la_info.insert(
                             exec_id,
                             LocalActivityInfo {
                                 task_token: tt,
-                                backing_off_task: Some(jh),
+                                backing_off_task: Some(backing_off),
                                 first_wft_has_ended: maybe_old_lai
                                     .as_ref()
                                     .map(|old| old.first_wft_has_ended)
                                     .unwrap_or_default(),
+                                retries_disabled: false,
+                                attempt_aborted: false,
+                                cancel_requested: false,
                                 attempts_in_wft: maybe_old_lai
                                     .as_ref()
                                     .map(|old| old.attempts_in_wft + 1)
                                     .unwrap_or(1),
-                                timeout_bag: maybe_old_lai.and_then(|old| old.timeout_bag),
+                                local_retries: maybe_old_lai
+                                    .as_ref()
+                                    .map(|old| old.local_retries + 1)
+                                    .unwrap_or(1),
+                                correlation_id: resolution.correlation_id.clone(),
+                                report_timeouts: maybe_old_lai
+                                    .as_ref()
+                                    .is_none_or(|old| old.report_timeouts),
+                                timeout_bag,
                             },
                         );
                         LACompleteAction::WillBeRetried(task)
                     } else {
+                        self.lifetime_counters
+                            .failed_terminal
+                            .fetch_add(1, Ordering::Relaxed);
                         LACompleteAction::Report {
                             run_id: info.la_info.workflow_exec_info.run_id,
                             resolution,
@@ -709,7 +3182,15 @@ impl LocalActivityManager {
                     }
                 }
                 Outcome::JustReport => {
-                    self.complete_notify.notify_one();
+                    if matches!(
+                        resolution.result,
+                        LocalActivityExecutionResult::Failed(_)
+                            | LocalActivityExecutionResult::TimedOut(_)
+                    ) {
+                        self.lifetime_counters
+                            .failed_terminal
+                            .fetch_add(1, Ordering::Relaxed);
+                    }
                     LACompleteAction::Report {
                         run_id: info.la_info.workflow_exec_info.run_id,
                         resolution,
@@ -717,6 +3198,24 @@ impl LocalActivityManager {
                     }
                 }
             }
+        } else if let Some((id, attempt)) = dlock.superseded_tokens.get(task_token) {
+            warn!(id=?id, attempt, "Tried to complete local activity with the token of an attempt \
+                                    which has already been retried");
+            LACompleteAction::StaleToken(id.clone())
+        } else if !dlock.was_issued(task_token) {
+            match self.opts.unknown_token_policy {
+                UnknownTokenPolicy::IgnoreUnknown => LACompleteAction::Untracked,
+                UnknownTokenPolicy::LogUnknown => {
+                    warn!(task_token=%task_token,
+                          "Tried to complete local activity with a token that was never issued");
+                    LACompleteAction::Untracked
+                }
+                UnknownTokenPolicy::ErrorUnknown => {
+                    error!(task_token=%task_token,
+                           "Tried to complete local activity with a token that was never issued");
+                    LACompleteAction::UnknownToken(task_token.clone())
+                }
+            }
         } else {
             if !matches!(
                 status,
@@ -729,17 +3228,203 @@ impl LocalActivityManager {
         }
     }
 
+    /// Cancels only the currently executing attempt of the activity. When lang completes it as
+    /// cancelled, that is treated as a retryable failure, so the retry policy decides whether there
+    /// is another attempt. Useful for attempt-level timeouts managed outside of core. Returns false
+    /// if there is no executing attempt to abort.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn abort_attempt(&self, id: &ExecutingLAId) -> bool {
+        let mut dlock = self.dat.lock();
+        let dat = &mut *dlock;
+        let Some(lai) = dat.la_info.get_mut(id) else {
+            return false;
+        };
+        if !dat.outstanding_activity_tasks.contains_key(&lai.task_token) {
+            return false;
+        }
+        debug!(id=?id, "Aborting current local activity attempt");
+        lai.attempt_aborted = true;
+        self.send_cancel(&lai.task_token, ActivityCancelReason::Cancelled);
+        true
+    }
+
+    /// Cancels the activity and waits for it to be resolved, returning the resolution. Activities
+    /// which are backing off resolve immediately. Returns `None` if the activity isn't known, or
+    /// stops being tracked without resolving (ex: its run is evicted).
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) async fn cancel_and_wait(
+        &self,
+        id: ExecutingLAId,
+    ) -> Option<LocalActivityResolution> {
+        let rx = {
+            // The waiter must be registered under the same lock as the cancel is issued, or the
+            // resolution could be produced before we start listening for it.
+            let mut dlock = self.dat.lock();
+            let lai = dlock.la_info.get_mut(&id)?;
+            if let Some(immediate) =
+                self.cancel_one_la(id.seq_num, lai, ActivityCancelReason::Cancelled)
+            {
+                dlock.la_info.remove(&id);
+                self.activity_resolved(&mut dlock, &id, &immediate);
+                return Some(immediate);
+            }
+            let (tx, rx) = oneshot::channel();
+            dlock.resolution_waiters.entry(id).or_default().push(tx);
+            rx
+        };
+        rx.await.ok()
+    }
+
+    /// Returns a future which resolves with the final resolution of the identified activity -
+    /// whether it completed, failed, was cancelled, or timed out. Retries aren't final, so the
+    /// future keeps waiting through them, including ones lang schedules after a timer backoff.
+    /// Resolves to `None` if the activity isn't known, or stops being tracked without resolving
+    /// (ex: its run is evicted).
+    ///
+    /// The waiter is registered when this is called, not when the future is first polled, so a
+    /// resolution produced in between is not missed.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn completion_future(
+        &self,
+        id: ExecutingLAId,
+    ) -> impl Future<Output = Option<LocalActivityResolution>> + use<> {
+        let rx = {
+            let mut dlock = self.dat.lock();
+            if dlock.la_info.contains_key(&id) {
+                let (tx, rx) = oneshot::channel();
+                dlock.resolution_waiters.entry(id).or_default().push(tx);
+                Some(rx)
+            } else {
+                None
+            }
+        };
+        async move { rx?.await.ok() }
+    }
+
+    /// Record heartbeat details for an outstanding local activity, so that they can be handed to
+    /// the next attempt should this one fail and be retried.
+    pub(crate) fn record_heartbeat(&self, task_token: &TaskToken, details: Vec<Payload>) {
+        if let Some(info) = self
+            .dat
+            .lock()
+            .outstanding_activity_tasks
+            .get_mut(task_token)
+        {
+            info.last_heartbeat_details = details;
+        } else {
+            debug!(task_token=%task_token, "Heartbeat for untracked local activity");
+        }
+    }
+
+    /// Reports intermediate output (ex: percent complete) from an executing activity, so it can be
+    /// passed on before the activity completes. Progress from one attempt is delivered in the order
+    /// it was recorded, and always before that attempt's completion is processed.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn record_progress(&self, task_token: &TaskToken, payload: Payload) {
+        let Some(tx) = self.opts.progress_tx.as_ref() else {
+            return;
+        };
+        let dlock = self.dat.lock();
+        let Some(info) = dlock.outstanding_activity_tasks.get(task_token) else {
+            debug!(task_token=%task_token, "Progress for untracked local activity");
+            return;
+        };
+        // Nobody listening any more is no reason to interfere with the activity
+        let _ = tx.send(LocalActivityProgress {
+            id: ExecutingLAId {
+                run_id: info.la_info.workflow_exec_info.run_id.clone(),
+                seq_num: info.la_info.schedule_cmd.seq,
+            },
+            attempt: info.attempt,
+            payload,
+        });
+    }
+
+    /// Like [Self::complete], but hands the resulting action to `notifier` and awaits it. Useful
+    /// for integrations which want completion and delivery of the result to the workflow machines
+    /// to be a single awaitable step.
+    pub(crate) async fn complete_and_notify<F, Fut>(
+        &self,
+        task_token: &TaskToken,
+        status: LocalActivityExecutionResult,
+        notifier: F,
+    ) -> Fut::Output
+    where
+        F: FnOnce(LACompleteAction) -> Fut,
+        Fut: Future,
+    {
+        let action = self.complete(task_token, status);
+        notifier(action).await
+    }
+
     pub(crate) fn workflows_have_shutdown(&self) {
         self.workflows_have_shut_down.cancel();
         self.set_shutdown_complete_if_ready(&mut self.dat.lock());
     }
 
-    pub(crate) async fn wait_all_outstanding_tasks_finished(&self) {
+    pub(crate) async fn wait_all_outstanding_tasks_finished(&self) -> ShutdownSummary {
+        self.drain_started.cancel();
+        for task in self.flush_cancels() {
+            // Only fails if the request stream is already gone, in which case lang is done polling
+            let _ = self.flushed_cancels_tx.send(task);
+        }
+        let aborted_backoffs = self.abort_all_backoffs();
+        let outstanding_at_start = {
+            let mut dlock = self.dat.lock();
+            dlock.aborted_backoffs.extend(aborted_backoffs);
+            dlock.outstanding_activity_tasks.len()
+        };
+        self.outstanding_count.send_replace(outstanding_at_start);
         while !self.set_shutdown_complete_if_ready(&mut self.dat.lock()) {
             self.complete_notify.notified().await;
         }
         // This makes sure we drop any permits that might be held inside the stream
         self.rcvs.lock().await.inner = stream::empty().boxed();
+        self.flush_state_store().await;
+        self.shutdown_summary()
+    }
+
+    /// What shutdown has waited on and aborted so far. Also available after
+    /// [Self::shutdown_with_timeout] gives up, in which case it covers what happened before then.
+    pub(crate) fn shutdown_summary(&self) -> ShutdownSummary {
+        let dlock = self.dat.lock();
+        ShutdownSummary {
+            completed_during_shutdown: dlock.finished_while_draining,
+            aborted_backoffs: dlock.aborted_backoffs.clone(),
+        }
+    }
+
+    /// Like [Self::wait_all_outstanding_tasks_finished], but gives up after `dur`. When it does,
+    /// the ids of the activities still outstanding are returned and shutdown is marked complete
+    /// regardless, so a hung activity can't hold up the rest of the worker. See
+    /// [Self::shutdown_summary] for what was waited on.
+    pub(crate) async fn shutdown_with_timeout(
+        &self,
+        dur: Duration,
+    ) -> Result<(), Vec<ExecutingLAId>> {
+        if tokio::time::timeout(dur, self.wait_all_outstanding_tasks_finished())
+            .await
+            .is_ok()
+        {
+            return Ok(());
+        }
+        let abandoned: Vec<_> = self
+            .dat
+            .lock()
+            .outstanding_activity_tasks
+            .values()
+            .map(|info| ExecutingLAId {
+                run_id: info.la_info.workflow_exec_info.run_id.clone(),
+                seq_num: info.la_info.schedule_cmd.seq,
+            })
+            .collect();
+        warn!(
+            ?abandoned,
+            "Local activities did not finish before shutdown timeout"
+        );
+        self.shutdown_complete_tok.cancel();
+        self.rcvs.lock().await.inner = stream::empty().boxed();
+        Err(abandoned)
     }
 
     /// Try to close the activity stream as soon as worker shutdown is initiated. This is required
@@ -759,6 +3444,195 @@ impl LocalActivityManager {
             .sum()
     }
 
+    /// Backing-off activities aren't outstanding, so they don't hold up shutdown, but their
+    /// retries would otherwise be sent into a request channel nobody is reading any more. They are
+    /// resolved as cancelled instead, and those resolutions are queued up for their workflows.
+    fn abort_all_backoffs(&self) -> Vec<LocalActivityResolution> {
+        let mut dlock = self.dat.lock();
+        let dat = &mut *dlock;
+        let backing_off: Vec<_> = dat
+            .la_info
+            .iter()
+            .filter(|(_, lai)| lai.backing_off_task.is_some())
+            .map(|(id, _)| id.clone())
+            .collect();
+        let mut resolutions = vec![];
+        for id in backing_off {
+            let Some(mut lai) = dat.la_info.remove(&id) else {
+                continue;
+            };
+            debug!(id=?id, "Aborting local activity backoff due to shutdown");
+            let Some(resolution) =
+                self.cancel_one_la(id.seq_num, &mut lai, ActivityCancelReason::WorkerShutdown)
+            else {
+                continue;
+            };
+            self.activity_resolved_until_restart(dat, &id, &resolution);
+            self.send_cancel_or_timeout(CancelOrTimeout::Resolved {
+                run_id: id.run_id,
+                resolution: resolution.clone(),
+            });
+            resolutions.push(resolution);
+        }
+        resolutions.sort_by_key(|r| r.seq);
+        resolutions
+    }
+
+    /// Immediately resolves every tracked activity as cancelled, whatever state it's in, and forgets
+    /// about all of them. Backoffs and timeout timers are aborted, and nothing waits on lang, which
+    /// may still be running some of them. Meant for when the worker hits a fatal error. Later
+    /// completions for any of these activities are treated as untracked. The resolutions are
+    /// returned alongside the run they belong to.
+    pub(crate) fn abort_all(&self) -> Vec<(String, LocalActivityResolution)> {
+        let mut dlock = self.dat.lock();
+        let dat = &mut *dlock;
+        // Nothing will get a turn any more, and this keeps resolving from advancing them
+        dat.serialized_runs.clear();
+        let mut outstanding = std::mem::take(&mut dat.outstanding_activity_tasks);
+        let queued_attempts: HashMap<_, _> =
+            std::mem::replace(&mut dat.queued_new, LruCache::unbounded())
+                .into_iter()
+                .map(|(id, q)| (id, q.attempt))
+                .collect();
+        let mut resolutions = vec![];
+        for (id, lai) in std::mem::take(&mut dat.la_info) {
+            let in_flight = outstanding.remove(&lai.task_token);
+            let backoff_attempt = lai.backing_off_task.as_ref().map(|b| {
+                b.handle.abort();
+                b.failed_attempt
+            });
+            let resolution = LocalActivityResolution {
+                seq: id.seq_num,
+                result: self.empty_cancel(),
+                runtime: in_flight
+                    .as_ref()
+                    .map(|info| info.dispatch_time.elapsed())
+                    .unwrap_or_default(),
+                attempt: in_flight
+                    .as_ref()
+                    .map(|info| info.attempt)
+                    .or(backoff_attempt)
+                    .or_else(|| queued_attempts.get(&id).copied())
+                    .unwrap_or_default()
+                    .max(1),
+                backoff: None,
+                original_schedule_time: in_flight
+                    .as_ref()
+                    .and_then(|info| info.la_info.schedule_cmd.original_schedule_time),
+                correlation_id: lai.correlation_id.clone(),
+                effective_timeouts: lai
+                    .timeout_bag
+                    .as_ref()
+                    .map(TimeoutBag::effective)
+                    .unwrap_or_default(),
+            };
+            // Dropping the info aborts its timeout timers
+            drop(lai);
+            self.activity_resolved(dat, &id, &resolution);
+            resolutions.push((id.run_id, resolution));
+        }
+        dat.executing_per_limited_type.clear();
+        dat.parked_per_limited_type.clear();
+        dat.superseded_tokens.clear();
+        dat.run_metrics.clear();
+        self.queued_cancels.lock().clear();
+        for (_, (_, watcher)) in dat.run_tokens.drain() {
+            watcher.abort();
+        }
+        warn!(count = resolutions.len(), "Aborted all local activities");
+        self.outstanding_changed(dat);
+        self.set_shutdown_complete_if_ready(&mut dlock);
+        resolutions.sort_by(|(a_run, a), (b_run, b)| (a_run, a.seq).cmp(&(b_run, b.seq)));
+        resolutions
+    }
+
+    /// Returns the request back if it may be dispatched now, otherwise holds on to it until an
+    /// activity of the same type completes.
+    fn park_if_type_at_capacity(&self, req: NewOrRetry) -> Option<NewOrRetry> {
+        let act_type = &req.la().schedule_cmd.activity_type;
+        let Some(&limit) = self.opts.max_concurrent_per_type.get(act_type) else {
+            return Some(req);
+        };
+        let mut dlock = self.dat.lock();
+        let executing = dlock
+            .executing_per_limited_type
+            .get(act_type)
+            .copied()
+            .unwrap_or_default();
+        if executing < limit {
+            return Some(req);
+        }
+        debug!(activity_type=%act_type, "Local activity type at concurrency limit, parking");
+        dlock
+            .parked_per_limited_type
+            .entry(act_type.clone())
+            .or_default()
+            .push_back(req);
+        None
+    }
+
+    fn release_type_capacity(&self, dlock: &mut MutexGuard<LAMData>, act_type: &str) {
+        if let Some(executing) = dlock.executing_per_limited_type.get_mut(act_type) {
+            *executing = executing.saturating_sub(1);
+            if let Some(parked) = dlock
+                .parked_per_limited_type
+                .get_mut(act_type)
+                .and_then(|q| q.pop_front())
+            {
+                self.req_tx.send(parked);
+            }
+        }
+    }
+
+    /// Cheaply checks whether there is anything at all for [Self::next_pending] to eventually
+    /// produce: activities executing, backing off, or waiting to be dispatched, or cancels and
+    /// timeouts waiting to be delivered. Requests still in the dispatch queue are counted even if
+    /// their activity has since been resolved, so this may briefly say there's work when there
+    /// isn't, but never the reverse.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn has_pending_work(&self) -> bool {
+        if self.req_tx.queued() > 0 || !self.cancels_req_rx.lock().is_empty() {
+            return true;
+        }
+        let dat = self.dat.lock();
+        !dat.outstanding_activity_tasks.is_empty()
+            || dat
+                .la_info
+                .values()
+                .any(|lai| lai.backing_off_task.is_some())
+            || dat.parked_per_limited_type.values().any(|q| !q.is_empty())
+            || dat
+                .serialized_runs
+                .values()
+                .any(|sr| !sr.waiting.is_empty())
+    }
+
+    /// Returns a receiver which always holds the number of outstanding activities, for showing
+    /// how far along draining is during shutdown
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn shutdown_progress(&self) -> watch::Receiver<usize> {
+        self.outstanding_count.subscribe()
+    }
+
+    /// Returns a receiver of every lifecycle event of every local activity from now on. Receivers
+    /// which fall too far behind miss the oldest events rather than slowing the manager down.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn subscribe_events(&self) -> broadcast::Receiver<LaEvent> {
+        self.events.subscribe()
+    }
+
+    fn emit(&self, event: LaEvent) {
+        // Nobody subscribing is fine
+        let _ = self.events.send(event);
+    }
+
+    /// Must be called whenever an activity stops being outstanding
+    fn outstanding_changed(&self, dat: &LAMData) {
+        self.complete_notify.notify_one();
+        self.outstanding_count
+            .send_replace(dat.outstanding_activity_tasks.len());
+    }
+
     fn set_shutdown_complete_if_ready(&self, dlock: &mut MutexGuard<LAMData>) -> bool {
         let nothing_outstanding = dlock.outstanding_activity_tasks.is_empty();
         if nothing_outstanding && self.workflows_have_shut_down.is_cancelled() {
@@ -767,36 +3641,177 @@ impl LocalActivityManager {
         nothing_outstanding
     }
 
+    fn cancel_all_in_run(
+        &self,
+        run_id: &str,
+        reason: ActivityCancelReason,
+    ) -> Vec<LocalActivityResolution> {
+        let mut dlock = self.dat.lock();
+        // Even if we've got 100k+ LAs this should only take a ms or two. Not worth
+        // adding another map to keep in sync.
+        let immediate: Vec<_> = dlock
+            .la_info
+            .iter_mut()
+            .filter(|(id, _)| id.run_id == run_id)
+            .filter_map(|(laid, lainf)| {
+                self.cancel_one_la(laid.seq_num, lainf, reason)
+                    .map(|r| (laid.clone(), r))
+            })
+            .collect();
+        immediate
+            .into_iter()
+            .map(|(id, resolution)| {
+                dlock.la_info.remove(&id);
+                self.activity_resolved(&mut dlock, &id, &resolution);
+                resolution
+            })
+            .collect()
+    }
+
+    fn cancel_by_id(&self, id: ExecutingLAId) -> Option<LocalActivityResolution> {
+        debug!(id=?id, "Cancelling local activity");
+        let mut dlock = self.dat.lock();
+        let lai = dlock.la_info.get_mut(&id)?;
+        let immediate = self.cancel_one_la(id.seq_num, lai, ActivityCancelReason::Cancelled)?;
+        // Resolved without waiting on lang, so nothing else will stop tracking it
+        dlock.la_info.remove(&id);
+        self.activity_resolved(&mut dlock, &id, &immediate);
+        Some(immediate)
+    }
+
+    /// Cancels the activity whose current attempt was dispatched with the given token, for callers
+    /// which kept the token rather than the activity's id. The cancel reaches lang through
+    /// [Self::next_pending] as usual, as does the resolution if the activity turns out to be
+    /// backing off by then. Returns false if no executing attempt has the token.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn cancel_by_token(&self, task_token: &TaskToken) -> bool {
+        let id = self
+            .dat
+            .lock()
+            .outstanding_activity_tasks
+            .get(task_token)
+            .map(|info| ExecutingLAId {
+                run_id: info.la_info.workflow_exec_info.run_id.clone(),
+                seq_num: info.la_info.schedule_cmd.seq,
+            });
+        let Some(id) = id else {
+            return false;
+        };
+        // The attempt may have finished and gone into a backoff since the lookup, in which case
+        // the cancel resolves it right away and the workflow has to be told through the queue
+        let run_id = id.run_id.clone();
+        if let Some(resolution) = self.cancel_by_id(id) {
+            self.send_cancel_or_timeout(CancelOrTimeout::Resolved { run_id, resolution });
+        }
+        true
+    }
+
+    /// A cancellation carrying [LocalActivityManagerOptions::default_cancel_details], if any
+    fn empty_cancel(&self) -> LocalActivityExecutionResult {
+        LocalActivityExecutionResult::Cancelled(Cancellation::from_details(
+            self.opts.default_cancel_details.clone(),
+        ))
+    }
+
     fn cancel_one_la(
         &self,
         seq: u32,
         lai: &mut LocalActivityInfo,
+        reason: ActivityCancelReason,
     ) -> Option<LocalActivityResolution> {
+        if reason == ActivityCancelReason::WorkflowCompleted {
+            lai.retries_disabled = true;
+        }
         // First check if this ID is currently backing off, if so abort the backoff
         // task
-        if let Some(t) = lai.backing_off_task.take() {
-            t.abort();
+        if let Some(b) = lai.backing_off_task.take() {
+            b.handle.abort();
             return Some(LocalActivityResolution {
                 seq,
-                result: LocalActivityExecutionResult::Cancelled(Cancellation::from_details(None)),
+                result: self.cancelled_during_backoff(),
                 runtime: Duration::from_secs(0),
-                attempt: 0,
+                attempt: b.failed_attempt,
                 backoff: None,
                 original_schedule_time: None,
+                correlation_id: lai.correlation_id.clone(),
+                effective_timeouts: lai
+                    .timeout_bag
+                    .as_ref()
+                    .map(TimeoutBag::effective)
+                    .unwrap_or_default(),
             });
         }
 
-        self.cancels_req_tx
-            .send(CancelOrTimeout::Cancel(ActivityTask::cancel_from_ids(
-                lai.task_token.0.clone(),
-                ActivityCancelReason::Cancelled,
-                ActivityTask::primary_reason_to_cancellation_details(
-                    ActivityCancelReason::Cancelled,
-                ),
-            )))
-            .expect("Receive half of LA cancel channel cannot be dropped");
+        lai.cancel_requested = true;
+        self.send_cancel(&lai.task_token, reason);
         None
     }
+
+    /// What a final timeout becomes when timeouts aren't reported to the workflow. The workflow
+    /// still has to be told the activity is over, or it would wait on it forever, so it is
+    /// resolved as cancelled, with the timeout kept as the cause.
+    fn unreported_timeout(
+        &self,
+        result: LocalActivityExecutionResult,
+    ) -> LocalActivityExecutionResult {
+        let LocalActivityExecutionResult::TimedOut(timeout) = result else {
+            return result;
+        };
+        let mut cancel = Cancellation::from_details(self.opts.default_cancel_details.clone());
+        if let Some(failure) = cancel.failure.as_mut() {
+            failure.cause = timeout.failure.map(Box::new);
+        }
+        LocalActivityExecutionResult::Cancelled(cancel)
+    }
+
+    /// What an activity resolves with when it is cancelled while waiting to retry
+    fn cancelled_during_backoff(&self) -> LocalActivityExecutionResult {
+        let details = CANCELLED_DURING_BACKOFF_DETAILS
+            .as_json_payload()
+            .expect("Serializing a string cannot fail");
+        LocalActivityExecutionResult::Cancelled(Cancellation::from_details(Some(details)))
+    }
+
+    /// Queues a cancel of the attempt with the given token to be handed to lang
+    fn send_cancel(&self, task_token: &TaskToken, reason: ActivityCancelReason) {
+        self.queued_cancels.lock().insert(task_token.clone());
+        if !self.send_cancel_or_timeout(CancelOrTimeout::Cancel(ActivityTask::cancel_from_ids(
+            task_token.0.clone(),
+            reason,
+            ActivityTask::primary_reason_to_cancellation_details(reason),
+        ))) {
+            self.queued_cancels.lock().remove(task_token);
+        }
+    }
+
+    /// Queues something for [LocalActivityManager::next_pending] to handle on the cancel channel.
+    /// Returns false if the channel is closed, which only happens while the manager is being torn
+    /// down, in which case it's dropped.
+    fn send_cancel_or_timeout(&self, msg: CancelOrTimeout) -> bool {
+        let sent = self.cancels_req_tx.send(msg).is_ok();
+        if !sent {
+            warn!("Dropping local activity cancel or timeout, since the cancel channel is closed");
+        }
+        sent
+    }
+
+    /// Returns the activities which have a cancel waiting to be taken by
+    /// [LocalActivityManager::next_pending]. Cancels for activities which have since stopped being
+    /// tracked are left out, since they will be dropped rather than dispatched. Useful for figuring
+    /// out why a cancellation seems stuck.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn pending_cancels(&self) -> Vec<ExecutingLAId> {
+        let dlock = self.dat.lock();
+        let queued = self.queued_cancels.lock();
+        let mut ids: Vec<_> = dlock
+            .la_info
+            .iter()
+            .filter(|(_, lai)| queued.contains(&lai.task_token))
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.sort_by(|a, b| (&a.run_id, a.seq_num).cmp(&(&b.run_id, b.seq_num)));
+        ids
+    }
 }
 
 #[derive(Debug)]
@@ -816,6 +3831,12 @@ pub(crate) enum LACompleteAction {
     WillBeRetried(Option<ActivityTask>),
     /// The activity was unknown
     Untracked,
+    /// The token belonged to an earlier attempt of this activity, which has since been replaced
+    /// by a retry. The result is discarded.
+    StaleToken(ExecutingLAId),
+    /// The token was never issued by this manager, and [UnknownTokenPolicy::ErrorUnknown] is in
+    /// effect. The worker should treat this as fatal.
+    UnknownToken(TaskToken),
 }
 
 #[derive(Debug)]
@@ -824,9 +3845,21 @@ enum NewOrRetry {
     Retry {
         in_flight: NewLocalAct,
         attempt: u32,
+        heartbeat_details: Vec<Payload>,
+        /// When the backoff for this attempt elapsed
+        attempt_scheduled_time: SystemTime,
     },
 }
 
+impl NewOrRetry {
+    fn la(&self) -> &NewLocalAct {
+        match self {
+            NewOrRetry::New(la) => la,
+            NewOrRetry::Retry { in_flight, .. } => in_flight,
+        }
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone)]
 enum CancelOrTimeout {
@@ -835,12 +3868,65 @@ enum CancelOrTimeout {
         run_id: String,
         resolution: LocalActivityResolution,
     },
+    /// Lang was sent a cancel for this task and may not have completed it yet
+    CancelAckTimeout(TaskToken),
+    /// The cancellation token for this run was cancelled
+    #[cfg(any(feature = "test-utilities", test))]
+    RunCancelled(String),
+    /// An activity which was already resolved, and only needs reporting to its workflow
+    Resolved {
+        run_id: String,
+        resolution: LocalActivityResolution,
+    },
+    /// The activity's schedule-to-close timeout fires soon. See
+    /// [LocalActivityManagerOptions::deadline_warning_lead].
+    DeadlineApproaching(ExecutingLAId),
+}
+
+type LAPermit = OwnedMeteredSemPermit<LocalActivitySlotKind>;
+
+/// Sends requests to the dispatch queue, keeping count of how many are in it. The channels can't
+/// say so themselves, since their receivers live inside the request stream.
+#[derive(Clone)]
+struct RequestSender {
+    chan: UnboundedSender<NewOrRetry>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl RequestSender {
+    /// Like [Self::try_send], but only logs if the queue is gone, which only happens while the
+    /// manager is being torn down. The request is dropped.
+    fn send(&self, req: NewOrRetry) {
+        if !self.try_send(req) {
+            warn!("Dropping local activity request, since the request queue is closed");
+        }
+    }
+
+    /// Returns false if the request couldn't be sent because the queue is gone
+    fn try_send(&self, req: NewOrRetry) -> bool {
+        let sent = self.chan.send(req).is_ok();
+        if sent {
+            self.queued.fetch_add(1, Ordering::AcqRel);
+        }
+        sent
+    }
+
+    /// Must be called for every request taken out of the queue
+    fn received(&self) {
+        self.queued.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    fn queued(&self) -> usize {
+        self.queued.load(Ordering::Acquire)
+    }
 }
 
 #[allow(clippy::large_enum_variant)]
 enum NewOrCancel {
-    New(NewOrRetry, OwnedMeteredSemPermit<LocalActivitySlotKind>),
+    New(NewOrRetry, LAPermit),
     Cancel(CancelOrTimeout),
+    /// Already checked to be dispatchable by [LocalActivityManager::flush_cancels]
+    FlushedCancel(ActivityTask),
 }
 
 #[pin_project::pin_project]
@@ -853,21 +3939,52 @@ impl RcvChans {
     fn new(
         new_reqs: UnboundedReceiver<NewOrRetry>,
         new_sem: MeteredPermitDealer<LocalActivitySlotKind>,
-        cancels: UnboundedReceiver<CancelOrTimeout>,
+        cancels: Arc<Mutex<UnboundedReceiver<CancelOrTimeout>>>,
+        flushed_cancels: UnboundedReceiver<ActivityTask>,
         shutdown_completed: CancellationToken,
+        metrics: MetricsContext,
+        permit_waiters: Arc<AtomicUsize>,
     ) -> Self {
-        let cancel_stream = UnboundedReceiverStream::new(cancels).map(NewOrCancel::Cancel);
+        let cancel_stream =
+            stream::poll_fn(move |cx| cancels.lock().poll_recv(cx)).map(NewOrCancel::Cancel);
+        // Since permits are acquired eagerly, someone is waiting on one any time slots are full,
+        // even with nothing queued. Counting requests taken off the queue until they're paired
+        // with a permit instead is what tells "no slots for queued work" apart from "no work".
+        let waiters_c = permit_waiters.clone();
+        let metrics_c = metrics.clone();
         let new_stream = UnboundedReceiverStream::new(new_reqs)
+            .map(move |req| {
+                let waiting = waiters_c.fetch_add(1, Ordering::AcqRel) + 1;
+                guard_metrics(|| metrics_c.la_permit_waiters(waiting));
+                // Tokio's, so paused time in tests applies to it
+                (req, tokio::time::Instant::now())
+            })
             // Get a permit for each new activity request
             .zip(stream::unfold(new_sem, |new_sem| async move {
                 let permit = new_sem.acquire_owned().await;
-                Some((permit, new_sem))
+                Some(((permit, tokio::time::Instant::now()), new_sem))
             }))
-            .map(|(req, permit)| NewOrCancel::New(req, permit));
+            .map(move |((req, received_at), (permit, acquired_at))| {
+                let waiting = permit_waiters.fetch_sub(1, Ordering::AcqRel) - 1;
+                guard_metrics(|| {
+                    metrics.la_permit_waiters(waiting);
+                    // Permits are acquired eagerly, so one that was ready before the request
+                    // arrived counts as no wait at all.
+                    metrics
+                        .la_permit_wait_latency(acquired_at.saturating_duration_since(received_at));
+                });
+                NewOrCancel::New(req, permit)
+            });
+        let flushed_stream =
+            UnboundedReceiverStream::new(flushed_cancels).map(NewOrCancel::FlushedCancel);
         Self {
-            inner: tokio_stream::StreamExt::merge(cancel_stream, new_stream)
-                .take_until(async move { shutdown_completed.cancelled().await })
-                .boxed(),
+            inner: stream::select_with_strategy(
+                flushed_stream,
+                tokio_stream::StreamExt::merge(cancel_stream, new_stream),
+                |_: &mut ()| PollNext::Left,
+            )
+            .take_until(async move { shutdown_completed.cancelled().await })
+            .boxed(),
         }
     }
 }
@@ -880,108 +3997,40 @@ impl Stream for RcvChans {
     }
 }
 
-struct TimeoutBag {
-    sched_to_close_handle: JoinHandle<()>,
-    start_to_close_dur_and_dat: Option<(Duration, CancelOrTimeout)>,
-    start_to_close_handle: Option<JoinHandle<()>>,
-    cancel_chan: UnboundedSender<CancelOrTimeout>,
-}
-
-impl TimeoutBag {
-    /// Create new timeout tasks for the provided local activity. This must be called as soon
-    /// as request to schedule it arrives.
-    ///
-    /// Returns error in the event the activity is *already* timed out
-    #[allow(clippy::result_large_err)]
-    fn new(
-        new_la: &NewLocalAct,
-        cancel_chan: UnboundedSender<CancelOrTimeout>,
-    ) -> Result<TimeoutBag, LocalActivityResolution> {
-        let (schedule_to_close, start_to_close) =
-            new_la.schedule_cmd.close_timeouts.into_sched_and_start();
-
-        let sched_time = new_la
-            .schedule_cmd
-            .original_schedule_time
-            .unwrap_or(new_la.schedule_time);
-        let resolution = LocalActivityResolution {
-            seq: new_la.schedule_cmd.seq,
-            result: LocalActivityExecutionResult::timeout(TimeoutType::ScheduleToClose),
-            runtime: Default::default(),
-            attempt: new_la.schedule_cmd.attempt,
-            backoff: None,
-            original_schedule_time: new_la.schedule_cmd.original_schedule_time,
-        };
-        // Remove any time already elapsed since the scheduling time
-        let schedule_to_close = schedule_to_close
-            .map(|s2c| s2c.saturating_sub(sched_time.elapsed().unwrap_or_default()));
-        if let Some(ref s2c) = schedule_to_close
-            && s2c.is_zero()
-        {
-            return Err(resolution);
-        }
-        let timeout_dat = CancelOrTimeout::Timeout {
-            run_id: new_la.workflow_exec_info.run_id.clone(),
-            resolution,
-        };
-        let start_to_close_dur_and_dat = start_to_close.map(|d| (d, timeout_dat.clone()));
-        let fut_dat = schedule_to_close.map(|s2c| (s2c, timeout_dat));
-
-        let cancel_chan_clone = cancel_chan.clone();
-        let scheduling = tokio::spawn(async move {
-            if let Some((timeout, dat)) = fut_dat {
-                sleep(timeout).await;
-                cancel_chan_clone
-                    .send(dat)
-                    .expect("receive half not dropped");
-            }
-        });
-        Ok(TimeoutBag {
-            sched_to_close_handle: scheduling,
-            start_to_close_dur_and_dat,
-            start_to_close_handle: None,
-            cancel_chan,
-        })
-    }
-
-    /// Must be called once the associated local activity has been started / dispatched to lang.
-    fn mark_started(&mut self) {
-        if let Some((start_to_close, mut dat)) = self.start_to_close_dur_and_dat.as_ref().cloned() {
-            let started_t = Instant::now();
-            let cchan = self.cancel_chan.clone();
-            self.start_to_close_handle = Some(tokio::spawn(async move {
-                sleep(start_to_close).await;
-                if let CancelOrTimeout::Timeout { resolution, .. } = &mut dat {
-                    resolution.result =
-                        LocalActivityExecutionResult::timeout(TimeoutType::StartToClose);
-                    resolution.runtime = started_t.elapsed();
-                }
-
-                cchan.send(dat).expect("receive half not dropped");
-            }));
-        }
-    }
-}
-
-impl Drop for TimeoutBag {
-    fn drop(&mut self) {
-        self.sched_to_close_handle.abort();
-        if let Some(x) = self.start_to_close_handle.as_ref() {
-            x.abort()
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use crate::{prost_dur, protosext::LACloseTimeouts, retry_logic::ValidatedRetryPolicy};
+    use super::{
+        retry::{MAX_PROTO_DURATION, backoff_as_proto},
+        *,
+    };
+    use crate::{prost_dur, retry_logic::ValidatedRetryPolicy, telemetry::MetricsCallBuffer};
     use futures_util::FutureExt;
-    use temporalio_common::protos::temporal::api::{
-        common::v1::RetryPolicy,
-        failure::v1::{ApplicationFailureInfo, Failure, failure::FailureInfo},
+    use rstest::rstest;
+    use std::{collections::HashSet, sync::Arc};
+    use temporalio_common::{
+        protos::{
+            coresdk::{
+                FromJsonPayloadExt, activity_task::Cancel, workflow_commands::ScheduleLocalActivity,
+            },
+            temporal::api::{
+                common::v1::RetryPolicy,
+                failure::v1::{Failure, failure::FailureInfo},
+            },
+        },
+        telemetry::{
+            CoreTelemetry, Logger, TaskQueueLabelStrategy, TelemetryOptions,
+            construct_filter_string,
+            metrics::{
+                CoreMeter, Counter, CounterBase, Gauge, GaugeF64, Histogram, HistogramDuration,
+                HistogramDurationBase, HistogramF64, MetricAttributable, MetricAttributes,
+                MetricParameters, NewAttributes, NoOpCoreMeter, TemporalMeter,
+                core::{BufferInstrumentRef, MetricCallBufferer, MetricEvent, MetricUpdateVal},
+            },
+            telemetry_init,
+        },
     };
     use tokio::task::yield_now;
+    use tracing::Level;
 
     impl NextPendingLAAction {
         fn unwrap(self) -> ActivityTask {
@@ -1003,10 +4052,101 @@ mod tests {
         }
     }
 
+    /// An entry in a [LaTestHarness] log
+    #[derive(Debug)]
+    enum LaLogEntry {
+        /// Returned by [LocalActivityManager::next_pending]
+        Next(NextPendingLAAction),
+        /// Returned by [LocalActivityManager::complete]
+        Completed(LACompleteAction),
+    }
+
+    /// Drives [LocalActivityManager::next_pending] in the background, and records everything it
+    /// returns along with the outcome of every completion made through the harness. Lets tests
+    /// assert on the whole sequence of actions rather than racing calls against each other.
+    struct LaTestHarness {
+        lam: Arc<LocalActivityManager>,
+        log: Arc<Mutex<Vec<LaLogEntry>>>,
+        log_changed: Arc<Notify>,
+        driver: JoinHandle<()>,
+    }
+
+    impl LaTestHarness {
+        fn new(lam: LocalActivityManager) -> Self {
+            let lam = Arc::new(lam);
+            let log = Arc::new(Mutex::new(vec![]));
+            let log_changed = Arc::new(Notify::new());
+            let driver = tokio::spawn({
+                let (lam, log, log_changed) = (lam.clone(), log.clone(), log_changed.clone());
+                async move {
+                    while !lam.shutdown_complete_tok.is_cancelled() {
+                        if let Some(action) = lam.next_pending().await {
+                            log.lock().push(LaLogEntry::Next(action));
+                            log_changed.notify_waiters();
+                        }
+                    }
+                }
+            });
+            Self {
+                lam,
+                log,
+                log_changed,
+                driver,
+            }
+        }
+
+        /// Completes the activity and records the outcome. The log stays locked throughout, so
+        /// anything dispatched as a consequence is guaranteed to be recorded after it.
+        fn complete(&self, task_token: &TaskToken, status: LocalActivityExecutionResult) {
+            let mut log = self.log.lock();
+            log.push(LaLogEntry::Completed(self.lam.complete(task_token, status)));
+            drop(log);
+            self.log_changed.notify_waiters();
+        }
+
+        async fn wait_for_entries(&self, len: usize) {
+            loop {
+                let notified = self.log_changed.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                if self.log.lock().len() >= len {
+                    return;
+                }
+                notified.await;
+            }
+        }
+
+        fn log(&self) -> MutexGuard<'_, Vec<LaLogEntry>> {
+            self.log.lock()
+        }
+
+        /// Returns the dispatched task at the given position in the log
+        fn dispatched(&self, index: usize) -> ActivityTask {
+            assert_matches!(
+                &self.log()[index],
+                LaLogEntry::Next(NextPendingLAAction::Dispatch(t)) => t.clone()
+            )
+        }
+    }
+
+    impl std::ops::Deref for LaTestHarness {
+        type Target = LocalActivityManager;
+
+        fn deref(&self) -> &Self::Target {
+            &self.lam
+        }
+    }
+
+    impl Drop for LaTestHarness {
+        fn drop(&mut self) {
+            self.driver.abort();
+        }
+    }
+
     #[tokio::test]
     async fn max_concurrent_respected() {
-        let lam = LocalActivityManager::test(1);
-        lam.enqueue((1..=50).map(|i| {
+        let harness = LaTestHarness::new(LocalActivityManager::test(1));
+        harness.enqueue((1..=50).map(|i| {
             NewLocalAct {
                 schedule_cmd: ValidScheduleLA {
                     seq: i,
@@ -1016,30 +4156,32 @@ mod tests {
                 workflow_type: "".to_string(),
                 workflow_exec_info: Default::default(),
                 schedule_time: SystemTime::now(),
+                ..Default::default()
             }
             .into()
         }));
         for i in 1..=50 {
-            let next = lam.next_pending().await.unwrap().unwrap();
+            harness.wait_for_entries(2 * i - 1).await;
+            let next = harness.dispatched(2 * i - 2);
             assert_matches!(
                 next.variant.unwrap(),
                 activity_task::Variant::Start(Start {activity_id, ..})
                     if activity_id == i.to_string()
             );
-            let next_tt = TaskToken(next.task_token);
-            let complete_branch = async {
-                lam.complete(
-                    &next_tt,
-                    LocalActivityExecutionResult::Completed(Default::default()),
-                )
-            };
-            tokio::select! {
-                // Next call will not resolve until we complete the first
-                _ = lam.next_pending() => {
-                    panic!("Branch must not be selected")
-                }
-                _ = complete_branch => {}
-            }
+            harness.complete(
+                &TaskToken(next.task_token),
+                LocalActivityExecutionResult::Completed(Default::default()),
+            );
+        }
+        // Nothing was ever dispatched while another activity was still executing
+        for pair in harness.log().chunks(2) {
+            assert_matches!(
+                pair,
+                [
+                    LaLogEntry::Next(NextPendingLAAction::Dispatch(_)),
+                    LaLogEntry::Completed(LACompleteAction::Report { .. })
+                ]
+            );
         }
     }
 
@@ -1055,6 +4197,7 @@ mod tests {
             workflow_type: "".to_string(),
             workflow_exec_info: Default::default(),
             schedule_time: SystemTime::now(),
+            ..Default::default()
         }
         .into()]);
 
@@ -1077,8 +4220,8 @@ mod tests {
 
     #[tokio::test]
     async fn can_cancel_in_flight() {
-        let lam = LocalActivityManager::test(5);
-        lam.enqueue([NewLocalAct {
+        let harness = LaTestHarness::new(LocalActivityManager::test(5));
+        harness.enqueue([NewLocalAct {
             schedule_cmd: ValidScheduleLA {
                 seq: 1,
                 activity_id: 1.to_string(),
@@ -1090,16 +4233,35 @@ mod tests {
                 run_id: "run_id".to_string(),
             },
             schedule_time: SystemTime::now(),
+            ..Default::default()
         }
         .into()]);
-        lam.next_pending().await.unwrap().unwrap();
+        harness.wait_for_entries(1).await;
 
-        lam.enqueue([LocalActRequest::Cancel(ExecutingLAId {
+        harness.enqueue([LocalActRequest::Cancel(ExecutingLAId {
             run_id: "run_id".to_string(),
             seq_num: 1,
         })]);
-        let next = lam.next_pending().await.unwrap().unwrap();
-        assert_matches!(next.variant.unwrap(), activity_task::Variant::Cancel(_));
+        harness.wait_for_entries(2).await;
+        let cancel = harness.dispatched(1);
+        harness.complete(
+            &TaskToken(cancel.task_token),
+            LocalActivityExecutionResult::Cancelled(Cancellation::from_details(None)),
+        );
+        assert_matches!(
+            harness.log().as_slice(),
+            [
+                LaLogEntry::Next(NextPendingLAAction::Dispatch(ActivityTask {
+                    variant: Some(activity_task::Variant::Start(_)),
+                    ..
+                })),
+                LaLogEntry::Next(NextPendingLAAction::Dispatch(ActivityTask {
+                    variant: Some(activity_task::Variant::Cancel(_)),
+                    ..
+                })),
+                LaLogEntry::Completed(LACompleteAction::Report { resolution, .. })
+            ] if matches!(resolution.result, LocalActivityExecutionResult::Cancelled(_))
+        );
     }
 
     #[tokio::test]
@@ -1123,6 +4285,7 @@ mod tests {
             workflow_type: "".to_string(),
             workflow_exec_info: Default::default(),
             schedule_time: SystemTime::now(),
+            ..Default::default()
         }
         .into()]);
 
@@ -1158,6 +4321,7 @@ mod tests {
             workflow_type: "".to_string(),
             workflow_exec_info: Default::default(),
             schedule_time: SystemTime::now(),
+            ..Default::default()
         }
         .into()]);
 
@@ -1205,6 +4369,7 @@ mod tests {
                 run_id: "run_id".to_string(),
             },
             schedule_time: SystemTime::now(),
+            ..Default::default()
         }
         .into()]);
 
@@ -1215,10 +4380,12 @@ mod tests {
             LocalActivityExecutionResult::Failed(Default::default()),
         );
         // Cancel the activity, which is performing local backoff
-        let immediate_res = lam.enqueue([LocalActRequest::Cancel(ExecutingLAId {
-            run_id: "run_id".to_string(),
-            seq_num: 1,
-        })]);
+        let immediate_res = lam
+            .enqueue([LocalActRequest::Cancel(ExecutingLAId {
+                run_id: "run_id".to_string(),
+                seq_num: 1,
+            })])
+            .immediate_resolutions;
         // It should not be present in the backoff tasks
         assert_eq!(lam.num_in_backoff(), 0);
         assert_eq!(lam.num_outstanding(), 0);
@@ -1228,6 +4395,23 @@ mod tests {
             immediate_res[0].result,
             LocalActivityExecutionResult::Cancelled { .. }
         );
+        // Which reports the attempt that actually ran and failed
+        assert_eq!(immediate_res[0].attempt, 5);
+        // And is distinguishable from cancelling a running attempt
+        let details = assert_matches!(
+            &immediate_res[0].result,
+            LocalActivityExecutionResult::Cancelled(Cancellation {
+                failure: Some(Failure {
+                    failure_info: Some(FailureInfo::CanceledFailureInfo(info)),
+                    ..
+                })
+            }) => info.details.as_ref().unwrap()
+        );
+        assert_eq!(
+            String::from_json_payload(&details.payloads[0]).unwrap(),
+            CANCELLED_DURING_BACKOFF_DETAILS
+        );
+        lam.drain_and_assert_clean().await;
     }
 
     #[tokio::test]
@@ -1252,6 +4436,7 @@ mod tests {
                 run_id: "run_id".to_string(),
             },
             schedule_time: SystemTime::now(),
+            ..Default::default()
         }
         .into()]);
 
@@ -1290,6 +4475,7 @@ mod tests {
                 run_id: "run_id".to_string(),
             },
             schedule_time: SystemTime::now(),
+            ..Default::default()
         }
         .into()]);
 
@@ -1299,6 +4485,7 @@ mod tests {
         assert!(lam.next_pending().await.unwrap().is_timeout(false));
         assert_eq!(lam.num_in_backoff(), 0);
         assert_eq!(lam.num_outstanding(), 0);
+        lam.drain_and_assert_clean().await;
     }
 
     #[rstest::rstest]
@@ -1325,7 +4512,7 @@ mod tests {
                     ..Default::default()
                 }),
                 local_retry_threshold: Duration::from_secs(500),
-                close_timeouts,
+                close_timeouts: Some(close_timeouts),
                 ..Default::default()
             },
             workflow_type: "".to_string(),
@@ -1334,6 +4521,7 @@ mod tests {
                 run_id: "run_id".to_string(),
             },
             schedule_time: SystemTime::now(),
+            ..Default::default()
         }
         .into()]);
 
@@ -1359,6 +4547,7 @@ mod tests {
         sleep(timeout + Duration::from_millis(10)).await;
         assert!(lam.next_pending().await.unwrap().is_timeout(true));
         assert_eq!(lam.num_outstanding(), 0);
+        lam.drain_and_assert_clean().await;
     }
 
     #[tokio::test]
@@ -1376,9 +4565,18 @@ mod tests {
                 run_id: "run_id".to_string(),
             },
             schedule_time: SystemTime::now(),
+            ..Default::default()
+        };
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
         };
         // Verify only one will get queued
-        lam.enqueue([new_la.clone().into(), new_la.clone().into()]);
+        let res = lam.enqueue([new_la.clone().into(), new_la.clone().into()]);
+        assert_eq!(
+            res.dropped,
+            vec![(id.clone(), EnqueueDropReason::AlreadyQueued)]
+        );
         lam.next_pending().await.unwrap().unwrap();
         assert_eq!(lam.num_outstanding(), 1);
         // There should be nothing else in the queue
@@ -1386,9 +4584,12 @@ mod tests {
 
         // Verify that if we now enqueue the same act again, after the task is outstanding, we still
         // don't add it.
-        lam.enqueue([new_la.into()]);
+        let res = lam.enqueue([new_la.into()]);
+        assert_eq!(res.dropped, vec![(id, EnqueueDropReason::AlreadyExecuting)]);
         assert_eq!(lam.num_outstanding(), 1);
         assert!(lam.rcvs.lock().await.next().now_or_never().is_none());
+        // The dropped duplicates never got a task token
+        assert_eq!(lam.dat.lock().next_tt_num, 1);
     }
 
     #[tokio::test]
@@ -1413,6 +4614,7 @@ mod tests {
                 run_id: run_id.to_string(),
             },
             schedule_time: SystemTime::now(),
+            ..Default::default()
         };
         lam.enqueue([new_la.clone().into()]);
         let spinfail = || async {
@@ -1444,4 +4646,5710 @@ mod tests {
             assert_eq!(count, 10);
         }
     }
+
+    #[tokio::test]
+    async fn shutdown_wait_woken_by_retrying_completion() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(10))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let next = lam.next_pending().await.unwrap().unwrap();
+        let tt = TaskToken(next.task_token);
+        lam.workflows_have_shutdown();
+        // The waiter is polled first so that it is already parked on the notify by the time the
+        // activity fails and goes into local backoff.
+        let (_, action) = tokio::time::timeout(
+            Duration::from_secs(5),
+            futures_util::future::join(
+                lam.wait_all_outstanding_tasks_finished(),
+                lam.complete_and_notify(
+                    &tt,
+                    LocalActivityExecutionResult::Failed(Default::default()),
+                    |action| async move { action },
+                ),
+            ),
+        )
+        .await
+        .expect("Shutdown wait must not hang while activity is backing off");
+        // Since shutdown was already draining, the retry is cancelled rather than backing off
+        let resolution = assert_matches!(
+            action,
+            LACompleteAction::Report { resolution, .. } => resolution
+        );
+        assert_matches!(
+            resolution.result,
+            LocalActivityExecutionResult::Cancelled(_)
+        );
+        assert_eq!(resolution.attempt, 1);
+        assert_eq!(lam.num_in_backoff(), 0);
+        lam.drain_and_assert_clean().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_aborts_backoffs_before_waiting() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(50))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let next = lam.next_pending().await.unwrap().unwrap();
+        let tt = TaskToken(next.task_token);
+        assert_matches!(
+            lam.complete(
+                &tt,
+                LocalActivityExecutionResult::Failed(Default::default()),
+            ),
+            LACompleteAction::WillBeRetried(_)
+        );
+        assert_eq!(lam.num_in_backoff(), 1);
+        let done = lam.completion_future(ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        });
+
+        lam.workflows_have_shutdown();
+        let summary = tokio::time::timeout(
+            Duration::from_secs(5),
+            lam.wait_all_outstanding_tasks_finished(),
+        )
+        .await
+        .expect("Shutdown must not hang while an activity is backing off");
+        assert_eq!(lam.num_in_backoff(), 0);
+        assert_eq!(lam.num_outstanding(), 0);
+        // The activity was resolved, not just forgotten
+        assert_matches!(
+            done.await,
+            Some(LocalActivityResolution {
+                result: LocalActivityExecutionResult::Cancelled(_),
+                ..
+            })
+        );
+        assert_matches!(
+            summary.aborted_backoffs.as_slice(),
+            [LocalActivityResolution {
+                seq: 1,
+                attempt: 1,
+                result: LocalActivityExecutionResult::Cancelled(_),
+                ..
+            }]
+        );
+        // Outlive the original backoff to make sure the retry never shows up
+        sleep(Duration::from_millis(100)).await;
+        assert!(lam.next_pending().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn shutdown_summary_counts_in_flight_and_backoffs() {
+        let lam = LocalActivityManager::test(5);
+        let new_la = |seq: u32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        initial_interval: Some(prost_dur!(from_secs(10))),
+                        backoff_coefficient: 1.0,
+                        ..Default::default()
+                    }),
+                    local_retry_threshold: Duration::from_secs(500),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        lam.enqueue((1..=4).map(new_la));
+        let mut tts = vec![];
+        for _ in 1..=4 {
+            let next = lam.next_pending().await.unwrap().unwrap();
+            tts.push(TaskToken(next.task_token));
+        }
+        assert_matches!(
+            lam.complete(
+                &tts[0],
+                LocalActivityExecutionResult::Failed(Default::default()),
+            ),
+            LACompleteAction::WillBeRetried(_)
+        );
+        lam.workflows_have_shutdown();
+
+        let (summary, _) = tokio::time::timeout(
+            Duration::from_secs(5),
+            futures_util::future::join(lam.wait_all_outstanding_tasks_finished(), async {
+                for tt in &tts[1..3] {
+                    lam.complete(
+                        tt,
+                        LocalActivityExecutionResult::Completed(Default::default()),
+                    );
+                }
+                // Abandoned rather than finished, so it isn't counted
+                lam.abort_all();
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.completed_during_shutdown, 2);
+        assert_matches!(
+            summary.aborted_backoffs.as_slice(),
+            [LocalActivityResolution { seq: 1, .. }]
+        );
+    }
+
+    #[tokio::test]
+    async fn shutdown_timeout_returns_hung_activities() {
+        let lam = LocalActivityManager::test(5);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        lam.next_pending().await.unwrap().unwrap();
+        lam.workflows_have_shutdown();
+
+        let abandoned = lam
+            .shutdown_with_timeout(Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            abandoned,
+            vec![ExecutingLAId {
+                run_id: "run_id".to_string(),
+                seq_num: 1,
+            }]
+        );
+        assert!(lam.shutdown_complete_tok.is_cancelled());
+        assert!(lam.next_pending().await.is_none());
+        // What was waited on is still available after giving up
+        assert_eq!(lam.shutdown_summary().completed_during_shutdown, 0);
+        assert!(lam.shutdown_summary().aborted_backoffs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn oversized_input_fails_immediately() {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                max_input_size: Some(10),
+                ..Default::default()
+            },
+        );
+        let immediate_res = lam
+            .enqueue([NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: 1,
+                    activity_id: 1.to_string(),
+                    arguments: vec![Payload {
+                        data: vec![0; 100],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: Default::default(),
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()])
+            .immediate_resolutions;
+        assert_eq!(immediate_res.len(), 1);
+        assert_matches!(
+            &immediate_res[0].result,
+            LocalActivityExecutionResult::Failed(ActFail { failure: Some(f) })
+                if f.message.contains("exceeds the maximum")
+        );
+        assert_eq!(lam.num_outstanding(), 0);
+        assert!(lam.rcvs.lock().await.next().now_or_never().is_none());
+    }
+
+    #[rstest]
+    #[case::coefficient_below_one(RetryPolicy {
+        backoff_coefficient: 0.5,
+        ..Default::default()
+    }, "backoff_coefficient")]
+    #[case::negative_initial_interval(RetryPolicy {
+        initial_interval: Some(prost_types::Duration { seconds: -1, nanos: 0 }),
+        ..Default::default()
+    }, "initial_interval must not be negative")]
+    #[case::negative_maximum_interval(RetryPolicy {
+        maximum_interval: Some(prost_types::Duration { seconds: -1, nanos: 0 }),
+        ..Default::default()
+    }, "maximum_interval must not be negative")]
+    #[case::maximum_below_initial(RetryPolicy {
+        initial_interval: Some(prost_dur!(from_secs(10))),
+        maximum_interval: Some(prost_dur!(from_secs(1))),
+        ..Default::default()
+    }, "must be >= initial_interval")]
+    #[tokio::test]
+    async fn malformed_retry_policy_fails_immediately(
+        #[case] retry_policy: RetryPolicy,
+        #[case] expected_msg: &str,
+    ) {
+        let lam = LocalActivityManager::test(1);
+        let schedule_cmd = ValidScheduleLA::from_schedule_la(
+            ScheduleLocalActivity {
+                seq: 1,
+                activity_id: "1".to_string(),
+                start_to_close_timeout: Some(prost_dur!(from_secs(10))),
+                retry_policy: Some(retry_policy),
+                ..Default::default()
+            },
+            None,
+        )
+        .unwrap();
+        let immediate_res = lam
+            .enqueue([NewLocalAct {
+                schedule_cmd,
+                workflow_type: "".to_string(),
+                workflow_exec_info: Default::default(),
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()])
+            .immediate_resolutions;
+        assert_eq!(immediate_res.len(), 1);
+        assert_matches!(
+            &immediate_res[0].result,
+            LocalActivityExecutionResult::Failed(ActFail { failure: Some(f) })
+                if f.message.contains(expected_msg)
+        );
+        assert_eq!(lam.num_outstanding(), 0);
+        assert!(lam.rcvs.lock().await.next().now_or_never().is_none());
+    }
+
+    #[tokio::test]
+    async fn unacknowledged_cancel_is_force_resolved() {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                cancel_ack_timeout: Some(Duration::from_millis(50)),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let start = lam.next_pending().await.unwrap().unwrap();
+        lam.enqueue([LocalActRequest::Cancel(ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        })]);
+        let cancel = lam.next_pending().await.unwrap().unwrap();
+        assert_eq!(cancel.task_token, start.task_token);
+        assert_matches!(cancel.variant, Some(activity_task::Variant::Cancel(_)));
+
+        // Lang never completes the activity
+        let forced = tokio::time::timeout(Duration::from_secs(5), lam.next_pending())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_matches!(
+            forced,
+            NextPendingLAAction::Autocomplete(LACompleteAction::Report {
+                resolution: LocalActivityResolution {
+                    seq: 1,
+                    result: LocalActivityExecutionResult::Cancelled(_),
+                    ..
+                },
+                ..
+            })
+        );
+        assert_eq!(lam.num_outstanding(), 0);
+        lam.drain_and_assert_clean().await;
+    }
+
+    #[tokio::test]
+    async fn retrying_failures_sent_to_side_channel() {
+        let (tx, mut rx) = unbounded_channel();
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                retrying_failures_tx: Some(tx),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(10))),
+                    backoff_coefficient: 1.0,
+                    maximum_attempts: 3,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let fail = |msg: &str| {
+            LocalActivityExecutionResult::Failed(ActFail {
+                failure: Some(Failure {
+                    message: msg.to_string(),
+                    ..Default::default()
+                }),
+            })
+        };
+        for attempt in 1..=2 {
+            let next = lam.next_pending().await.unwrap().unwrap();
+            assert_matches!(
+                lam.complete(&TaskToken(next.task_token), fail(&attempt.to_string())),
+                LACompleteAction::WillBeRetried(_)
+            );
+            let side = rx.try_recv().unwrap();
+            assert_eq!(side.id.seq_num, 1);
+            assert_eq!(side.attempt, attempt);
+            assert_eq!(side.backoff, Duration::from_millis(10));
+            assert_matches!(
+                side.result,
+                LocalActivityExecutionResult::Failed(ActFail { failure: Some(f) })
+                    if f.message == attempt.to_string()
+            );
+        }
+        // The final attempt is reported to the workflow as usual, and not sent to the side channel
+        let next = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            lam.complete(&TaskToken(next.task_token), fail("3")),
+            LACompleteAction::Report { .. }
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn completing_with_superseded_token_is_rejected_as_stale() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(10))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let first = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        lam.complete(
+            &first,
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        let second = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        assert_ne!(first, second);
+
+        assert_matches!(
+            lam.complete(
+                &first,
+                LocalActivityExecutionResult::Completed(Default::default()),
+            ),
+            LACompleteAction::StaleToken(ExecutingLAId { seq_num: 1, .. })
+        );
+        // The current attempt is unaffected
+        assert_eq!(lam.num_outstanding(), 1);
+        assert_matches!(
+            lam.complete(
+                &TaskToken(b"never issued".to_vec()),
+                LocalActivityExecutionResult::Completed(Default::default()),
+            ),
+            LACompleteAction::Untracked
+        );
+        assert_matches!(
+            lam.complete(
+                &second,
+                LocalActivityExecutionResult::Completed(Default::default()),
+            ),
+            LACompleteAction::Report { .. }
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::within_tolerance(Duration::from_secs(5), false)]
+    #[case::beyond_tolerance(Duration::from_secs(1), true)]
+    #[tokio::test]
+    async fn future_schedule_time_does_not_time_out_immediately(
+        #[case] tolerance: Duration,
+        #[case] expect_warning: bool,
+    ) {
+        let telem = telemetry_init(
+            TelemetryOptions::builder()
+                .logging(Logger::Forward {
+                    filter: construct_filter_string(Level::WARN, Level::WARN),
+                })
+                .build(),
+        )
+        .unwrap();
+        let _g = tracing::subscriber::set_default(telem.trace_subscriber().unwrap());
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                clock_skew_tolerance: Some(tolerance),
+                ..Default::default()
+            },
+        );
+        let immediate_res = lam
+            .enqueue([NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: 1,
+                    activity_id: 1.to_string(),
+                    close_timeouts: Some(LACloseTimeouts::ScheduleOnly(Duration::from_secs(10))),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now() + Duration::from_secs(2),
+                ..Default::default()
+            }
+            .into()])
+            .immediate_resolutions;
+        // The future schedule time is clamped to now either way, it's only logged when the skew is
+        // more than what's tolerated
+        assert!(immediate_res.is_empty());
+        let warned = telem
+            .fetch_buffered_logs()
+            .iter()
+            .any(|l| l.message.contains("clocks may be skewed"));
+        assert_eq!(warned, expect_warning);
+        let next = lam.next_pending().await.unwrap().unwrap();
+        let start = assert_matches!(next.variant, Some(activity_task::Variant::Start(s)) => s);
+        assert_eq!(
+            start.schedule_to_close_timeout,
+            Some(prost_dur!(from_secs(10)))
+        );
+    }
+
+    #[tokio::test]
+    async fn cancel_and_wait_resolves_in_flight_after_completion() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        lam.next_pending().await.unwrap().unwrap();
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        };
+
+        let lang = async {
+            let cancel = lam.next_pending().await.unwrap().unwrap();
+            assert_matches!(cancel.variant, Some(activity_task::Variant::Cancel(_)));
+            lam.complete(
+                &TaskToken(cancel.task_token),
+                LocalActivityExecutionResult::empty_cancel(),
+            );
+        };
+        let (resolution, _) = tokio::time::timeout(
+            Duration::from_secs(5),
+            futures_util::future::join(lam.cancel_and_wait(id), lang),
+        )
+        .await
+        .unwrap();
+        assert_matches!(
+            resolution,
+            Some(LocalActivityResolution {
+                seq: 1,
+                result: LocalActivityExecutionResult::Cancelled(_),
+                ..
+            })
+        );
+        assert_eq!(lam.num_outstanding(), 0);
+    }
+
+    #[tokio::test]
+    async fn cancel_and_wait_resolves_backing_off_immediately() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(100))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let next = lam.next_pending().await.unwrap().unwrap();
+        lam.complete(
+            &TaskToken(next.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        assert_eq!(lam.num_in_backoff(), 1);
+
+        let resolution = tokio::time::timeout(
+            Duration::from_secs(5),
+            lam.cancel_and_wait(ExecutingLAId {
+                run_id: "run_id".to_string(),
+                seq_num: 1,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_matches!(
+            resolution,
+            Some(LocalActivityResolution {
+                seq: 1,
+                attempt: 1,
+                result: LocalActivityExecutionResult::Cancelled(_),
+                ..
+            })
+        );
+        assert_eq!(lam.num_in_backoff(), 0);
+        lam.drain_and_assert_clean().await;
+    }
+
+    #[tokio::test]
+    async fn cancel_and_wait_resolves_when_shutdown_aborts_backoff() {
+        let lam = LocalActivityManager::test(1);
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        };
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(100))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+        .into()]);
+        let next = lam.next_pending().await.unwrap().unwrap();
+        let waiter = lam.cancel_and_wait(id.clone());
+        tokio::pin!(waiter);
+        assert!(waiter.as_mut().now_or_never().is_none());
+        // Lang ignores the cancel and fails the attempt, so it goes into backoff
+        lam.complete(
+            &TaskToken(next.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        assert_eq!(lam.num_in_backoff(), 1);
+        assert!(waiter.as_mut().now_or_never().is_none());
+
+        lam.workflows_have_shutdown();
+        lam.wait_all_outstanding_tasks_finished().await;
+        assert_matches!(
+            waiter.now_or_never(),
+            Some(Some(LocalActivityResolution {
+                seq: 1,
+                result: LocalActivityExecutionResult::Cancelled(_),
+                ..
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn timeout_info_reflects_scheduled_timeouts() {
+        let lam = LocalActivityManager::test(1);
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        };
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                close_timeouts: Some(LACloseTimeouts::Both {
+                    sched: Duration::from_secs(100),
+                    start: Duration::from_secs(10),
+                }),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        assert_eq!(
+            lam.timeout_info(&id),
+            Some(TimeoutInfo {
+                schedule_to_close: Some(Duration::from_secs(100)),
+                schedule_to_close_active: true,
+                start_to_close: Some(Duration::from_secs(10)),
+                start_to_close_active: false,
+            })
+        );
+        lam.next_pending().await.unwrap().unwrap();
+        assert_eq!(
+            lam.timeout_info(&id),
+            Some(TimeoutInfo {
+                schedule_to_close: Some(Duration::from_secs(100)),
+                schedule_to_close_active: true,
+                start_to_close: Some(Duration::from_secs(10)),
+                start_to_close_active: true,
+            })
+        );
+        assert_eq!(
+            lam.timeout_info(&ExecutingLAId {
+                run_id: "run_id".to_string(),
+                seq_num: 2,
+            }),
+            None
+        );
+    }
+
+    #[rstest]
+    #[case::unlimited(None)]
+    #[case::safety_valve(Some(3))]
+    #[tokio::test]
+    async fn unlimited_attempts_retry_locally_until_safety_valve(
+        #[case] max_local_retries: Option<u32>,
+    ) {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                max_local_retries,
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(1))),
+                    backoff_coefficient: 1.0,
+                    maximum_attempts: 0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        for attempt in 1..=6 {
+            let next = lam.next_pending().await.unwrap().unwrap();
+            let res = lam.complete(
+                &TaskToken(next.task_token),
+                LocalActivityExecutionResult::Failed(Default::default()),
+            );
+            if max_local_retries.is_some_and(|max| attempt > max) {
+                assert_matches!(
+                    res,
+                    LACompleteAction::Report {
+                        resolution: LocalActivityResolution {
+                            backoff: Some(_),
+                            ..
+                        },
+                        ..
+                    }
+                );
+                return;
+            }
+            assert_matches!(res, LACompleteAction::WillBeRetried(_));
+        }
+        assert!(max_local_retries.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn state_store_round_trips_across_restart() {
+        let store = Arc::new(InMemoryLaStateStore::default());
+        let opts = || LocalActivityManagerOptions {
+            state_store: Some(store.clone()),
+            ..Default::default()
+        };
+        let new_la = |seq: u32| NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq,
+                activity_id: seq.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(10))),
+                    backoff_coefficient: 1.0,
+                    maximum_interval: Some(prost_dur!(from_secs(10))),
+                    maximum_attempts: 10,
+                    non_retryable_error_types: vec![],
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                non_idempotent: seq == 5,
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        };
+        let id = |seq_num| ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num,
+        };
+        let started = |task: &ActivityTask| {
+            assert_matches!(
+                &task.variant,
+                Some(activity_task::Variant::Start(Start { activity_id, attempt, .. }))
+                    => (activity_id.clone(), *attempt)
+            )
+        };
+
+        let lam = LocalActivityManager::test_with_opts(5, opts());
+        lam.enqueue([1, 2, 4, 5].map(|seq| new_la(seq).into()));
+        let mut tokens = HashMap::new();
+        for _ in 0..4 {
+            let task = lam.next_pending().await.unwrap().unwrap();
+            tokens.insert(started(&task).0, TaskToken(task.task_token));
+        }
+        lam.complete(
+            &tokens["1"],
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        assert_matches!(
+            lam.complete(
+                &tokens["4"],
+                LocalActivityExecutionResult::Failed(Default::default()),
+            ),
+            LACompleteAction::WillBeRetried(_)
+        );
+        lam.enqueue([new_la(3).into()]);
+        lam.flush_state_store().await;
+        drop(lam);
+
+        let persisted = store.load();
+        let phases: HashMap<_, _> = persisted
+            .activities
+            .iter()
+            .map(|(id, la)| (id.seq_num, (la.attempt, la.phase)))
+            .collect();
+        assert_eq!(phases.len(), 4);
+        assert_eq!(phases[&2], (1, PersistedLaPhase::InFlight));
+        assert_eq!(phases[&3], (1, PersistedLaPhase::Queued));
+        assert_matches!(phases[&4], (1, PersistedLaPhase::BackingOff { .. }));
+        assert_eq!(phases[&5], (1, PersistedLaPhase::InFlight));
+
+        let restarted = LocalActivityManager::test_with_opts(5, opts());
+        let res = restarted.enqueue((2..=5).map(|seq| new_la(seq).into()));
+        // The interrupted attempt of a non-idempotent activity may have had its effects already
+        assert_eq!(res.immediate_resolutions.len(), 1);
+        assert_eq!(res.immediate_resolutions[0].seq, 5);
+        assert_matches!(
+            res.immediate_resolutions[0].result,
+            LocalActivityExecutionResult::Failed(_)
+        );
+        let mut attempts = HashMap::new();
+        let mut restarted_tokens = HashMap::new();
+        for _ in 0..3 {
+            let task = restarted.next_pending().await.unwrap().unwrap();
+            let (activity_id, attempt) = started(&task);
+            restarted_tokens.insert(activity_id.clone(), TaskToken(task.task_token));
+            attempts.insert(activity_id, attempt);
+        }
+        // The interrupted attempt counts, and the backoff carries on where it left off
+        assert_eq!(attempts["2"], 2);
+        assert_eq!(attempts["3"], 1);
+        assert_eq!(attempts["4"], 2);
+        // The queued activity keeps its token, but new ones must not collide with old ones
+        assert_eq!(
+            restarted_tokens["3"],
+            persisted.activities[&id(3)].task_token
+        );
+        for tt in [&restarted_tokens["2"], &restarted_tokens["4"]] {
+            assert!(!tokens.values().any(|old| old == tt));
+        }
+        for seq in 2..=4 {
+            restarted.complete(
+                &restarted_tokens[&seq.to_string()],
+                LocalActivityExecutionResult::Completed(Default::default()),
+            );
+        }
+        restarted.flush_state_store().await;
+        assert!(store.load().activities.is_empty());
+    }
+
+    #[tokio::test]
+    async fn timeout_racing_completion_emits_no_cancel() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(100))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                close_timeouts: Some(LACloseTimeouts::StartOnly(Duration::from_secs(1000))),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let next = lam.next_pending().await.unwrap().unwrap();
+        // The start-to-close timeout fires, but the attempt fails before it's handled
+        lam.force_timeouts();
+        assert_matches!(
+            lam.complete(
+                &TaskToken(next.task_token),
+                LocalActivityExecutionResult::Failed(Default::default()),
+            ),
+            LACompleteAction::WillBeRetried(None)
+        );
+        assert!(lam.next_pending().await.is_none());
+        assert_eq!(lam.num_in_backoff(), 1);
+        assert_matches!(
+            lam.timeout_info(&ExecutingLAId {
+                run_id: "run_id".to_string(),
+                seq_num: 1,
+            }),
+            Some(TimeoutInfo {
+                start_to_close_active: false,
+                ..
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn activity_type_default_close_timeouts() {
+        let lam = LocalActivityManager::test_with_opts(
+            5,
+            LocalActivityManagerOptions {
+                default_close_timeouts_per_type: HashMap::from([(
+                    "defaulted".to_string(),
+                    LACloseTimeouts::StartOnly(Duration::from_secs(7)),
+                )]),
+                ..Default::default()
+            },
+        );
+        let new_la = |seq: u32, activity_type: &str, close_timeouts| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    activity_type: activity_type.to_string(),
+                    close_timeouts,
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        let res = lam.enqueue([
+            new_la(1, "defaulted", None),
+            new_la(
+                2,
+                "defaulted",
+                Some(LACloseTimeouts::ScheduleOnly(Duration::from_secs(3))),
+            ),
+            new_la(3, "other", None),
+        ]);
+        assert_matches!(
+            res.immediate_resolutions.as_slice(),
+            [LocalActivityResolution {
+                seq: 3,
+                result: LocalActivityExecutionResult::Failed(_),
+                ..
+            }]
+        );
+
+        let mut starts = HashMap::new();
+        for _ in 1..=2 {
+            let next = lam.next_pending().await.unwrap().unwrap();
+            let s = assert_matches!(next.variant, Some(activity_task::Variant::Start(s)) => s);
+            starts.insert(s.activity_id.clone(), s);
+        }
+        assert_eq!(
+            starts["1"].start_to_close_timeout,
+            Some(prost_dur!(from_secs(7)))
+        );
+        assert_eq!(
+            starts["2"].schedule_to_close_timeout,
+            Some(prost_dur!(from_secs(3)))
+        );
+        assert_eq!(
+            starts["2"].start_to_close_timeout,
+            Some(prost_dur!(from_secs(3)))
+        );
+    }
+
+    #[tokio::test]
+    async fn scheduled_without_close_timeouts_uses_type_default() {
+        let lam = LocalActivityManager::test_with_opts(
+            5,
+            LocalActivityManagerOptions {
+                default_close_timeouts_per_type: HashMap::from([(
+                    "defaulted".to_string(),
+                    LACloseTimeouts::StartOnly(Duration::from_secs(7)),
+                )]),
+                ..Default::default()
+            },
+        );
+        let schedule_cmd = ValidScheduleLA::from_schedule_la(
+            ScheduleLocalActivity {
+                seq: 1,
+                activity_id: "1".to_string(),
+                activity_type: "defaulted".to_string(),
+                ..Default::default()
+            },
+            None,
+        )
+        .unwrap();
+        let res = lam.enqueue([NewLocalAct {
+            schedule_cmd,
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        assert!(res.immediate_resolutions.is_empty());
+
+        let next = lam.next_pending().await.unwrap().unwrap();
+        let start = assert_matches!(next.variant, Some(activity_task::Variant::Start(s)) => s);
+        assert_eq!(start.start_to_close_timeout, Some(prost_dur!(from_secs(7))));
+    }
+
+    #[tokio::test]
+    async fn retry_carries_last_heartbeat_details() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(10))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: Default::default(),
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let next = lam.next_pending().await.unwrap().unwrap();
+        let tt = TaskToken(next.task_token);
+        let details = vec![Payload {
+            data: b"checkpoint".to_vec(),
+            ..Default::default()
+        }];
+        lam.record_heartbeat(&tt, details.clone());
+        lam.complete(
+            &tt,
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+
+        let next = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            next.variant.unwrap(),
+            activity_task::Variant::Start(Start { attempt: 2, heartbeat_details, .. })
+                if heartbeat_details == details
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_has_distinct_attempt_scheduled_time() {
+        let lam = LocalActivityManager::test(1);
+        let schedule_time = SystemTime::now();
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(10))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: Default::default(),
+            schedule_time,
+            ..Default::default()
+        }
+        .into()]);
+
+        let next = lam.next_pending().await.unwrap().unwrap();
+        let first = assert_matches!(next.variant, Some(activity_task::Variant::Start(s)) => s);
+        assert_eq!(first.scheduled_time, first.current_attempt_scheduled_time);
+        lam.complete(
+            &TaskToken(next.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+
+        let next = lam.next_pending().await.unwrap().unwrap();
+        let second = assert_matches!(next.variant, Some(activity_task::Variant::Start(s)) => s);
+        assert_eq!(second.scheduled_time, Some(schedule_time.into()));
+        let attempt_sched: SystemTime = second
+            .current_attempt_scheduled_time
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert!(attempt_sched >= schedule_time + Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn forced_timeouts_fire_immediately() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    maximum_attempts: 1,
+                    ..Default::default()
+                }),
+                close_timeouts: Some(LACloseTimeouts::StartOnly(Duration::from_secs(1000))),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        lam.next_pending().await.unwrap().unwrap();
+
+        lam.force_timeouts();
+        let next = lam.next_pending().await.unwrap();
+        assert!(next.is_timeout(true));
+        assert_matches!(
+            next,
+            NextPendingLAAction::Autocomplete(LACompleteAction::Report { resolution, .. })
+                if resolution.result.get_timeout_type() == Some(TimeoutType::StartToClose)
+        );
+        assert_eq!(lam.num_outstanding(), 0);
+    }
+
+    #[tokio::test]
+    async fn dispatch_observer_sees_every_task() {
+        let observed = Arc::new(Mutex::new(vec![]));
+        let observed_c = observed.clone();
+        let lam = LocalActivityManager::test_with_opts(
+            5,
+            LocalActivityManagerOptions {
+                dispatch_observer: Some(Arc::new(move |t| observed_c.lock().push(t.clone()))),
+                ..Default::default()
+            },
+        );
+        lam.enqueue((1..=2).map(|i| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: i,
+                    activity_id: i.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        }));
+        let mut dispatched = vec![];
+        for _ in 1..=2 {
+            dispatched.push(lam.next_pending().await.unwrap().unwrap());
+        }
+        lam.enqueue([LocalActRequest::Cancel(ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        })]);
+        dispatched.push(lam.next_pending().await.unwrap().unwrap());
+
+        let observed = observed.lock();
+        assert_eq!(*observed, dispatched);
+        assert_matches!(
+            observed.iter().map(|t| t.variant.as_ref().unwrap()).collect::<Vec<_>>().as_slice(),
+            [
+                activity_task::Variant::Start(Start { activity_id: a1, .. }),
+                activity_task::Variant::Start(Start { activity_id: a2, .. }),
+                activity_task::Variant::Cancel(_),
+            ] if a1 == "1" && a2 == "2"
+        );
+    }
+
+    #[tokio::test]
+    async fn result_interceptor_can_turn_failures_into_successes() {
+        let lam = LocalActivityManager::test_with_opts(
+            5,
+            LocalActivityManagerOptions {
+                result_interceptor: Some(Arc::new(|r| match r {
+                    LocalActivityExecutionResult::Failed(_) => {
+                        LocalActivityExecutionResult::Completed(Default::default())
+                    }
+                    other => other,
+                })),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(10))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let next = lam.next_pending().await.unwrap().unwrap();
+        let res = lam.complete(
+            &TaskToken(next.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        assert_matches!(
+            res,
+            LACompleteAction::Report {
+                resolution: LocalActivityResolution {
+                    result: LocalActivityExecutionResult::Completed(_),
+                    ..
+                },
+                ..
+            }
+        );
+        assert_eq!(lam.num_in_backoff(), 0);
+        assert_eq!(lam.num_outstanding(), 0);
+    }
+
+    #[tokio::test]
+    async fn lifetime_stats_count_outcomes() {
+        let lam = LocalActivityManager::test(5);
+        let new_la = |seq: u32, maximum_attempts: i32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        initial_interval: Some(prost_dur!(from_millis(10))),
+                        backoff_coefficient: 1.0,
+                        maximum_attempts,
+                        ..Default::default()
+                    }),
+                    local_retry_threshold: Duration::from_secs(500),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        lam.enqueue([new_la(1, 0), new_la(2, 1)]);
+        let first = lam.next_pending().await.unwrap().unwrap();
+        let second = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            lam.complete(
+                &TaskToken(first.task_token),
+                LocalActivityExecutionResult::Failed(Default::default()),
+            ),
+            LACompleteAction::WillBeRetried(_)
+        );
+        assert_matches!(
+            lam.complete(
+                &TaskToken(second.task_token),
+                LocalActivityExecutionResult::Failed(Default::default()),
+            ),
+            LACompleteAction::Report { .. }
+        );
+        let retry = lam.next_pending().await.unwrap().unwrap();
+        lam.complete(
+            &TaskToken(retry.task_token),
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+
+        assert_eq!(
+            lam.lifetime_stats(),
+            LaLifetimeStats {
+                dispatched: 3,
+                completed: 1,
+                failed_terminal: 1,
+                retried: 1,
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn start_delay_holds_back_dispatch() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                start_delay: Some(Duration::from_millis(200)),
+                close_timeouts: Some(LACloseTimeouts::ScheduleOnly(Duration::from_millis(100))),
+                schedule_to_start_timeout: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        assert_eq!(lam.num_in_backoff(), 1);
+        assert!(lam.next_pending().now_or_never().is_none());
+        // Past both timeouts, which shouldn't have counted the delay against the activity
+        tokio::time::advance(Duration::from_millis(150)).await;
+        yield_now().await;
+        assert!(lam.next_pending().now_or_never().is_none());
+
+        tokio::time::advance(Duration::from_millis(50)).await;
+        let next = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(next.variant, Some(activity_task::Variant::Start(_)));
+        assert_eq!(lam.num_in_backoff(), 0);
+    }
+
+    #[tokio::test]
+    async fn backing_off_reports_remaining_time() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(100))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        assert!(lam.backing_off().is_empty());
+        let next = lam.next_pending().await.unwrap().unwrap();
+        lam.complete(
+            &TaskToken(next.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+
+        let backing_off = lam.backing_off();
+        assert_matches!(
+            backing_off.as_slice(),
+            [(id, remaining)]
+                if id.seq_num == 1
+                && *remaining <= Duration::from_secs(100)
+                && *remaining > Duration::from_secs(95)
+        );
+    }
+
+    #[tokio::test]
+    async fn max_local_backoff_forces_timer_backoff() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(60))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                max_local_backoff: Some(Duration::from_secs(30)),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let next = lam.next_pending().await.unwrap().unwrap();
+        let res = lam.complete(
+            &TaskToken(next.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        assert_matches!(
+            res,
+            LACompleteAction::Report {
+                resolution: LocalActivityResolution {
+                    backoff: Some(backoff),
+                    ..
+                },
+                ..
+            } if backoff == prost_dur!(from_secs(60))
+        );
+        assert_eq!(lam.num_in_backoff(), 0);
+    }
+
+    #[tokio::test]
+    async fn cancel_for_workflow_completion_uses_reason_and_never_retries() {
+        let lam = LocalActivityManager::test(5);
+        lam.enqueue((1..=2).map(|i| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: i,
+                    activity_id: i.to_string(),
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        initial_interval: Some(prost_dur!(from_millis(10))),
+                        backoff_coefficient: 1.0,
+                        ..Default::default()
+                    }),
+                    local_retry_threshold: Duration::from_secs(500),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        }));
+        let mut tts = vec![];
+        for _ in 1..=2 {
+            tts.push(TaskToken(
+                lam.next_pending().await.unwrap().unwrap().task_token,
+            ));
+        }
+
+        let immediate_res = lam
+            .enqueue([LocalActRequest::CancelForWorkflowCompletion(
+                "run_id".to_string(),
+            )])
+            .immediate_resolutions;
+        assert!(immediate_res.is_empty());
+        for _ in 1..=2 {
+            let next = lam.next_pending().await.unwrap().unwrap();
+            assert_matches!(
+                next.variant.unwrap(),
+                activity_task::Variant::Cancel(Cancel { reason, details: Some(details) })
+                    if reason == ActivityCancelReason::WorkflowCompleted as i32
+                       && details.is_workflow_completed
+            );
+        }
+
+        // Even retryable failures must not be retried once the workflow has completed
+        for tt in tts {
+            assert_matches!(
+                lam.complete(
+                    &tt,
+                    LocalActivityExecutionResult::Failed(Default::default())
+                ),
+                LACompleteAction::Report { .. }
+            );
+        }
+        assert_eq!(lam.num_in_backoff(), 0);
+        assert_eq!(lam.num_outstanding(), 0);
+    }
+
+    #[tokio::test]
+    async fn per_type_concurrency_limit_respected() {
+        let lam = LocalActivityManager::test_with_opts(
+            10,
+            LocalActivityManagerOptions {
+                max_concurrent_per_type: HashMap::from([("limited".to_string(), 2)]),
+                ..Default::default()
+            },
+        );
+        let new_la = |seq: u32, activity_type: &str| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    activity_type: activity_type.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: Default::default(),
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        lam.enqueue([
+            new_la(1, "limited"),
+            new_la(2, "limited"),
+            new_la(3, "limited"),
+            new_la(4, "other"),
+        ]);
+        let mut dispatched = HashMap::new();
+        for _ in 1..=3 {
+            let next = lam.next_pending().await.unwrap().unwrap();
+            let start = assert_matches!(next.variant, Some(activity_task::Variant::Start(s)) => s);
+            dispatched.insert(start.activity_id, TaskToken(next.task_token));
+        }
+        // The third "limited" activity was skipped in favor of the other type
+        assert!(dispatched.contains_key("1"));
+        assert!(dispatched.contains_key("2"));
+        assert!(dispatched.contains_key("4"));
+        assert!(lam.next_pending().now_or_never().is_none());
+
+        lam.complete(
+            &dispatched["4"],
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        assert!(lam.next_pending().now_or_never().is_none());
+        lam.complete(
+            &dispatched["1"],
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        let next = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            next.variant.unwrap(),
+            activity_task::Variant::Start(Start { activity_id, .. }) if activity_id == "3"
+        );
+    }
+
+    #[derive(Debug, Clone)]
+    struct NamedInstrument(String);
+    impl BufferInstrumentRef for NamedInstrument {}
+
+    fn buffered_metrics() -> (Arc<MetricsCallBuffer<NamedInstrument>>, MetricsContext) {
+        let call_buffer = Arc::new(MetricsCallBuffer::<NamedInstrument>::new(1000));
+        let metrics = MetricsContext::top_level_with_meter(
+            "fake_ns".to_string(),
+            "q".to_string(),
+            Some(TemporalMeter::new(
+                call_buffer.clone(),
+                Default::default(),
+                TaskQueueLabelStrategy::UseNormal,
+            )),
+        );
+        (call_buffer, metrics)
+    }
+
+    /// Drains the buffer and returns every update recorded against the named metric. Must only be
+    /// called once per buffer, since instrument creation events are only seen the first time.
+    fn metric_updates(
+        call_buffer: &MetricsCallBuffer<NamedInstrument>,
+        name: &str,
+    ) -> Vec<MetricUpdateVal> {
+        let mut updates = vec![];
+        for event in call_buffer.retrieve() {
+            match event {
+                MetricEvent::Create {
+                    params,
+                    populate_into,
+                    ..
+                } => {
+                    populate_into
+                        .set(Arc::new(NamedInstrument(params.name.to_string())))
+                        .unwrap();
+                }
+                MetricEvent::Update {
+                    instrument, update, ..
+                } if instrument.get().0 == name => updates.push(update),
+                _ => {}
+            }
+        }
+        updates
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn permit_wait_latency_is_recorded() {
+        let (call_buffer, metrics) = buffered_metrics();
+        let lam = LocalActivityManager::test_with_metrics(1, Default::default(), metrics);
+        lam.enqueue((1..=2).map(|seq| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        }));
+        let first = lam.next_pending().await.unwrap().unwrap();
+        // The second activity can't get a permit until the first one completes
+        let (second, _) = tokio::join!(lam.next_pending(), async {
+            tokio::time::advance(Duration::from_secs(1)).await;
+            lam.complete(
+                &TaskToken(first.task_token),
+                LocalActivityExecutionResult::Completed(Default::default()),
+            );
+        });
+        second.unwrap().unwrap();
+
+        let waits = metric_updates(&call_buffer, "local_activity_slot_wait_latency");
+        assert_eq!(waits.len(), 2);
+        assert_matches!(waits[0], MetricUpdateVal::Duration(d) if d == Duration::ZERO);
+        assert_matches!(waits[1], MetricUpdateVal::Duration(d) if d == Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn duplicate_enqueues_are_counted() {
+        let (call_buffer, metrics) = buffered_metrics();
+        let lam = LocalActivityManager::test_with_metrics(1, Default::default(), metrics);
+        let new_la = || {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: 1,
+                    activity_id: "1".to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        lam.enqueue([new_la(), new_la()]);
+        lam.next_pending().await.unwrap().unwrap();
+        lam.enqueue([new_la()]);
+
+        let dupes = metric_updates(&call_buffer, "local_activity_duplicate_enqueue");
+        assert_eq!(dupes.len(), 2);
+        assert!(dupes.iter().all(|u| matches!(u, MetricUpdateVal::Delta(1))));
+    }
+
+    #[test]
+    fn internal_tasks_spawn_on_provided_runtime() {
+        let dedicated = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_time()
+            .build()
+            .unwrap();
+        let ambient = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        ambient.block_on(async {
+            let lam = LocalActivityManager::test_on_runtime(1, dedicated.handle().clone());
+            lam.enqueue([NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: 1,
+                    activity_id: 1.to_string(),
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        initial_interval: Some(prost_dur!(from_secs(10))),
+                        backoff_coefficient: 1.0,
+                        ..Default::default()
+                    }),
+                    local_retry_threshold: Duration::from_secs(500),
+                    close_timeouts: Some(LACloseTimeouts::ScheduleOnly(Duration::from_secs(100))),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()]);
+            // The schedule-to-close timeout task
+            assert_eq!(dedicated.metrics().num_alive_tasks(), 1);
+
+            let next = lam.next_pending().await.unwrap().unwrap();
+            lam.complete(
+                &TaskToken(next.task_token),
+                LocalActivityExecutionResult::Failed(Default::default()),
+            );
+            // Plus the local backoff task
+            assert_eq!(dedicated.metrics().num_alive_tasks(), 2);
+            assert_eq!(Handle::current().metrics().num_alive_tasks(), 0);
+        });
+    }
+
+    #[tokio::test]
+    async fn flush_cancels_returns_all_pending_cancels() {
+        let lam = LocalActivityManager::test(5);
+        lam.enqueue((1..=3).map(|i| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: i,
+                    activity_id: i.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        }));
+        let mut tts = HashSet::new();
+        for _ in 1..=3 {
+            tts.insert(lam.next_pending().await.unwrap().unwrap().task_token);
+        }
+        lam.enqueue((1..=3).map(|i| {
+            LocalActRequest::Cancel(ExecutingLAId {
+                run_id: "run_id".to_string(),
+                seq_num: i,
+            })
+        }));
+
+        let cancels = lam.flush_cancels();
+        assert_eq!(cancels.len(), 3);
+        for c in cancels {
+            assert_matches!(c.variant, Some(activity_task::Variant::Cancel(_)));
+            assert!(tts.remove(&c.task_token));
+        }
+        assert!(lam.flush_cancels().is_empty());
+    }
+
+    struct CancelledIsFinal;
+    impl FailureClassifier for CancelledIsFinal {
+        fn classify(&self, failure: &Failure) -> FailureClassification {
+            if matches!(
+                failure.failure_info,
+                Some(FailureInfo::CanceledFailureInfo(_))
+            ) {
+                FailureClassification::NonRetryable
+            } else {
+                FailureClassification::DeferToRetryPolicy
+            }
+        }
+    }
+
+    #[rstest]
+    #[case::classified_non_retryable(FailureInfo::CanceledFailureInfo(Default::default()), false)]
+    #[case::deferred_to_policy(FailureInfo::ApplicationFailureInfo(Default::default()), true)]
+    #[tokio::test]
+    async fn failure_classifier_can_prevent_retries(
+        #[case] failure_info: FailureInfo,
+        #[case] should_retry: bool,
+    ) {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                failure_classifier: Some(Arc::new(CancelledIsFinal)),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: "1".to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(1))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: Default::default(),
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let next = lam.next_pending().await.unwrap().unwrap();
+        let res = lam.complete(
+            &TaskToken(next.task_token),
+            LocalActivityExecutionResult::Failed(ActFail {
+                failure: Some(Failure {
+                    failure_info: Some(failure_info),
+                    ..Default::default()
+                }),
+            }),
+        );
+        if should_retry {
+            assert_matches!(res, LACompleteAction::WillBeRetried(_));
+        } else {
+            assert_matches!(res, LACompleteAction::Report { .. });
+        }
+    }
+
+    #[tokio::test]
+    async fn correlation_id_survives_retries() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(10))),
+                    backoff_coefficient: 1.0,
+                    maximum_attempts: 2,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: Default::default(),
+            schedule_time: SystemTime::now(),
+            correlation_id: Some("trace-123".to_string()),
+            ..Default::default()
+        }
+        .into()]);
+
+        for attempt in 1..=2 {
+            let next = lam.next_pending().await.unwrap().unwrap();
+            let start = assert_matches!(next.variant, Some(activity_task::Variant::Start(s)) => s);
+            assert_eq!(start.attempt, attempt);
+            assert_eq!(
+                String::from_json_payload(&start.header_fields[LA_CORRELATION_ID_HEADER]).unwrap(),
+                "trace-123"
+            );
+            let res = lam.complete(
+                &TaskToken(next.task_token),
+                LocalActivityExecutionResult::Failed(Default::default()),
+            );
+            if attempt == 1 {
+                assert_matches!(res, LACompleteAction::WillBeRetried(_));
+            } else {
+                assert_matches!(
+                    res,
+                    LACompleteAction::Report { resolution, .. }
+                        if resolution.correlation_id.as_deref() == Some("trace-123")
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn aborted_attempt_is_retried() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(10))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        };
+        let first = lam.next_pending().await.unwrap().unwrap();
+
+        assert!(lam.abort_attempt(&id));
+        let cancel = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(cancel.variant, Some(activity_task::Variant::Cancel(_)));
+        assert_eq!(cancel.task_token, first.task_token);
+        assert_matches!(
+            lam.complete(
+                &TaskToken(first.task_token),
+                LocalActivityExecutionResult::Cancelled(Cancellation::from_details(None)),
+            ),
+            LACompleteAction::WillBeRetried(_)
+        );
+        // Nothing is executing while backing off, so there's no attempt to abort
+        assert!(!lam.abort_attempt(&id));
+
+        let retry = lam.next_pending().await.unwrap().unwrap();
+        let start = assert_matches!(retry.variant, Some(activity_task::Variant::Start(s)) => s);
+        assert_eq!(start.attempt, 2);
+        // Cancelling the logical activity still works as usual afterwards
+        lam.enqueue([LocalActRequest::Cancel(id)]);
+        lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            lam.complete(
+                &TaskToken(retry.task_token),
+                LocalActivityExecutionResult::Cancelled(Cancellation::from_details(None)),
+            ),
+            LACompleteAction::Report { resolution, .. }
+                if matches!(resolution.result, LocalActivityExecutionResult::Cancelled(_))
+        );
+    }
+
+    #[tokio::test]
+    async fn dropping_next_pending_futures_does_not_leak_permits() {
+        let lam = LocalActivityManager::test(2);
+        lam.enqueue((1..=5).map(|i| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: i,
+                    activity_id: i.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: Default::default(),
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        }));
+
+        let mut executing = vec![];
+        let mut completed = 0;
+        for _ in 0..100 {
+            // Each future is polled at most once and then dropped, whether it was waiting on the
+            // stream lock, a permit, or a request.
+            for _ in 0..10 {
+                if let Some(Some(next)) = lam.next_pending().now_or_never() {
+                    executing.push(TaskToken(next.unwrap().task_token));
+                }
+                lam.assert_permit_accounting();
+            }
+            assert!(executing.len() <= 2);
+            if executing.is_empty() {
+                if completed == 5 {
+                    break;
+                }
+                tokio::task::yield_now().await;
+                continue;
+            }
+            let tt = executing.remove(0);
+            assert_matches!(
+                lam.complete(
+                    &tt,
+                    LocalActivityExecutionResult::Completed(Default::default()),
+                ),
+                LACompleteAction::Report { .. }
+            );
+            completed += 1;
+            lam.assert_permit_accounting();
+        }
+        assert_eq!(completed, 5);
+        lam.assert_permit_accounting();
+        // At most the one permit acquired eagerly for the next request may still be held
+        let extant = *lam.permit_dealer.get_extant_count_rcv().borrow();
+        assert!(extant <= 1);
+        assert_eq!(lam.permit_dealer.available_permits(), Some(2 - extant));
+    }
+
+    #[tokio::test]
+    async fn completion_future_resolves_for_its_activity_only() {
+        let lam = LocalActivityManager::test(3);
+        let run_id = "run_id".to_string();
+        lam.enqueue((1..=3).map(|i| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: i,
+                    activity_id: i.to_string(),
+                    // So a failure is final
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        maximum_attempts: 1,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: run_id.clone(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        }));
+        let second_done = lam.completion_future(ExecutingLAId {
+            run_id: run_id.clone(),
+            seq_num: 2,
+        });
+        tokio::pin!(second_done);
+        let mut tts = HashMap::new();
+        for _ in 1..=3 {
+            let task = lam.next_pending().await.unwrap().unwrap();
+            let start = assert_matches!(task.variant, Some(activity_task::Variant::Start(s)) => s);
+            tts.insert(start.activity_id, TaskToken(task.task_token));
+        }
+
+        for id in ["1", "3"] {
+            lam.complete(
+                &tts[id],
+                LocalActivityExecutionResult::Completed(Default::default()),
+            );
+        }
+        assert!((&mut second_done).now_or_never().is_none());
+        lam.complete(
+            &tts["2"],
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        let resolution = second_done.await.unwrap();
+        assert_eq!(resolution.seq, 2);
+        assert_matches!(resolution.result, LocalActivityExecutionResult::Failed(_));
+
+        // Unknown activities resolve right away
+        assert!(
+            lam.completion_future(ExecutingLAId { run_id, seq_num: 4 })
+                .await
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn completion_future_waits_through_timer_backoff() {
+        let lam = LocalActivityManager::test(1);
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        };
+        let new_la = |attempt| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: 1,
+                    activity_id: 1.to_string(),
+                    attempt,
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        initial_interval: Some(prost_dur!(from_secs(10))),
+                        backoff_coefficient: 1.0,
+                        maximum_attempts: 5,
+                        ..Default::default()
+                    }),
+                    local_retry_threshold: Duration::from_secs(1),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: id.run_id.clone(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        lam.enqueue([new_la(1)]);
+        let done = lam.completion_future(id.clone());
+        tokio::pin!(done);
+        let tt = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        assert_matches!(
+            lam.complete(
+                &tt,
+                LocalActivityExecutionResult::Failed(Default::default())
+            ),
+            LACompleteAction::Report {
+                resolution: LocalActivityResolution {
+                    backoff: Some(_),
+                    ..
+                },
+                ..
+            }
+        );
+        assert!((&mut done).now_or_never().is_none());
+
+        // Lang schedules the next attempt once its timer fires
+        lam.enqueue([new_la(2)]);
+        let tt = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        lam.complete(
+            &tt,
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        let resolution = done.await.unwrap();
+        assert_eq!(resolution.attempt, 2);
+        assert_matches!(
+            resolution.result,
+            LocalActivityExecutionResult::Completed(_)
+        );
+        lam.drain_and_assert_clean().await;
+    }
+
+    #[tokio::test]
+    async fn completion_future_resolves_when_cancelled_during_backoff() {
+        let lam = LocalActivityManager::test(1);
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        };
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(10))),
+                    backoff_coefficient: 1.0,
+                    maximum_attempts: 5,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: id.run_id.clone(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let done = lam.completion_future(id.clone());
+        let tt = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        assert_matches!(
+            lam.complete(
+                &tt,
+                LocalActivityExecutionResult::Failed(Default::default())
+            ),
+            LACompleteAction::WillBeRetried(_)
+        );
+        let immediate = lam
+            .enqueue([LocalActRequest::Cancel(id)])
+            .immediate_resolutions;
+        assert_eq!(immediate.len(), 1);
+        let resolution = done.await.unwrap();
+        assert_matches!(
+            resolution.result,
+            LocalActivityExecutionResult::Cancelled(_)
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::logging_off(false, false, None)]
+    #[case::default_redaction(
+        true,
+        false,
+        Some("Payload(5 bytes, metadata keys: [encoding, secret_key])")
+    )]
+    #[case::custom_redaction(true, true, Some("<5 bytes>"))]
+    fn payloads_are_logged_in_redacted_form(
+        #[case] log_payloads: bool,
+        #[case] custom_redactor: bool,
+        #[case] expected: Option<&str>,
+    ) {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                log_payloads,
+                payload_redactor: custom_redactor.then(|| {
+                    Arc::new(|p: &Payload| format!("<{} bytes>", p.data.len())) as PayloadRedactor
+                }),
+                ..Default::default()
+            },
+        );
+        let payload = Payload {
+            metadata: HashMap::from([
+                ("secret_key".to_string(), b"hunter2".to_vec()),
+                ("encoding".to_string(), b"binary/plain".to_vec()),
+            ]),
+            data: b"hello".to_vec(),
+            ..Default::default()
+        };
+        let logged = lam.loggable_payloads([&payload]);
+        assert_eq!(logged, expected.map(|e| vec![e.to_string()]));
+        if let Some(logged) = logged {
+            assert!(!logged[0].contains("hello") && !logged[0].contains("hunter2"));
+        }
+    }
+
+    #[rstest::rstest]
+    #[case::allow_list_miss(Some(HashSet::from(["allowed".to_string()])), HashSet::new())]
+    #[case::deny_list_hit(None, HashSet::from(["forbidden".to_string()]))]
+    #[case::deny_beats_allow(
+        Some(HashSet::from(["allowed".to_string(), "forbidden".to_string()])),
+        HashSet::from(["forbidden".to_string()])
+    )]
+    #[tokio::test]
+    async fn disallowed_activity_types_fail_immediately(
+        #[case] allowed_activity_types: Option<HashSet<String>>,
+        #[case] denied_activity_types: HashSet<String>,
+    ) {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                allowed_activity_types,
+                denied_activity_types,
+                ..Default::default()
+            },
+        );
+        let new_la = |seq: u32, activity_type: &str| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    activity_type: activity_type.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: Default::default(),
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        let immediate = lam
+            .enqueue([new_la(1, "forbidden"), new_la(2, "allowed")])
+            .immediate_resolutions;
+        assert_matches!(
+            immediate.as_slice(),
+            [LocalActivityResolution {
+                seq: 1,
+                result: LocalActivityExecutionResult::Failed(f),
+                ..
+            }] if f.failure.as_ref().unwrap().message.contains("'forbidden' is not registered")
+        );
+        let dispatched = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            dispatched.variant,
+            Some(activity_task::Variant::Start(Start { activity_type, .. }))
+                if activity_type == "allowed"
+        );
+    }
+
+    #[tokio::test]
+    async fn serialized_run_dispatches_in_seq_order() {
+        let lam = LocalActivityManager::test(10);
+        lam.serialize_run("serialized".to_string());
+        let new_la = |run_id: &str, seq: u32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: run_id.to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        lam.enqueue([
+            new_la("serialized", 3),
+            new_la("serialized", 1),
+            new_la("other", 1),
+            new_la("serialized", 2),
+            new_la("other", 2),
+        ]);
+
+        let mut dispatched = vec![];
+        let next = async || {
+            let task = lam.next_pending().await.unwrap().unwrap();
+            let start = assert_matches!(task.variant, Some(activity_task::Variant::Start(s)) => s);
+            (
+                start.workflow_execution.unwrap().run_id,
+                start.activity_id,
+                TaskToken(task.task_token),
+            )
+        };
+        // Only the lowest seq of the serialized run is available, while the other run isn't held
+        // back at all
+        for _ in 0..3 {
+            dispatched.push(next().await);
+        }
+        let ids = |d: &[(String, String, TaskToken)]| {
+            d.iter()
+                .map(|(r, a, _)| format!("{r}/{a}"))
+                .collect::<HashSet<_>>()
+        };
+        assert_eq!(
+            ids(&dispatched),
+            HashSet::from(["serialized/1", "other/1", "other/2"].map(String::from))
+        );
+        assert!(lam.next_pending().now_or_never().is_none());
+
+        for expected in ["2", "3"] {
+            let (_, _, tt) = dispatched
+                .extract_if(.., |(r, _, _)| r == "serialized")
+                .next()
+                .unwrap();
+            lam.complete(
+                &tt,
+                LocalActivityExecutionResult::Completed(Default::default()),
+            );
+            let (run, act_id, tt) = next().await;
+            assert_eq!((run.as_str(), act_id.as_str()), ("serialized", expected));
+            dispatched.push((run, act_id, tt));
+            assert!(lam.next_pending().now_or_never().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn cancel_in_same_batch_as_schedule_resolves_immediately() {
+        let lam = LocalActivityManager::test(5);
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        };
+        let immediate = lam
+            .enqueue([
+                NewLocalAct {
+                    schedule_cmd: ValidScheduleLA {
+                        seq: 1,
+                        activity_id: 1.to_string(),
+                        ..Default::default()
+                    },
+                    workflow_type: "".to_string(),
+                    workflow_exec_info: WorkflowExecution {
+                        workflow_id: "".to_string(),
+                        run_id: id.run_id.clone(),
+                    },
+                    schedule_time: SystemTime::now(),
+                    ..Default::default()
+                }
+                .into(),
+                LocalActRequest::Cancel(id.clone()),
+            ])
+            .immediate_resolutions;
+        assert_matches!(
+            immediate.as_slice(),
+            [LocalActivityResolution {
+                seq: 1,
+                result: LocalActivityExecutionResult::Cancelled(_),
+                ..
+            }]
+        );
+        assert!(lam.next_pending().now_or_never().is_none());
+        assert_eq!(lam.num_outstanding(), 0);
+        // Never having been tracked, it can be scheduled again
+        assert!(!lam.dat.lock().la_info.contains_key(&id));
+        lam.drain_and_assert_clean().await;
+    }
+
+    struct TimeoutsAreFinal;
+    impl FailureClassifier for TimeoutsAreFinal {
+        fn classify(&self, failure: &Failure) -> FailureClassification {
+            if matches!(
+                failure.failure_info,
+                Some(FailureInfo::TimeoutFailureInfo(_))
+            ) {
+                FailureClassification::NonRetryable
+            } else {
+                FailureClassification::DeferToRetryPolicy
+            }
+        }
+    }
+
+    #[rstest]
+    #[case::retried(false, false, true)]
+    #[case::non_idempotent(true, false, false)]
+    #[case::classified_non_retryable(false, true, false)]
+    #[tokio::test]
+    async fn schedule_to_start_timeout_can_be_retried(
+        #[case] non_idempotent: bool,
+        #[case] timeouts_are_final: bool,
+        #[case] should_retry: bool,
+    ) {
+        let (tx, mut rx) = unbounded_channel();
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                failure_classifier: timeouts_are_final
+                    .then(|| Arc::new(TimeoutsAreFinal) as Arc<dyn FailureClassifier>),
+                retrying_failures_tx: Some(tx),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                schedule_to_start_timeout: Some(Duration::from_secs(1)),
+                retry_schedule_to_start_timeout: true,
+                non_idempotent,
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(10))),
+                    backoff_coefficient: 1.0,
+                    maximum_attempts: 3,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: Default::default(),
+            // Already well past its schedule-to-start timeout
+            schedule_time: SystemTime::now() - Duration::from_secs(10),
+            ..Default::default()
+        }
+        .into()]);
+
+        if !should_retry {
+            assert_matches!(
+                lam.next_pending().await.unwrap(),
+                NextPendingLAAction::Autocomplete(LACompleteAction::Report { resolution, .. })
+                    if resolution.result.get_timeout_type() == Some(TimeoutType::ScheduleToStart)
+            );
+            assert_eq!(lam.lifetime_stats().failed_terminal, 1);
+            return;
+        }
+        assert_matches!(
+            lam.next_pending().await.unwrap(),
+            NextPendingLAAction::Autocomplete(LACompleteAction::WillBeRetried(None))
+        );
+        // Counted and reported like any other retried attempt
+        assert_eq!(lam.lifetime_stats().retried, 1);
+        assert_eq!(lam.run_metrics("").retried, 1);
+        assert_matches!(
+            rx.try_recv(),
+            Ok(RetryingAttemptFailure { attempt: 1, result, .. })
+                if result.get_timeout_type() == Some(TimeoutType::ScheduleToStart)
+        );
+        let next = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            next.variant,
+            Some(activity_task::Variant::Start(Start { attempt: 2, .. }))
+        );
+    }
+
+    #[rstest]
+    #[case::over_threshold(Duration::ZERO, None)]
+    #[case::over_max_local_retries(Duration::from_secs(500), Some(0))]
+    #[tokio::test]
+    async fn schedule_to_start_timeout_retry_can_use_timer(
+        #[case] local_retry_threshold: Duration,
+        #[case] max_local_retries: Option<u32>,
+    ) {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                max_local_retries,
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                schedule_to_start_timeout: Some(Duration::from_secs(1)),
+                retry_schedule_to_start_timeout: true,
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(10))),
+                    backoff_coefficient: 1.0,
+                    maximum_attempts: 3,
+                    ..Default::default()
+                }),
+                local_retry_threshold,
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: Default::default(),
+            // Already well past its schedule-to-start timeout
+            schedule_time: SystemTime::now() - Duration::from_secs(10),
+            ..Default::default()
+        }
+        .into()]);
+
+        // Lang is asked to back off with a timer, like for any other failed attempt
+        assert_matches!(
+            lam.next_pending().await.unwrap(),
+            NextPendingLAAction::Autocomplete(LACompleteAction::Report { resolution, .. })
+                if resolution.result.get_timeout_type() == Some(TimeoutType::ScheduleToStart)
+                    && resolution.backoff == Some(prost_dur!(from_millis(10)))
+        );
+        assert_eq!(lam.num_in_backoff(), 0);
+        assert_eq!(lam.num_outstanding(), 0);
+        lam.drain_and_assert_clean().await;
+    }
+
+    #[tokio::test]
+    async fn permit_waiters_reflect_blocked_requests() {
+        let (call_buffer, metrics) = buffered_metrics();
+        let lam = LocalActivityManager::test_with_metrics(1, Default::default(), metrics);
+        lam.enqueue((1..=2).map(|i| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: i,
+                    activity_id: i.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: Default::default(),
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        }));
+        let first = lam.next_pending().await.unwrap().unwrap();
+        assert_eq!(lam.pending_waiters(), 0);
+        // The second request gets taken off the queue, but there's no slot for it
+        assert!(lam.next_pending().now_or_never().is_none());
+        assert_eq!(lam.pending_waiters(), 1);
+
+        lam.complete(
+            &TaskToken(first.task_token),
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        lam.next_pending().await.unwrap().unwrap();
+        assert_eq!(lam.pending_waiters(), 0);
+        assert_matches!(
+            metric_updates(&call_buffer, "local_activity_slot_waiters").as_slice(),
+            [
+                MetricUpdateVal::Value(1),
+                MetricUpdateVal::Value(0),
+                MetricUpdateVal::Value(1),
+                MetricUpdateVal::Value(0),
+            ]
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::ignore(UnknownTokenPolicy::IgnoreUnknown)]
+    #[case::log(UnknownTokenPolicy::LogUnknown)]
+    #[case::error(UnknownTokenPolicy::ErrorUnknown)]
+    #[tokio::test]
+    async fn unknown_token_policy_is_applied(#[case] unknown_token_policy: UnknownTokenPolicy) {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                unknown_token_policy,
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: Default::default(),
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let issued = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        let never_issued = [
+            TaskToken(b"garbage".to_vec()),
+            TaskToken::new_local_activity_token(100_u32.to_le_bytes()),
+        ];
+
+        for tt in never_issued {
+            let action = lam.complete(
+                &tt,
+                LocalActivityExecutionResult::Completed(Default::default()),
+            );
+            if unknown_token_policy == UnknownTokenPolicy::ErrorUnknown {
+                assert_matches!(action, LACompleteAction::UnknownToken(t) if t == tt);
+            } else {
+                assert_matches!(action, LACompleteAction::Untracked);
+            }
+        }
+        // Tokens which were issued are always just untracked once their activity is done
+        assert_matches!(
+            lam.complete(
+                &issued,
+                LocalActivityExecutionResult::Completed(Default::default()),
+            ),
+            LACompleteAction::Report { .. }
+        );
+        assert_matches!(
+            lam.complete(
+                &issued,
+                LocalActivityExecutionResult::Completed(Default::default()),
+            ),
+            LACompleteAction::Untracked
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::idempotent(false)]
+    #[case::non_idempotent(true)]
+    #[tokio::test]
+    async fn non_idempotent_activity_not_retried_after_cancel(#[case] non_idempotent: bool) {
+        let lam = LocalActivityManager::test(1);
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        };
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                non_idempotent,
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(10))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: id.run_id.clone(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let start = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            start.variant,
+            Some(activity_task::Variant::Start(Start { non_idempotent: ni, .. })) if ni == non_idempotent
+        );
+        lam.enqueue([LocalActRequest::Cancel(id)]);
+        assert_matches!(
+            lam.next_pending().await.unwrap().unwrap().variant,
+            Some(activity_task::Variant::Cancel(_))
+        );
+        // Lang reports a retryable failure for the cancelled attempt
+        let action = lam.complete(
+            &TaskToken(start.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        if non_idempotent {
+            assert_matches!(
+                action,
+                LACompleteAction::Report {
+                    resolution: LocalActivityResolution {
+                        result: LocalActivityExecutionResult::Failed(_),
+                        ..
+                    },
+                    ..
+                }
+            );
+            assert_eq!(lam.num_in_backoff(), 0);
+        } else {
+            assert_matches!(action, LACompleteAction::WillBeRetried(_));
+        }
+    }
+
+    #[tokio::test]
+    async fn repeat_cancels_are_coalesced() {
+        let lam = LocalActivityManager::test(1);
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        };
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: id.run_id.clone(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let start = lam.next_pending().await.unwrap().unwrap();
+        for _ in 0..3 {
+            lam.enqueue([LocalActRequest::Cancel(id.clone())]);
+        }
+        assert_matches!(
+            lam.next_pending().await.unwrap().unwrap().variant,
+            Some(activity_task::Variant::Cancel(_))
+        );
+        // The other two are consumed without producing anything
+        for _ in 0..2 {
+            assert!(lam.next_pending().await.is_none());
+        }
+        assert!(lam.next_pending().now_or_never().is_none());
+        assert_matches!(
+            lam.complete(
+                &TaskToken(start.task_token),
+                LocalActivityExecutionResult::Cancelled(Cancellation::from_details(None)),
+            ),
+            LACompleteAction::Report { .. }
+        );
+    }
+
+    #[tokio::test]
+    async fn in_flight_counts_grouped_by_type() {
+        let lam = LocalActivityManager::test(10);
+        let new_la = |seq: u32, activity_type: &str| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    activity_type: activity_type.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: Default::default(),
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        assert!(lam.in_flight_by_type().is_empty());
+        lam.enqueue([
+            new_la(1, "a"),
+            new_la(2, "a"),
+            new_la(3, "b"),
+            new_la(4, "a"),
+        ]);
+        let mut tokens = vec![];
+        for _ in 1..=4 {
+            tokens.push(TaskToken(
+                lam.next_pending().await.unwrap().unwrap().task_token,
+            ));
+        }
+        assert_eq!(
+            lam.in_flight_by_type(),
+            HashMap::from([("a".to_string(), 3), ("b".to_string(), 1)])
+        );
+
+        lam.complete(
+            &tokens[2],
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        assert_eq!(
+            lam.in_flight_by_type(),
+            HashMap::from([("a".to_string(), 3)])
+        );
+    }
+
+    #[tokio::test]
+    async fn chaos_forced_failures_are_retried_then_reported() {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                chaos: Some(ChaosConfig {
+                    failure_probability: 1.0,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(10))),
+                    backoff_coefficient: 1.0,
+                    maximum_attempts: 2,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        // Lang never sees either attempt
+        assert_matches!(
+            lam.next_pending().await,
+            Some(NextPendingLAAction::Autocomplete(
+                LACompleteAction::WillBeRetried(_)
+            ))
+        );
+        assert_matches!(
+            lam.next_pending().await,
+            Some(NextPendingLAAction::Autocomplete(
+                LACompleteAction::Report {
+                    resolution: LocalActivityResolution {
+                        attempt: 2,
+                        result: LocalActivityExecutionResult::Failed(_),
+                        ..
+                    },
+                    ..
+                }
+            ))
+        );
+        assert_eq!(lam.num_outstanding(), 0);
+        assert_eq!(lam.lifetime_stats().dispatched, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn longest_running_is_earliest_dispatched() {
+        let lam = LocalActivityManager::test(10);
+        let new_la = |seq: u32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        assert!(lam.longest_running().is_none());
+        lam.enqueue([new_la(1)]);
+        let first = lam.next_pending().await.unwrap().unwrap();
+        tokio::time::advance(Duration::from_millis(10)).await;
+        lam.enqueue([new_la(2)]);
+        lam.next_pending().await.unwrap().unwrap();
+
+        let (id, running_for) = lam.longest_running().unwrap();
+        assert_eq!(id.seq_num, 1);
+        assert!(running_for >= Duration::from_millis(10));
+
+        assert!(
+            lam.cancel_longest_running_over(Duration::from_secs(60))
+                .is_none()
+        );
+        assert_matches!(
+            lam.cancel_longest_running_over(Duration::from_millis(5)),
+            Some((cancelled, None)) if cancelled == id
+        );
+        let cancel = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(cancel.variant, Some(activity_task::Variant::Cancel(_)));
+        assert_eq!(cancel.task_token, first.task_token);
+    }
+
+    #[tokio::test]
+    async fn custom_backoff_fn_replaces_policy_intervals() {
+        let fib = |n: u32| {
+            let (mut a, mut b) = (1, 1);
+            for _ in 1..n {
+                (a, b) = (b, a + b);
+            }
+            a
+        };
+        let (tx, mut rx) = unbounded_channel();
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                retrying_failures_tx: Some(tx),
+                backoff_fn: Some(Arc::new(move |attempt, _| {
+                    Some(Duration::from_millis(5 * fib(attempt)))
+                })),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(10))),
+                    maximum_attempts: 6,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let mut backoffs = vec![];
+        for _ in 1..=5 {
+            let next = lam.next_pending().await.unwrap().unwrap();
+            assert_matches!(
+                lam.complete(
+                    &TaskToken(next.task_token),
+                    LocalActivityExecutionResult::Failed(Default::default())
+                ),
+                LACompleteAction::WillBeRetried(_)
+            );
+            backoffs.push(rx.try_recv().unwrap().backoff);
+        }
+        assert_eq!(
+            backoffs,
+            [5, 5, 10, 15, 25].map(Duration::from_millis).to_vec()
+        );
+        // Maximum attempts still applies
+        let next = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            lam.complete(
+                &TaskToken(next.task_token),
+                LocalActivityExecutionResult::Failed(Default::default())
+            ),
+            LACompleteAction::Report { .. }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn start_to_close_can_be_extended_within_schedule_to_close() {
+        let lam = LocalActivityManager::test(5);
+        let new_la = |seq: u32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    close_timeouts: Some(LACloseTimeouts::Both {
+                        sched: Duration::from_secs(30),
+                        start: Duration::from_secs(10),
+                    }),
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        maximum_attempts: 1,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        lam.enqueue([new_la(1)]);
+        let tt = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+
+        assert_eq!(
+            lam.extend_start_to_close(&tt, Duration::from_secs(25)),
+            Err(ExtendTimeoutError::ExceedsScheduleToClose)
+        );
+        assert_eq!(
+            lam.extend_start_to_close(&TaskToken(b"nope".to_vec()), Duration::from_secs(1)),
+            Err(ExtendTimeoutError::NoActiveStartToClose)
+        );
+        lam.extend_start_to_close(&tt, Duration::from_secs(5))
+            .unwrap();
+
+        let before = tokio::time::Instant::now();
+        assert_matches!(
+            lam.next_pending().await.unwrap(),
+            NextPendingLAAction::Autocomplete(LACompleteAction::Report { resolution, .. })
+                if resolution.result.get_timeout_type() == Some(TimeoutType::StartToClose)
+        );
+        assert_eq!(before.elapsed(), Duration::from_secs(15));
+    }
+
+    #[tokio::test]
+    async fn progress_delivered_in_order_before_completion() {
+        let (tx, mut rx) = unbounded_channel();
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                progress_tx: Some(tx),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let tt = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+
+        for pct in ["50%", "90%"] {
+            lam.record_progress(&tt, pct.as_json_payload().unwrap());
+        }
+        lam.complete(
+            &tt,
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        // Progress for an activity that's done goes nowhere
+        lam.record_progress(&tt, "100%".as_json_payload().unwrap());
+
+        let mut reported = vec![];
+        while let Ok(progress) = rx.try_recv() {
+            assert_eq!(progress.id.seq_num, 1);
+            assert_eq!(progress.attempt, 1);
+            reported.push(String::from_json_payload(&progress.payload).unwrap());
+        }
+        assert_eq!(reported, ["50%", "90%"]);
+    }
+
+    /// Records nothing, and panics whenever a counter or duration histogram is recorded to
+    #[derive(Debug)]
+    struct PanickingMeter;
+    struct PanickingInstrument;
+    impl CoreMeter for PanickingMeter {
+        fn new_attributes(&self, attribs: NewAttributes) -> MetricAttributes {
+            NoOpCoreMeter.new_attributes(attribs)
+        }
+        fn extend_attributes(
+            &self,
+            existing: MetricAttributes,
+            attribs: NewAttributes,
+        ) -> MetricAttributes {
+            NoOpCoreMeter.extend_attributes(existing, attribs)
+        }
+        fn counter(&self, _: MetricParameters) -> Counter {
+            Counter::new(Arc::new(PanickingInstrument))
+        }
+        fn histogram(&self, params: MetricParameters) -> Histogram {
+            NoOpCoreMeter.histogram(params)
+        }
+        fn histogram_f64(&self, params: MetricParameters) -> HistogramF64 {
+            NoOpCoreMeter.histogram_f64(params)
+        }
+        fn histogram_duration(&self, _: MetricParameters) -> HistogramDuration {
+            HistogramDuration::new(Arc::new(PanickingInstrument))
+        }
+        fn gauge(&self, params: MetricParameters) -> Gauge {
+            NoOpCoreMeter.gauge(params)
+        }
+        fn gauge_f64(&self, params: MetricParameters) -> GaugeF64 {
+            NoOpCoreMeter.gauge_f64(params)
+        }
+    }
+    impl MetricAttributable<Box<dyn CounterBase>> for PanickingInstrument {
+        fn with_attributes(
+            &self,
+            _: &MetricAttributes,
+        ) -> Result<Box<dyn CounterBase>, Box<dyn std::error::Error>> {
+            Ok(Box::new(PanickingInstrument))
+        }
+    }
+    impl CounterBase for PanickingInstrument {
+        fn adds(&self, _: u64) {
+            panic!("counter exploded");
+        }
+    }
+    impl MetricAttributable<Box<dyn HistogramDurationBase>> for PanickingInstrument {
+        fn with_attributes(
+            &self,
+            _: &MetricAttributes,
+        ) -> Result<Box<dyn HistogramDurationBase>, Box<dyn std::error::Error>> {
+            Ok(Box::new(PanickingInstrument))
+        }
+    }
+    impl HistogramDurationBase for PanickingInstrument {
+        fn records(&self, _: Duration) {
+            panic!("histogram exploded");
+        }
+    }
+
+    #[tokio::test]
+    async fn panicking_metrics_backend_does_not_break_manager() {
+        let metrics = MetricsContext::top_level_with_meter(
+            "fake_ns".to_string(),
+            "q".to_string(),
+            Some(TemporalMeter::new(
+                Arc::new(PanickingMeter),
+                Default::default(),
+                TaskQueueLabelStrategy::UseNormal,
+            )),
+        );
+        let lam = LocalActivityManager::test_with_metrics(1, Default::default(), metrics);
+        let new_la = |seq: u32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        for seq in 1..=2 {
+            lam.enqueue([new_la(seq)]);
+            let next = lam.next_pending().await.unwrap().unwrap();
+            assert_matches!(
+                lam.complete(
+                    &TaskToken(next.task_token),
+                    LocalActivityExecutionResult::Completed(Default::default()),
+                ),
+                LACompleteAction::Report { .. }
+            );
+        }
+        assert_eq!(lam.num_outstanding(), 0);
+        assert_eq!(lam.lifetime_stats().completed, 2);
+    }
+
+    #[tokio::test]
+    async fn effective_schedule_to_close_excludes_time_already_elapsed() {
+        let lam = LocalActivityManager::test(1);
+        let already_elapsed = Duration::from_secs(4);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                close_timeouts: Some(LACloseTimeouts::Both {
+                    sched: Duration::from_secs(10),
+                    start: Duration::from_secs(3),
+                }),
+                original_schedule_time: Some(SystemTime::now() - already_elapsed),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let tt = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        let resolution = assert_matches!(
+            lam.complete(
+                &tt,
+                LocalActivityExecutionResult::Completed(Default::default()),
+            ),
+            LACompleteAction::Report { resolution, .. } => resolution
+        );
+        let EffectiveTimeouts {
+            schedule_to_close,
+            start_to_close,
+        } = resolution.effective_timeouts;
+        let schedule_to_close = schedule_to_close.unwrap();
+        assert!(schedule_to_close <= Duration::from_secs(6));
+        // Allow for slow test machines
+        assert!(schedule_to_close > Duration::from_secs(5));
+        assert_eq!(start_to_close, Some(Duration::from_secs(3)));
+    }
+
+    #[tokio::test]
+    async fn new_activities_rejected_once_shutdown_drains() {
+        let lam = LocalActivityManager::test(5);
+        let new_la = |seq: u32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        lam.enqueue([new_la(1)]);
+        let first = lam.next_pending().await.unwrap().unwrap();
+        let drain = lam.wait_all_outstanding_tasks_finished();
+        tokio::pin!(drain);
+        assert!(drain.as_mut().now_or_never().is_none());
+
+        let res = lam.enqueue([new_la(2)]);
+        assert_matches!(
+            res.immediate_resolutions.as_slice(),
+            [LocalActivityResolution {
+                seq: 2,
+                result: LocalActivityExecutionResult::Failed(f),
+                ..
+            }] if f.failure.as_ref().unwrap().message.contains("shutting down")
+        );
+        // Cancels still go through
+        lam.enqueue([LocalActRequest::Cancel(ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        })]);
+        let cancel = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(cancel.variant, Some(activity_task::Variant::Cancel(_)));
+        lam.complete(
+            &TaskToken(first.task_token),
+            LocalActivityExecutionResult::Cancelled(Cancellation::from_details(None)),
+        );
+        drain.await;
+    }
+
+    #[tokio::test]
+    async fn replay_mode_timeouts_are_fed_in_and_match_live() {
+        let new_la = || {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: 1,
+                    activity_id: 1.to_string(),
+                    close_timeouts: Some(LACloseTimeouts::StartOnly(Duration::from_millis(10))),
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        maximum_attempts: 1,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        };
+
+        let live = LocalActivityManager::test(1);
+        live.enqueue([new_la()]);
+        live.next_pending().await.unwrap().unwrap();
+        let live_timeout = live.next_pending().await.unwrap();
+
+        let replay = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                replay_mode: true,
+                ..Default::default()
+            },
+        );
+        replay.enqueue([new_la()]);
+        replay.next_pending().await.unwrap().unwrap();
+        assert!(!replay.timeout_info(&id).unwrap().start_to_close_active);
+        // No timer is running, so nothing happens by itself
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), replay.next_pending())
+                .await
+                .is_err()
+        );
+        assert!(!replay.force_timeout(&id, TimeoutType::Heartbeat));
+        assert!(replay.force_timeout(&id, TimeoutType::StartToClose));
+        let replay_timeout = replay.next_pending().await.unwrap();
+
+        for timeout in [live_timeout, replay_timeout] {
+            assert!(timeout.is_timeout(true));
+            assert_matches!(
+                timeout,
+                NextPendingLAAction::Autocomplete(LACompleteAction::Report { resolution, .. })
+                    if resolution.seq == 1
+                        && resolution.attempt == 1
+                        && resolution.result.get_timeout_type() == Some(TimeoutType::StartToClose)
+            );
+        }
+    }
+
+    #[rstest::rstest]
+    #[case::schedule_to_close(TimeoutType::ScheduleToClose)]
+    #[case::schedule_to_start(TimeoutType::ScheduleToStart)]
+    #[tokio::test]
+    async fn forced_timeout_resolves_backing_off_activity(#[case] timeout_type: TimeoutType) {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(10))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                close_timeouts: Some(LACloseTimeouts::Both {
+                    sched: Duration::from_secs(1000),
+                    start: Duration::from_secs(100),
+                }),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        };
+        let next = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            lam.complete(
+                &TaskToken(next.task_token),
+                LocalActivityExecutionResult::Failed(Default::default()),
+            ),
+            LACompleteAction::WillBeRetried(_)
+        );
+        assert_eq!(lam.num_in_backoff(), 1);
+
+        // There's no attempt running for a start-to-close timeout to apply to, and refusing it
+        // must leave the real schedule-to-close timer alone
+        assert!(!lam.force_timeout(&id, TimeoutType::StartToClose));
+        assert!(lam.timeout_info(&id).unwrap().schedule_to_close_active);
+
+        assert!(lam.force_timeout(&id, timeout_type));
+        assert_matches!(
+            lam.next_pending().await.unwrap(),
+            NextPendingLAAction::Autocomplete(LACompleteAction::Report { resolution, .. })
+                if resolution.attempt == 1
+                    && resolution.result.get_timeout_type() == Some(timeout_type)
+        );
+        assert_eq!(lam.num_in_backoff(), 0);
+        assert!(lam.timeout_info(&id).is_none());
+        lam.drain_and_assert_clean().await;
+    }
+
+    #[rstest::rstest]
+    #[case::shared_window(false)]
+    #[case::window_per_attempt(true)]
+    #[tokio::test(start_paused = true)]
+    async fn retries_can_reset_schedule_to_close_window(#[case] reset: bool) {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                close_timeouts: Some(LACloseTimeouts::ScheduleOnly(Duration::from_secs(12))),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(1))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                reset_schedule_time_on_retry: reset,
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        // The first two attempts each run for 4 seconds and fail, then back off for 1 second
+        for _ in 1..=2 {
+            let tt = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+            sleep(Duration::from_secs(4)).await;
+            assert_matches!(
+                lam.complete(
+                    &tt,
+                    LocalActivityExecutionResult::Failed(Default::default())
+                ),
+                LACompleteAction::WillBeRetried(_)
+            );
+        }
+        let third = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            third.variant,
+            Some(activity_task::Variant::Start(Start { attempt: 3, .. }))
+        );
+        // The third attempt starts 10 seconds in, and needs 4 more to finish
+        let timed_out = tokio::time::timeout(Duration::from_secs(4), lam.next_pending()).await;
+        if reset {
+            // It has until 12 seconds after its backoff ended
+            assert!(timed_out.is_err());
+            assert_matches!(
+                lam.complete(
+                    &TaskToken(third.task_token),
+                    LocalActivityExecutionResult::Completed(Default::default()),
+                ),
+                LACompleteAction::Report { .. }
+            );
+        } else {
+            // All attempts share the window which started when it was first scheduled
+            assert_matches!(
+                timed_out.unwrap().unwrap(),
+                NextPendingLAAction::Autocomplete(LACompleteAction::Report { resolution, .. })
+                    if resolution.result.get_timeout_type() == Some(TimeoutType::ScheduleToClose)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn reset_window_retry_past_deadline_times_out() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                deadline: Some(SystemTime::now() + Duration::from_secs(60)),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(100))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                reset_schedule_time_on_retry: true,
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let tt = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+
+        // A fresh window doesn't help when the deadline passes during the backoff
+        assert_matches!(
+            lam.complete(&tt, LocalActivityExecutionResult::Failed(Default::default())),
+            LACompleteAction::Report { resolution, .. }
+                if resolution.result.get_timeout_type() == Some(TimeoutType::ScheduleToClose)
+                    && resolution.backoff.is_none()
+        );
+        lam.drain_and_assert_clean().await;
+    }
+
+    struct EchoExecutor;
+    impl LocalExecutor for EchoExecutor {
+        fn try_execute(
+            &self,
+            _: &ExecutingLAId,
+            activity_type: &str,
+            arguments: &[Payload],
+            _: u32,
+        ) -> Option<LocalActivityExecutionResult> {
+            (activity_type == "echo").then(|| {
+                LocalActivityExecutionResult::Completed(Success {
+                    result: Some(arguments[0].clone()),
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn local_executor_can_claim_activities() {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                local_executor: Some(Arc::new(EchoExecutor)),
+                ..Default::default()
+            },
+        );
+        let new_la = |seq: u32, activity_type: &str| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    activity_type: activity_type.to_string(),
+                    arguments: vec!["hi".as_json_payload().unwrap()],
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        lam.enqueue([new_la(1, "echo"), new_la(2, "other")]);
+        let done = lam.completion_future(ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        });
+
+        let echoed = assert_matches!(
+            lam.next_pending().await.unwrap(),
+            NextPendingLAAction::Autocomplete(LACompleteAction::Report {
+                resolution: LocalActivityResolution {
+                    seq: 1,
+                    result: LocalActivityExecutionResult::Completed(success),
+                    ..
+                },
+                ..
+            }) => success
+        );
+        assert_eq!(
+            String::from_json_payload(echoed.result.as_ref().unwrap()).unwrap(),
+            "hi"
+        );
+        assert_matches!(done.await, Some(LocalActivityResolution { seq: 1, .. }));
+        // The executor didn't take a slot, so the other activity goes to lang
+        let dispatched = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            dispatched.variant,
+            Some(activity_task::Variant::Start(Start { activity_type, .. })) if activity_type == "other"
+        );
+        assert_eq!(lam.num_outstanding(), 1);
+    }
+
+    #[tokio::test]
+    async fn cancelling_run_token_cancels_all_its_activities() {
+        let lam = LocalActivityManager::test(5);
+        let new_la = |seq: u32, run_id: &str| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        initial_interval: Some(prost_dur!(from_secs(10))),
+                        backoff_coefficient: 1.0,
+                        ..Default::default()
+                    }),
+                    local_retry_threshold: Duration::from_secs(500),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: run_id.to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        let tok = lam.run_cancellation_token("run_id");
+        lam.enqueue([
+            new_la(1, "run_id"),
+            new_la(2, "run_id"),
+            new_la(3, "run_id"),
+            new_la(1, "other_run"),
+        ]);
+        let mut tokens = vec![];
+        for _ in 0..4 {
+            tokens.push(TaskToken(
+                lam.next_pending().await.unwrap().unwrap().task_token,
+            ));
+        }
+        // Put one of the run's activities into local backoff
+        assert_matches!(
+            lam.complete(
+                &tokens[2],
+                LocalActivityExecutionResult::Failed(Default::default())
+            ),
+            LACompleteAction::WillBeRetried(None)
+        );
+        assert_eq!(lam.num_in_backoff(), 1);
+
+        tok.cancel();
+        let mut cancelled = HashSet::new();
+        let mut resolved = vec![];
+        while cancelled.len() + resolved.len() < 3 {
+            // Handling the token itself produces no action
+            let Some(action) = lam.next_pending().await else {
+                continue;
+            };
+            match action {
+                NextPendingLAAction::Dispatch(ActivityTask {
+                    task_token,
+                    variant: Some(activity_task::Variant::Cancel(_)),
+                    ..
+                }) => {
+                    cancelled.insert(TaskToken(task_token));
+                }
+                NextPendingLAAction::Autocomplete(LACompleteAction::Report {
+                    run_id,
+                    resolution,
+                    ..
+                }) => {
+                    assert_eq!(run_id, "run_id");
+                    resolved.push(resolution);
+                }
+                other => panic!("Unexpected action {other:?}"),
+            }
+        }
+        // Executing attempts were sent cancels, and the one backing off was resolved right away
+        assert_eq!(
+            cancelled,
+            HashSet::from([tokens[0].clone(), tokens[1].clone()])
+        );
+        assert_matches!(
+            resolved.as_slice(),
+            [LocalActivityResolution {
+                seq: 3,
+                result: LocalActivityExecutionResult::Cancelled(_),
+                ..
+            }]
+        );
+        assert_eq!(lam.num_in_backoff(), 0);
+        // The other run is untouched
+        assert!(
+            lam.dat
+                .lock()
+                .outstanding_activity_tasks
+                .contains_key(&tokens[3])
+        );
+        // And the spent token isn't handed out again
+        assert!(!lam.run_cancellation_token("run_id").is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn completion_future_resolves_across_run_token_cancel() {
+        let lam = LocalActivityManager::test(5);
+        let new_la = |seq: u32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        initial_interval: Some(prost_dur!(from_secs(10))),
+                        backoff_coefficient: 1.0,
+                        ..Default::default()
+                    }),
+                    local_retry_threshold: Duration::from_secs(500),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        let id = |seq_num| ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num,
+        };
+        let tok = lam.run_cancellation_token("run_id");
+        lam.enqueue([new_la(1), new_la(2)]);
+        let executing = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        let backing_off = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        lam.complete(
+            &backing_off,
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        let executing_done = lam.completion_future(id(1));
+        let backing_off_done = lam.completion_future(id(2));
+
+        tok.cancel();
+        let mut cancel_sent = false;
+        let mut reported = false;
+        while !(cancel_sent && reported) {
+            match lam.next_pending().await {
+                Some(NextPendingLAAction::Dispatch(ActivityTask {
+                    variant: Some(activity_task::Variant::Cancel(_)),
+                    ..
+                })) => cancel_sent = true,
+                Some(NextPendingLAAction::Autocomplete(LACompleteAction::Report { .. })) => {
+                    reported = true
+                }
+                _ => {}
+            }
+        }
+        // The backing off activity was resolved as part of handling the token
+        assert_matches!(
+            backing_off_done.await,
+            Some(LocalActivityResolution {
+                seq: 2,
+                result: LocalActivityExecutionResult::Cancelled(_),
+                ..
+            })
+        );
+        // The executing one resolves once lang acknowledges the cancel
+        lam.complete(
+            &executing,
+            LocalActivityExecutionResult::Cancelled(Default::default()),
+        );
+        assert_matches!(
+            executing_done.await,
+            Some(LocalActivityResolution {
+                seq: 1,
+                result: LocalActivityExecutionResult::Cancelled(_),
+                ..
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn run_cancellation_token_forgotten_once_run_resolves() {
+        let lam = LocalActivityManager::test(1);
+        let tok = lam.run_cancellation_token("run_id");
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let task = lam.next_pending().await.unwrap().unwrap();
+        assert!(lam.dat.lock().run_tokens.contains_key("run_id"));
+        lam.complete(
+            &TaskToken(task.task_token),
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        assert!(lam.dat.lock().run_tokens.is_empty());
+        // The old token no longer does anything, and a fresh one is handed out
+        tok.cancel();
+        assert!(!lam.run_cancellation_token("run_id").is_cancelled());
+        lam.drain_and_assert_clean().await;
+    }
+
+    #[tokio::test]
+    async fn queue_depth_limit_evicts_oldest_queued() {
+        // One slot, so everything after the first activity stays queued
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                max_queue_depth: Some(2),
+                ..Default::default()
+            },
+        );
+        let new_la = |seq: u32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        let res = lam.enqueue([new_la(1)]);
+        assert!(res.immediate_resolutions.is_empty());
+        let first = lam.next_pending().await.unwrap().unwrap();
+
+        let res = lam.enqueue([new_la(2), new_la(3)]);
+        assert!(res.immediate_resolutions.is_empty());
+        let res = lam.enqueue([new_la(4)]);
+        assert_matches!(
+            res.immediate_resolutions.as_slice(),
+            [LocalActivityResolution {
+                seq: 2,
+                result: LocalActivityExecutionResult::TimedOut(_),
+                ..
+            }]
+        );
+        assert_eq!(
+            lam.timeout_info(&ExecutingLAId {
+                run_id: "run_id".to_string(),
+                seq_num: 2,
+            }),
+            None
+        );
+
+        // Once the slot frees up, the newer ones are dispatched in order and the evicted one is
+        // skipped over
+        lam.complete(
+            &TaskToken(first.task_token),
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        for seq in [3, 4] {
+            let task = loop {
+                if let Some(NextPendingLAAction::Dispatch(t)) = lam.next_pending().await {
+                    break t;
+                }
+            };
+            assert_matches!(
+                &task.variant,
+                Some(activity_task::Variant::Start(Start { activity_id, .. }))
+                    if *activity_id == seq.to_string()
+            );
+            lam.complete(
+                &TaskToken(task.task_token),
+                LocalActivityExecutionResult::Completed(Default::default()),
+            );
+        }
+        assert_eq!(lam.num_outstanding(), 0);
+    }
+
+    #[tokio::test]
+    async fn dispatch_hook_distinguishes_retries() {
+        let dispatches = Arc::new(Mutex::new(vec![]));
+        let recorded = dispatches.clone();
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                attempt_dispatch_hook: Some(Arc::new(move |id, attempt, is_retry| {
+                    recorded.lock().push((id.seq_num, attempt, is_retry));
+                })),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                // Explicit attempt numbers don't make the first dispatch look like a retry
+                attempt: 3,
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(1))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let first = lam.next_pending().await.unwrap().unwrap();
+        assert_eq!(*dispatches.lock(), vec![(1, 3, false)]);
+        lam.complete(
+            &TaskToken(first.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        let retry = lam.next_pending().await.unwrap().unwrap();
+        assert_eq!(*dispatches.lock(), vec![(1, 3, false), (1, 4, true)]);
+        lam.complete(
+            &TaskToken(retry.task_token),
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::default_max(None, MAX_PROTO_DURATION)]
+    #[case::configured_max(Some(Duration::from_secs(3600)), Duration::from_secs(3600))]
+    #[case::configured_max_too_big(Some(Duration::MAX), MAX_PROTO_DURATION)]
+    #[tokio::test]
+    async fn overflowing_backoff_is_clamped(
+        #[case] max_timer_backoff: Option<Duration>,
+        #[case] expected: Duration,
+    ) {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                backoff_fn: Some(Arc::new(|_, _| Some(Duration::MAX))),
+                max_timer_backoff,
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(1))),
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(5),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let task = lam.next_pending().await.unwrap().unwrap();
+        let res = lam.complete(
+            &TaskToken(task.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        let backoff = assert_matches!(
+            res,
+            LACompleteAction::Report {
+                resolution: LocalActivityResolution {
+                    result: LocalActivityExecutionResult::Failed(_),
+                    backoff: Some(b),
+                    ..
+                },
+                ..
+            } => b
+        );
+        assert_eq!(Duration::try_from(backoff).unwrap(), expected);
+        assert_eq!(lam.num_outstanding(), 0);
+        assert_eq!(
+            backoff_as_proto(Duration::MAX),
+            Err(LocalActivityError::BackoffOutOfRange(Duration::MAX))
+        );
+        assert!(backoff_as_proto(MAX_PROTO_DURATION).is_ok());
+        // The manager carries on as normal afterwards
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 2,
+                activity_id: 2.to_string(),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        lam.next_pending().await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dispatch_rate_limit_throttles_starts() {
+        let lam = LocalActivityManager::test_with_opts(
+            10,
+            LocalActivityManagerOptions {
+                dispatch_rate_limit: Some(DispatchRateLimit {
+                    per_second: 2.0,
+                    burst: 2,
+                }),
+                ..Default::default()
+            },
+        );
+        lam.enqueue((1..=6).map(|seq| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        }));
+
+        let start = tokio::time::Instant::now();
+        let mut dispatched_at = vec![];
+        while dispatched_at.len() < 6 {
+            if let Some(NextPendingLAAction::Dispatch(_)) = lam.next_pending().await {
+                dispatched_at.push(start.elapsed());
+            }
+        }
+        // The burst goes at once, then one every half second
+        let expected = [0, 0, 500, 1000, 1500, 2000].map(Duration::from_millis);
+        for (actual, expected) in dispatched_at.iter().zip(expected) {
+            assert!(
+                actual.abs_diff(expected) < Duration::from_millis(10),
+                "Dispatched at {dispatched_at:?}"
+            );
+        }
+        assert_eq!(lam.num_outstanding(), 6);
+    }
+
+    #[rstest::rstest]
+    #[case::zero(0.0)]
+    #[case::negative(-1.0)]
+    #[case::nan(f64::NAN)]
+    #[case::infinite(f64::INFINITY)]
+    #[tokio::test]
+    async fn invalid_dispatch_rate_limit_rejected(#[case] per_second: f64) {
+        let (hb_tx, _hb_rx) = unbounded_channel();
+        let res = LocalActivityManager::new(
+            "fake_ns".to_string(),
+            LocalActivityManager::test_permit_dealer(1),
+            hb_tx,
+            MetricsContext::no_op(),
+            LocalActivityManagerOptions {
+                dispatch_rate_limit: Some(DispatchRateLimit {
+                    per_second,
+                    burst: 1,
+                }),
+                ..Default::default()
+            },
+        );
+        assert!(res.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_token_not_spent_on_undispatched_activity() {
+        let lam = LocalActivityManager::test_with_opts(
+            10,
+            LocalActivityManagerOptions {
+                dispatch_rate_limit: Some(DispatchRateLimit {
+                    per_second: 0.1,
+                    burst: 1,
+                }),
+                ..Default::default()
+            },
+        );
+        let new_la = |seq: u32, schedule_time| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    schedule_to_start_timeout: Some(Duration::from_secs(1)),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time,
+                ..Default::default()
+            }
+            .into()
+        };
+        // Already past its schedule-to-start timeout, so it's resolved rather than dispatched
+        lam.enqueue([
+            new_la(1, SystemTime::now() - Duration::from_secs(10)),
+            new_la(2, SystemTime::now()),
+        ]);
+        assert_matches!(
+            lam.next_pending().await.unwrap(),
+            NextPendingLAAction::Autocomplete(LACompleteAction::Report { .. })
+        );
+        let start = tokio::time::Instant::now();
+        assert_matches!(
+            lam.next_pending().await.unwrap(),
+            NextPendingLAAction::Dispatch(_)
+        );
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn force_fail_reports_without_retrying() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(1))),
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let task = lam.next_pending().await.unwrap().unwrap();
+        let tt = TaskToken(task.task_token);
+        let res = lam.force_fail(
+            &tt,
+            APIFailure::application_failure("activity worker crashed".to_string(), true),
+        );
+        let failure = assert_matches!(
+            res,
+            LACompleteAction::Report {
+                resolution: LocalActivityResolution {
+                    result: LocalActivityExecutionResult::Failed(ActFail { failure: Some(f) }),
+                    backoff: None,
+                    ..
+                },
+                ..
+            } => f
+        );
+        assert_eq!(failure.message, "activity worker crashed");
+        assert_eq!(lam.num_outstanding(), 0);
+        assert_eq!(lam.num_in_backoff(), 0);
+        assert!(lam.dat.lock().la_info.is_empty());
+        // A late completion from lang finds nothing to complete
+        assert_matches!(
+            lam.complete(
+                &tt,
+                LocalActivityExecutionResult::Completed(Default::default())
+            ),
+            LACompleteAction::Untracked
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::small_result(10, false)]
+    #[case::oversized_result(1000, true)]
+    #[tokio::test]
+    async fn oversized_results_become_failures(#[case] result_len: usize, #[case] too_big: bool) {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                max_result_size: Some(100),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(1))),
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let task = lam.next_pending().await.unwrap().unwrap();
+        let res = lam.complete(
+            &TaskToken(task.task_token),
+            LocalActivityExecutionResult::Completed(Success {
+                result: Some("x".repeat(result_len).as_json_payload().unwrap()),
+            }),
+        );
+        let result = assert_matches!(
+            res,
+            LACompleteAction::Report {
+                resolution: LocalActivityResolution { result, .. },
+                ..
+            } => result
+        );
+        if too_big {
+            // Reported as a terminal failure, not retried
+            let failure = assert_matches!(
+                result,
+                LocalActivityExecutionResult::Failed(ActFail { failure: Some(f) }) => f
+            );
+            assert!(failure.message.contains("exceeds the maximum of 100 bytes"));
+            assert_eq!(lam.num_in_backoff(), 0);
+        } else {
+            assert_matches!(result, LocalActivityExecutionResult::Completed(_));
+        }
+        assert_eq!(lam.num_outstanding(), 0);
+    }
+
+    #[tokio::test]
+    async fn shutdown_progress_counts_down_outstanding() {
+        let lam = LocalActivityManager::test(5);
+        lam.enqueue((1..=3).map(|seq| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        }));
+        let mut tts = vec![];
+        for _ in 1..=3 {
+            tts.push(TaskToken(
+                lam.next_pending().await.unwrap().unwrap().task_token,
+            ));
+        }
+        let mut progress = lam.shutdown_progress();
+        lam.workflows_have_shutdown();
+        let drain = lam.wait_all_outstanding_tasks_finished();
+        tokio::pin!(drain);
+        assert!(drain.as_mut().now_or_never().is_none());
+        assert_eq!(*progress.borrow_and_update(), 3);
+
+        for (i, tt) in tts.iter().enumerate() {
+            lam.complete(
+                tt,
+                LocalActivityExecutionResult::Completed(Default::default()),
+            );
+            assert!(progress.has_changed().unwrap());
+            assert_eq!(*progress.borrow_and_update(), 2 - i);
+        }
+        drain.await;
+        assert_eq!(*progress.borrow(), 0);
+    }
+
+    #[rstest::rstest]
+    #[case::strict(true)]
+    #[case::lenient(false)]
+    #[tokio::test]
+    async fn stale_attempt_completions_after_resolution(#[case] strict: bool) {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                strict_attempt_order: strict,
+                ..Default::default()
+            },
+        );
+        let id = |seq_num| ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num,
+        };
+        let new_la = |seq: u32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        initial_interval: Some(prost_dur!(from_millis(1))),
+                        ..Default::default()
+                    }),
+                    local_retry_threshold: Duration::from_secs(500),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        lam.enqueue([new_la(1)]);
+
+        let first = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        assert_matches!(
+            lam.complete(
+                &first,
+                LocalActivityExecutionResult::Failed(Default::default())
+            ),
+            LACompleteAction::WillBeRetried(_)
+        );
+        let second = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        assert_ne!(first, second);
+        // Something else of the run stays tracked after the first activity resolves
+        lam.enqueue([new_la(2)]);
+        // While the retry is running, the first attempt's token is always rejected
+        assert_matches!(
+            lam.complete(
+                &first,
+                LocalActivityExecutionResult::Completed(Default::default())
+            ),
+            LACompleteAction::StaleToken(stale) if stale == id(1)
+        );
+        assert_matches!(
+            lam.complete(
+                &second,
+                LocalActivityExecutionResult::Completed(Default::default())
+            ),
+            LACompleteAction::Report { .. }
+        );
+
+        let late = lam.complete(
+            &first,
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        if strict {
+            assert_matches!(late, LACompleteAction::StaleToken(stale) if stale == id(1));
+        } else {
+            assert_matches!(late, LACompleteAction::Untracked);
+        }
+
+        // Strict mode remembers stale tokens only until nothing of the run is tracked any more
+        let other = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        lam.complete(
+            &other,
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        assert!(lam.dat.lock().superseded_tokens.is_empty());
+        assert_matches!(
+            lam.complete(
+                &first,
+                LocalActivityExecutionResult::Completed(Default::default())
+            ),
+            LACompleteAction::Untracked
+        );
+    }
+
+    #[tokio::test]
+    async fn diagnostic_snapshot_serializes_tracked_state() {
+        let lam = LocalActivityManager::test(2);
+        let new_la = |seq: u32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    activity_type: "diag".to_string(),
+                    close_timeouts: Some(LACloseTimeouts::StartOnly(Duration::from_secs(60))),
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        initial_interval: Some(prost_dur!(from_secs(10))),
+                        ..Default::default()
+                    }),
+                    local_retry_threshold: Duration::from_secs(500),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        lam.enqueue([new_la(1), new_la(2)]);
+        let first = lam.next_pending().await.unwrap().unwrap();
+        lam.next_pending().await.unwrap().unwrap();
+        lam.complete(
+            &TaskToken(first.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        lam.enqueue([new_la(3)]);
+
+        let snapshot = serde_json::to_value(lam.diagnostic_snapshot()).unwrap();
+        assert_eq!(
+            snapshot["outstanding"][0]["id"],
+            serde_json::json!({"run_id": "run_id", "seq_num": 2})
+        );
+        assert_eq!(snapshot["outstanding"][0]["activity_type"], "diag");
+        assert_eq!(snapshot["outstanding"][0]["attempt"], 1);
+        assert!(snapshot["outstanding"][0]["elapsed"].is_object());
+        assert_eq!(snapshot["outstanding"].as_array().unwrap().len(), 1);
+        assert_eq!(snapshot["backing_off"][0]["id"]["seq_num"], 1);
+        assert_eq!(snapshot["backing_off"][0]["failed_attempt"], 1);
+        assert!(
+            snapshot["backing_off"][0]["remaining"]["secs"]
+                .as_u64()
+                .unwrap()
+                <= 10
+        );
+        // Every tracked activity has a start-to-close timeout, but only the executing one's is
+        // running
+        let timeouts = snapshot["timeouts"].as_array().unwrap();
+        assert_eq!(timeouts.len(), 3);
+        assert_eq!(
+            timeouts
+                .iter()
+                .map(|t| t["start_to_close_active"].as_bool().unwrap())
+                .collect::<Vec<_>>(),
+            vec![false, true, false]
+        );
+        assert_eq!(snapshot["queue_depth"], 1);
+        assert_eq!(snapshot["available_slots"], 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn local_backoff_respects_floor() {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                min_local_backoff: Some(Duration::from_millis(100)),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(1))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let task = lam.next_pending().await.unwrap().unwrap();
+        let failed_at = tokio::time::Instant::now();
+        assert_matches!(
+            lam.complete(
+                &TaskToken(task.task_token),
+                LocalActivityExecutionResult::Failed(Default::default()),
+            ),
+            LACompleteAction::WillBeRetried(None)
+        );
+        let retry = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            retry.variant,
+            Some(activity_task::Variant::Start(Start { attempt: 2, .. }))
+        );
+        assert!(failed_at.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn dropping_next_pending_while_waiting_loses_nothing() {
+        let lam = LocalActivityManager::test(1);
+        let new_la = |seq: u32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+            .into()
+        };
+        lam.enqueue([new_la(1), new_la(2)]);
+        let first = lam.next_pending().await.unwrap().unwrap();
+        // The second request is queued, but there's no permit for it
+        assert!(lam.next_pending().now_or_never().is_none());
+        lam.assert_permit_accounting();
+
+        lam.complete(
+            &TaskToken(first.task_token),
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        let second = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            second.variant,
+            Some(activity_task::Variant::Start(Start { activity_id, .. })) if activity_id == "2"
+        );
+        lam.assert_permit_accounting();
+        lam.complete(
+            &TaskToken(second.task_token),
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        lam.drain_and_assert_clean().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn absolute_deadline_times_out_schedule_to_close() {
+        let lam = LocalActivityManager::test(1);
+        let new_la = |seq: u32, deadline: SystemTime| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    close_timeouts: Some(LACloseTimeouts::StartOnly(Duration::from_secs(60))),
+                    deadline: Some(deadline),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        // A deadline which already passed resolves right away
+        let res = lam.enqueue([new_la(1, SystemTime::now() - Duration::from_secs(1))]);
+        assert_matches!(
+            res.immediate_resolutions.as_slice(),
+            [LocalActivityResolution { seq: 1, result, .. }]
+                if result.get_timeout_type() == Some(TimeoutType::ScheduleToClose)
+        );
+
+        let start = tokio::time::Instant::now();
+        lam.enqueue([new_la(2, SystemTime::now() + Duration::from_millis(200))]);
+        lam.next_pending().await.unwrap().unwrap();
+        let res = lam.next_pending().await.unwrap();
+        assert_matches!(
+            res,
+            NextPendingLAAction::Autocomplete(LACompleteAction::Report {
+                resolution: LocalActivityResolution { seq: 2, result, .. },
+                ..
+            }) if result.get_timeout_type() == Some(TimeoutType::ScheduleToClose)
+        );
+        assert!(start.elapsed().abs_diff(Duration::from_millis(200)) < Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn context_propagator_injects_and_extracts() {
+        #[derive(Default)]
+        struct HeaderPropagator {
+            extracted: Mutex<Vec<(ExecutingLAId, bool)>>,
+        }
+        impl ContextPropagator for HeaderPropagator {
+            fn inject(&self, id: &ExecutingLAId, headers: &mut HashMap<String, Payload>) {
+                headers.insert(
+                    "traceparent".to_string(),
+                    format!("span-for-{}", id.seq_num)
+                        .as_json_payload()
+                        .unwrap(),
+                );
+            }
+            fn extract(&self, id: &ExecutingLAId, result: &LocalActivityExecutionResult) {
+                self.extracted.lock().push((
+                    id.clone(),
+                    matches!(result, LocalActivityExecutionResult::Completed(_)),
+                ));
+            }
+        }
+
+        let propagator = Arc::new(HeaderPropagator::default());
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                context_propagator: Some(propagator.clone()),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let task = lam.next_pending().await.unwrap().unwrap();
+        let start = assert_matches!(
+            task.variant,
+            Some(activity_task::Variant::Start(start)) => start
+        );
+        assert_eq!(
+            String::from_json_payload(&start.header_fields["traceparent"]).unwrap(),
+            "span-for-1"
+        );
+        assert!(propagator.extracted.lock().is_empty());
+        lam.complete(
+            &TaskToken(task.task_token),
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        assert_eq!(
+            *propagator.extracted.lock(),
+            vec![(
+                ExecutingLAId {
+                    run_id: "run_id".to_string(),
+                    seq_num: 1,
+                },
+                true
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn abort_all_resolves_everything_tracked() {
+        let lam = LocalActivityManager::test(2);
+        let new_la = |seq: u32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        initial_interval: Some(prost_dur!(from_secs(10))),
+                        ..Default::default()
+                    }),
+                    local_retry_threshold: Duration::from_secs(500),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        lam.enqueue([new_la(1), new_la(2)]);
+        let first = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        let second = TaskToken(lam.next_pending().await.unwrap().unwrap().task_token);
+        // 1 is backing off, 2 is executing, and 3 is still queued
+        lam.complete(
+            &first,
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        lam.enqueue([new_la(3)]);
+        let waiter = lam.completion_future(ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 3,
+        });
+        // The executing attempt can't resolve immediately
+        assert!(
+            lam.cancel_by_id(ExecutingLAId {
+                run_id: "run_id".to_string(),
+                seq_num: 2,
+            })
+            .is_none()
+        );
+        assert!(!lam.queued_cancels.lock().is_empty());
+
+        let resolutions = lam.abort_all();
+        assert_eq!(
+            resolutions
+                .iter()
+                .map(|(run_id, r)| (run_id.as_str(), r.seq, r.attempt))
+                .collect::<Vec<_>>(),
+            vec![("run_id", 1, 1), ("run_id", 2, 1), ("run_id", 3, 1)]
+        );
+        assert!(
+            resolutions
+                .iter()
+                .all(|(_, r)| matches!(r.result, LocalActivityExecutionResult::Cancelled(_)))
+        );
+        assert_matches!(waiter.await, Some(LocalActivityResolution { seq: 3, .. }));
+        {
+            let dat = lam.dat.lock();
+            assert!(dat.la_info.is_empty());
+            assert!(dat.outstanding_activity_tasks.is_empty());
+            assert!(dat.queued_new.is_empty());
+            assert!(dat.superseded_tokens.is_empty());
+            assert!(dat.run_metrics.is_empty());
+        }
+        assert!(lam.queued_cancels.lock().is_empty());
+        lam.assert_permit_accounting();
+        // Lang finishing afterwards changes nothing
+        assert_matches!(
+            lam.complete(
+                &second,
+                LocalActivityExecutionResult::Completed(Default::default())
+            ),
+            LACompleteAction::Untracked
+        );
+    }
+
+    #[tokio::test]
+    async fn has_pending_work_tracks_queue_and_outstanding() {
+        let lam = LocalActivityManager::test(1);
+        assert!(!lam.has_pending_work());
+        let new_la = |seq: u32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        lam.enqueue([new_la(1), new_la(2)]);
+        assert!(lam.has_pending_work());
+        assert_eq!(lam.req_tx.queued(), 2);
+
+        for _ in 1..=2 {
+            let task = lam.next_pending().await.unwrap().unwrap();
+            assert!(lam.has_pending_work());
+            lam.complete(
+                &TaskToken(task.task_token),
+                LocalActivityExecutionResult::Completed(Default::default()),
+            );
+        }
+        assert_eq!(lam.req_tx.queued(), 0);
+        assert!(!lam.has_pending_work());
+    }
+
+    #[tokio::test]
+    async fn updated_retry_policy_applies_to_next_failure() {
+        let lam = LocalActivityManager::test(1);
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        };
+        let new_policy = RetryPolicy {
+            maximum_attempts: 1,
+            ..Default::default()
+        };
+        assert_matches!(
+            lam.update_retry_policy(&id, new_policy.clone()),
+            Err(LocalActivityError::NotOutstanding(_))
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(1))),
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let task = lam.next_pending().await.unwrap().unwrap();
+        // Held to the same rules as policies the activity is scheduled with
+        assert_matches!(
+            lam.update_retry_policy(
+                &id,
+                RetryPolicy {
+                    backoff_coefficient: 0.5,
+                    ..Default::default()
+                }
+            ),
+            Err(LocalActivityError::InvalidRetryPolicy(_))
+        );
+        lam.update_retry_policy(&id, new_policy).unwrap();
+        let res = lam.complete(
+            &TaskToken(task.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        assert_matches!(
+            res,
+            LACompleteAction::Report {
+                resolution: LocalActivityResolution {
+                    result: LocalActivityExecutionResult::Failed(_),
+                    backoff: None,
+                    ..
+                },
+                ..
+            }
+        );
+        assert_eq!(lam.num_outstanding(), 0);
+        assert_eq!(lam.num_in_backoff(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn circuit_breaker_trips_and_recovers() {
+        let lam = LocalActivityManager::test_with_opts(
+            5,
+            LocalActivityManagerOptions {
+                circuit_breaker: Some(CircuitBreakerOptions {
+                    failure_threshold: 2,
+                    window: Duration::from_secs(10),
+                    cooldown: Duration::from_secs(5),
+                }),
+                ..Default::default()
+            },
+        );
+        let new_la = |seq: u32| NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq,
+                activity_id: seq.to_string(),
+                activity_type: "flaky".to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    maximum_attempts: 1,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        };
+
+        for seq in 1..=2 {
+            lam.enqueue([new_la(seq).into()]);
+            let task = lam.next_pending().await.unwrap().unwrap();
+            assert_matches!(
+                lam.complete(
+                    &TaskToken(task.task_token),
+                    LocalActivityExecutionResult::Failed(Default::default()),
+                ),
+                LACompleteAction::Report { .. }
+            );
+        }
+
+        // The circuit is open, so the next one fails without reaching lang
+        lam.enqueue([new_la(3).into()]);
+        let resolution = assert_matches!(
+            lam.next_pending().await.unwrap(),
+            NextPendingLAAction::Autocomplete(LACompleteAction::Report { resolution, .. })
+                => resolution
+        );
+        assert_eq!(resolution.seq, 3);
+        assert_matches!(resolution.result, LocalActivityExecutionResult::Failed(_));
+        assert_eq!(lam.num_outstanding(), 0);
+        assert!(lam.dat.lock().la_info.is_empty());
+
+        // Once the cooldown passes, dispatches go through again, and a success closes the circuit
+        tokio::time::advance(Duration::from_secs(6)).await;
+        for seq in 4..=5 {
+            lam.enqueue([new_la(seq).into()]);
+            let task = lam.next_pending().await.unwrap().unwrap();
+            assert_matches!(
+                task.variant,
+                Some(activity_task::Variant::Start(Start { activity_id, .. }))
+                    if activity_id == seq.to_string()
+            );
+            lam.complete(
+                &TaskToken(task.task_token),
+                LocalActivityExecutionResult::Completed(Default::default()),
+            );
+        }
+        lam.assert_permit_accounting();
+    }
+
+    #[rstest::rstest]
+    #[case::during_backoff(true, CANCELLED_DURING_BACKOFF_DETAILS)]
+    #[case::in_same_batch(false, "cancelled")]
+    #[tokio::test]
+    async fn default_cancel_details_only_fill_in_missing_details(
+        #[case] during_backoff: bool,
+        #[case] expected_details: &str,
+    ) {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                default_cancel_details: Some("cancelled".as_json_payload().unwrap()),
+                ..Default::default()
+            },
+        );
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        };
+        let new_la = LocalActRequest::New(NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(10))),
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        });
+        let immediate_res = if during_backoff {
+            lam.enqueue([new_la]);
+            let next = lam.next_pending().await.unwrap().unwrap();
+            lam.complete(
+                &TaskToken(next.task_token),
+                LocalActivityExecutionResult::Failed(Default::default()),
+            );
+            lam.enqueue([LocalActRequest::Cancel(id)])
+                .immediate_resolutions
+        } else {
+            lam.enqueue([new_la, LocalActRequest::Cancel(id)])
+                .immediate_resolutions
+        };
+        assert_eq!(immediate_res.len(), 1);
+        let details = assert_matches!(
+            &immediate_res[0].result,
+            LocalActivityExecutionResult::Cancelled(Cancellation {
+                failure: Some(Failure {
+                    failure_info: Some(FailureInfo::CanceledFailureInfo(info)),
+                    ..
+                })
+            }) => info.details.as_ref().unwrap()
+        );
+        assert_eq!(
+            String::from_json_payload(&details.payloads[0]).unwrap(),
+            expected_details
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::genuine_first(0, true)]
+    #[case::explicit_first(1, true)]
+    #[case::explicitly_numbered_retry(3, false)]
+    #[tokio::test]
+    async fn first_attempt_flag_only_set_on_first_dispatch(
+        #[case] scheduled_attempt: u32,
+        #[case] expect_first: bool,
+    ) {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                attempt: scheduled_attempt,
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(1))),
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let task = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            task.variant,
+            Some(activity_task::Variant::Start(Start { first_attempt, .. }))
+                if first_attempt == expect_first
+        );
+        lam.complete(
+            &TaskToken(task.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        // A local retry is never the first attempt
+        let task = loop {
+            if let Some(NextPendingLAAction::Dispatch(t)) = lam.next_pending().await {
+                break t;
+            }
+        };
+        assert_matches!(
+            task.variant,
+            Some(activity_task::Variant::Start(Start {
+                first_attempt: false,
+                ..
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn backoff_latency_is_recorded() {
+        let (call_buffer, metrics) = buffered_metrics();
+        let lam = LocalActivityManager::test_with_metrics(1, Default::default(), metrics);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(50))),
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+
+        let task = lam.next_pending().await.unwrap().unwrap();
+        lam.complete(
+            &TaskToken(task.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        let retry = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(
+            retry.variant,
+            Some(activity_task::Variant::Start(Start { attempt: 2, .. }))
+        );
+
+        let backoffs = metric_updates(&call_buffer, "local_activity_backoff_latency");
+        assert_eq!(backoffs.len(), 1);
+        assert_matches!(
+            backoffs[0],
+            MetricUpdateVal::Duration(d)
+                if d >= Duration::from_millis(50) && d < Duration::from_secs(5)
+        );
+    }
+
+    #[tokio::test]
+    async fn pending_cancels_lists_undispatched_cancels() {
+        let lam = LocalActivityManager::test(5);
+        lam.enqueue((1..=3).map(|seq| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        }));
+        for _ in 1..=3 {
+            lam.next_pending().await.unwrap().unwrap();
+        }
+        assert!(lam.pending_cancels().is_empty());
+
+        let id = |seq_num| ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num,
+        };
+        lam.enqueue([
+            LocalActRequest::Cancel(id(1)),
+            LocalActRequest::Cancel(id(3)),
+        ]);
+        assert_eq!(lam.pending_cancels(), vec![id(1), id(3)]);
+
+        let cancel = lam.next_pending().await.unwrap().unwrap();
+        assert_matches!(cancel.variant, Some(activity_task::Variant::Cancel(_)));
+        assert_eq!(lam.pending_cancels().len(), 1);
+        lam.next_pending().await.unwrap().unwrap();
+        assert!(lam.pending_cancels().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_dispatch_jitter_staggers_simultaneous_retries() {
+        let lam = LocalActivityManager::test_with_opts(
+            5,
+            LocalActivityManagerOptions {
+                retry_dispatch_jitter: Some(RetryDispatchJitter {
+                    window: Duration::from_secs(1),
+                    seed: Some(7),
+                }),
+                ..Default::default()
+            },
+        );
+        lam.enqueue((1..=3).map(|seq| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        initial_interval: Some(prost_dur!(from_millis(10))),
+                        ..Default::default()
+                    }),
+                    local_retry_threshold: Duration::from_secs(500),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        }));
+        let mut tokens = vec![];
+        for _ in 1..=3 {
+            tokens.push(TaskToken(
+                lam.next_pending().await.unwrap().unwrap().task_token,
+            ));
+        }
+        let failed_at = tokio::time::Instant::now();
+        for tt in tokens {
+            lam.complete(
+                &tt,
+                LocalActivityExecutionResult::Failed(Default::default()),
+            );
+        }
+
+        let mut dispatched_after = vec![];
+        while dispatched_after.len() < 3 {
+            if let Some(NextPendingLAAction::Dispatch(_)) = lam.next_pending().await {
+                dispatched_after.push(failed_at.elapsed());
+            }
+        }
+        assert!(
+            dispatched_after
+                .iter()
+                .all(|d| *d >= Duration::from_millis(10) && *d <= Duration::from_millis(1010))
+        );
+        dispatched_after.dedup();
+        assert_eq!(dispatched_after.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn cancel_by_token_dispatches_cancel() {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let task = lam.next_pending().await.unwrap().unwrap();
+        assert!(!lam.cancel_by_token(&TaskToken(b"nope".to_vec())));
+        assert!(lam.cancel_by_token(&TaskToken(task.task_token.clone())));
+
+        let cancel = lam.next_pending().await.unwrap().unwrap();
+        assert_eq!(cancel.task_token, task.task_token);
+        assert_matches!(cancel.variant, Some(activity_task::Variant::Cancel(_)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deadline_warning_dispatched_before_timeout() {
+        let lam = LocalActivityManager::test_with_opts(
+            1,
+            LocalActivityManagerOptions {
+                deadline_warning_lead: Some(Duration::from_secs(2)),
+                ..Default::default()
+            },
+        );
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    maximum_attempts: 1,
+                    ..Default::default()
+                }),
+                close_timeouts: Some(LACloseTimeouts::ScheduleOnly(Duration::from_secs(10))),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let started = tokio::time::Instant::now();
+        let task = lam.next_pending().await.unwrap().unwrap();
+
+        let warning = lam.next_pending().await.unwrap().unwrap();
+        assert_eq!(warning.task_token, task.task_token);
+        let reason = assert_matches!(
+            warning.variant,
+            Some(activity_task::Variant::Cancel(Cancel { reason, details: Some(d) }))
+                if d.is_deadline_approaching => reason
+        );
+        assert_eq!(reason, ActivityCancelReason::DeadlineApproaching as i32);
+        let warned_after = started.elapsed();
+        assert!(warned_after >= Duration::from_secs(8) && warned_after < Duration::from_secs(10));
+        // The activity is still running until the real timeout
+        assert_eq!(lam.num_outstanding(), 1);
+
+        assert!(lam.next_pending().await.unwrap().is_timeout(true));
+        assert!(started.elapsed() >= Duration::from_secs(10));
+        assert_eq!(lam.num_outstanding(), 0);
+    }
+
+    #[rstest::rstest]
+    #[case::versioned(Some("1.2.3"))]
+    #[case::unversioned(None)]
+    #[tokio::test]
+    async fn build_id_is_passed_to_lang(#[case] build_id: Option<&str>) {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            build_id: build_id.map(ToString::to_string),
+            ..Default::default()
+        }
+        .into()]);
+
+        let next = lam.next_pending().await.unwrap().unwrap();
+        let start = assert_matches!(next.variant, Some(activity_task::Variant::Start(s)) => s);
+        assert_eq!(
+            start
+                .header_fields
+                .get(LA_BUILD_ID_HEADER)
+                .map(|p| String::from_json_payload(p).unwrap()),
+            build_id.map(ToString::to_string)
+        );
+    }
+
+    #[tokio::test]
+    async fn lifecycle_events_are_broadcast() {
+        let lam = LocalActivityManager::test(1);
+        let mut events = lam.subscribe_events();
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_millis(10))),
+                    backoff_coefficient: 1.0,
+                    ..Default::default()
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let first = lam.next_pending().await.unwrap().unwrap();
+        lam.complete(
+            &TaskToken(first.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        let retry = lam.next_pending().await.unwrap().unwrap();
+        lam.complete(
+            &TaskToken(retry.task_token),
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+
+        let id = ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num: 1,
+        };
+        let seen: Vec<_> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        assert_eq!(
+            seen,
+            vec![
+                LaEvent::Queued(id.clone()),
+                LaEvent::Dispatched {
+                    id: id.clone(),
+                    attempt: 1
+                },
+                LaEvent::BackingOff {
+                    id: id.clone(),
+                    failed_attempt: 1,
+                    backoff: Duration::from_millis(10)
+                },
+                LaEvent::Dispatched {
+                    id: id.clone(),
+                    attempt: 2
+                },
+                LaEvent::Resolved { id, attempt: 2 },
+            ]
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::schedule(true)]
+    #[case::start(false)]
+    #[tokio::test(start_paused = true)]
+    async fn unreported_timeouts_resolve_as_cancelled(#[case] is_schedule: bool) {
+        let lam = LocalActivityManager::test(1);
+        let timeout = Duration::from_millis(100);
+        let close_timeouts = if is_schedule {
+            LACloseTimeouts::ScheduleOnly(timeout)
+        } else {
+            LACloseTimeouts::StartOnly(timeout)
+        };
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    maximum_attempts: 1,
+                    ..Default::default()
+                }),
+                close_timeouts: Some(close_timeouts),
+                report_timeouts: Some(false),
+                ..Default::default()
+            },
+            workflow_type: "".to_string(),
+            workflow_exec_info: WorkflowExecution {
+                workflow_id: "".to_string(),
+                run_id: "run_id".to_string(),
+            },
+            schedule_time: SystemTime::now(),
+            ..Default::default()
+        }
+        .into()]);
+        let start = lam.next_pending().await.unwrap().unwrap();
+
+        // Time auto-advances to the timeout once everything is idle. The workflow hears of a
+        // cancellation rather than the timeout, and lang is still told to stop the attempt.
+        let (resolution, cancel) = assert_matches!(
+            lam.next_pending().await.unwrap(),
+            NextPendingLAAction::Autocomplete(LACompleteAction::Report {
+                resolution,
+                task: Some(cancel),
+                ..
+            }) => (resolution, cancel)
+        );
+        let cause = assert_matches!(
+            resolution.result,
+            LocalActivityExecutionResult::Cancelled(Cancellation { failure: Some(f) }) => f.cause
+        );
+        let expected = if is_schedule {
+            TimeoutType::ScheduleToClose
+        } else {
+            TimeoutType::StartToClose
+        };
+        assert_matches!(
+            cause.and_then(|c| c.failure_info),
+            Some(FailureInfo::TimeoutFailureInfo(TimeoutFailureInfo { timeout_type, .. }))
+                if timeout_type == expected as i32
+        );
+        assert_eq!(cancel.task_token, start.task_token);
+        assert_matches!(
+            cancel.variant,
+            Some(activity_task::Variant::Cancel(Cancel { reason, .. }))
+                if reason == ActivityCancelReason::TimedOut as i32
+        );
+        assert!(lam.next_pending().now_or_never().is_none());
+        assert_eq!(lam.num_outstanding(), 0);
+        assert_matches!(
+            lam.complete(
+                &TaskToken(cancel.task_token),
+                LocalActivityExecutionResult::empty_cancel(),
+            ),
+            LACompleteAction::Untracked
+        );
+        lam.drain_and_assert_clean().await;
+    }
+
+    #[tokio::test]
+    async fn unreported_timeout_at_enqueue_resolves_as_cancelled() {
+        let lam = LocalActivityManager::test(1);
+        let res = lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                original_schedule_time: Some(SystemTime::now() - Duration::from_secs(10)),
+                close_timeouts: Some(LACloseTimeouts::ScheduleOnly(Duration::from_secs(1))),
+                report_timeouts: Some(false),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+        .into()]);
+        assert_matches!(
+            res.immediate_resolutions.as_slice(),
+            [LocalActivityResolution {
+                result: LocalActivityExecutionResult::Cancelled(_),
+                ..
+            }]
+        );
+        assert_eq!(lam.num_outstanding(), 0);
+        lam.drain_and_assert_clean().await;
+    }
+
+    #[rstest::rstest]
+    #[case::reported(true)]
+    #[case::unreported(false)]
+    #[tokio::test(start_paused = true)]
+    async fn schedule_to_close_during_local_backoff(#[case] report_timeouts: bool) {
+        let lam = LocalActivityManager::test(1);
+        lam.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_id: 1.to_string(),
+                attempt: 2,
+                retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                    initial_interval: Some(prost_dur!(from_secs(10))),
+                    backoff_coefficient: 1.0,
+                    maximum_interval: Some(prost_dur!(from_secs(10))),
+                    maximum_attempts: 10,
+                    non_retryable_error_types: vec![],
+                }),
+                local_retry_threshold: Duration::from_secs(500),
+                close_timeouts: Some(LACloseTimeouts::ScheduleOnly(Duration::from_secs(5))),
+                report_timeouts: Some(report_timeouts),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+        .into()]);
+        let next = lam.next_pending().await.unwrap().unwrap();
+        lam.complete(
+            &TaskToken(next.task_token),
+            LocalActivityExecutionResult::Failed(Default::default()),
+        );
+        assert_eq!(lam.num_in_backoff(), 1);
+
+        let resolved = tokio::select! {
+            next = lam.next_pending() => next,
+            _ = sleep(Duration::from_secs(20)) => None,
+        };
+        if report_timeouts {
+            let res = assert_matches!(
+                resolved,
+                Some(NextPendingLAAction::Autocomplete(LACompleteAction::Report {
+                    resolution, ..
+                })) => resolution
+            );
+            assert_eq!(
+                res.result.get_timeout_type(),
+                Some(TimeoutType::ScheduleToClose)
+            );
+            // The attempt which failed before the backoff, not the one which never started
+            assert_eq!(res.attempt, 2);
+        } else {
+            // The workflow still has to hear the activity is over
+            assert_matches!(
+                resolved,
+                Some(NextPendingLAAction::Autocomplete(
+                    LACompleteAction::Report {
+                        resolution: LocalActivityResolution {
+                            result: LocalActivityExecutionResult::Cancelled(_),
+                            ..
+                        },
+                        ..
+                    }
+                ))
+            );
+        }
+        // Either way the retry must never be dispatched
+        assert_eq!(lam.num_in_backoff(), 0);
+        assert!(lam.next_pending().now_or_never().is_none());
+        lam.drain_and_assert_clean().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn run_metrics_aggregate_per_run() {
+        let lam = LocalActivityManager::test(5);
+        lam.enqueue(
+            [(1, "run_a"), (2, "run_a"), (3, "run_b")].map(|(seq, run_id)| {
+                NewLocalAct {
+                    schedule_cmd: ValidScheduleLA {
+                        seq,
+                        activity_id: seq.to_string(),
+                        retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                            initial_interval: Some(prost_dur!(from_millis(50))),
+                            backoff_coefficient: 1.0,
+                            ..Default::default()
+                        }),
+                        local_retry_threshold: Duration::from_secs(500),
+                        ..Default::default()
+                    },
+                    workflow_type: "".to_string(),
+                    workflow_exec_info: WorkflowExecution {
+                        workflow_id: "".to_string(),
+                        run_id: run_id.to_string(),
+                    },
+                    schedule_time: SystemTime::now(),
+                    ..Default::default()
+                }
+                .into()
+            }),
+        );
+        let mut tokens = HashMap::new();
+        let next_start = async |tokens: &mut HashMap<_, _>| {
+            let task = lam.next_pending().await.unwrap().unwrap();
+            let Some(activity_task::Variant::Start(start)) = task.variant else {
+                panic!("Expected a start task");
+            };
+            tokens.insert(start.activity_id, TaskToken(task.task_token));
+        };
+        for _ in 1..=3 {
+            next_start(&mut tokens).await;
+        }
+        let complete = |tokens: &HashMap<String, TaskToken>, seq: u32, success: bool| {
+            lam.complete(
+                &tokens[&seq.to_string()],
+                if success {
+                    LocalActivityExecutionResult::Completed(Default::default())
+                } else {
+                    LocalActivityExecutionResult::Failed(Default::default())
+                },
+            );
+        };
+        complete(&tokens, 3, true);
+        assert_eq!(
+            lam.run_metrics("run_b"),
+            RunLaMetrics {
+                outstanding: 0,
+                dispatched: 1,
+                retried: 0,
+                backoff_time: Duration::ZERO,
+            }
+        );
+
+        complete(&tokens, 1, false);
+        complete(&tokens, 2, false);
+        assert_eq!(
+            lam.run_metrics("run_a"),
+            RunLaMetrics {
+                outstanding: 0,
+                dispatched: 2,
+                retried: 2,
+                backoff_time: Duration::ZERO,
+            }
+        );
+        next_start(&mut tokens).await;
+        next_start(&mut tokens).await;
+        complete(&tokens, 1, false);
+        next_start(&mut tokens).await;
+        complete(&tokens, 2, true);
+
+        let metrics = lam.run_metrics("run_a");
+        assert_eq!(metrics.outstanding, 1);
+        assert_eq!(metrics.dispatched, 5);
+        assert_eq!(metrics.retried, 3);
+        assert_eq!(metrics.backoff_time, Duration::from_millis(150));
+
+        // Resolving the run's last activity doesn't drop its counters, since the workflow may yet
+        // schedule more
+        complete(&tokens, 1, true);
+        assert_eq!(lam.run_metrics("run_a").dispatched, 5);
+        // The run going away does
+        for run_id in ["run_a", "run_b"] {
+            lam.enqueue([LocalActRequest::CancelAllInRun(run_id.to_string())]);
+            assert_eq!(lam.run_metrics(run_id), RunLaMetrics::default());
+        }
+        assert!(lam.dat.lock().run_metrics.is_empty());
+        lam.drain_and_assert_clean().await;
+    }
+
+    #[tokio::test]
+    async fn closed_channels_are_tolerated() {
+        let lam = LocalActivityManager::test(5);
+        let new_la = |seq: u32| {
+            NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq,
+                    activity_id: seq.to_string(),
+                    retry_policy: ValidatedRetryPolicy::from_proto_with_defaults(RetryPolicy {
+                        initial_interval: Some(prost_dur!(from_millis(10))),
+                        ..Default::default()
+                    }),
+                    local_retry_threshold: Duration::from_secs(500),
+                    ..Default::default()
+                },
+                workflow_type: "".to_string(),
+                workflow_exec_info: WorkflowExecution {
+                    workflow_id: "".to_string(),
+                    run_id: "run_id".to_string(),
+                },
+                schedule_time: SystemTime::now(),
+                ..Default::default()
+            }
+            .into()
+        };
+        let id = |seq_num| ExecutingLAId {
+            run_id: "run_id".to_string(),
+            seq_num,
+        };
+        lam.enqueue([new_la(1), new_la(2)]);
+        let mut tokens = HashMap::new();
+        for _ in 1..=2 {
+            let task = lam.next_pending().await.unwrap().unwrap();
+            let Some(activity_task::Variant::Start(start)) = task.variant else {
+                panic!("Expected a start task");
+            };
+            tokens.insert(start.activity_id, TaskToken(task.task_token));
+        }
+        lam.close_receivers().await;
+
+        // Nothing can be queued any more, but none of these may panic
+        lam.enqueue([new_la(3)]);
+        // Nothing would ever dispatch it, so it isn't left tracked
+        assert!(!lam.dat.lock().la_info.contains_key(&id(3)));
+        lam.enqueue([LocalActRequest::Cancel(id(1))]);
+        assert!(lam.pending_cancels().is_empty());
+        assert_matches!(
+            lam.complete(
+                &tokens["2"],
+                LocalActivityExecutionResult::Failed(Default::default())
+            ),
+            LACompleteAction::WillBeRetried(_)
+        );
+        let backoff = lam
+            .dat
+            .lock()
+            .la_info
+            .get_mut(&id(2))
+            .and_then(|lai| lai.backing_off_task.take())
+            .unwrap();
+        // The backoff task's send of the retry is dropped rather than panicking
+        tokio::time::timeout(Duration::from_secs(5), backoff.handle)
+            .await
+            .unwrap()
+            .unwrap();
+    }
 }
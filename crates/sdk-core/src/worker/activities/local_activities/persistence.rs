@@ -0,0 +1,145 @@
+use super::{ExecutingLAId, LocalActivityManager, NewLocalAct};
+use crate::TaskToken;
+use parking_lot::Mutex;
+use std::{collections::HashMap, time::SystemTime};
+use tokio::sync::oneshot;
+
+/// Storage for the local activity bookkeeping that should survive a worker restart. Calls are
+/// made in order from a task of their own, never while the manager's internal lock is held, so
+/// implementations are free to do IO.
+pub trait LaStateStore: Send + Sync {
+    /// The activity is tracked and is now in the given state. `next_token_num` must be restored
+    /// so that tokens issued after a restart can't be confused with ones lang may still hold.
+    fn record(&self, id: &ExecutingLAId, state: PersistedLa, next_token_num: u32);
+    /// The activity is no longer tracked
+    fn remove(&self, id: &ExecutingLAId);
+    /// Everything recorded and not since removed. Called once, when the manager is created.
+    fn load(&self) -> PersistedLaState;
+}
+
+/// Everything a [LaStateStore] holds
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PersistedLaState {
+    /// The highest `next_token_num` recorded
+    pub next_token_num: u32,
+    /// The latest recorded state of each tracked activity
+    pub activities: HashMap<ExecutingLAId, PersistedLa>,
+}
+
+/// The recorded state of one tracked local activity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistedLa {
+    /// The token the activity is tracked by
+    pub task_token: TaskToken,
+    /// The attempt which is queued or executing, or which failed if backing off
+    pub attempt: u32,
+    /// What the activity is doing
+    pub phase: PersistedLaPhase,
+}
+
+/// Where a tracked local activity is in its lifecycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PersistedLaPhase {
+    /// Waiting to be dispatched
+    Queued,
+    /// Handed to lang
+    InFlight,
+    /// Waiting to retry after an attempt failed
+    BackingOff {
+        /// When the next attempt is due
+        retry_at: SystemTime,
+    },
+}
+
+impl PersistedLa {
+    /// Picks the re-scheduled activity up from where it was before the restart. Returns the token
+    /// to track it with, or None if it was executing and isn't safe to run again.
+    pub(super) fn resume(self, act: &mut NewLocalAct, fresh_token: TaskToken) -> Option<TaskToken> {
+        let sa = &mut act.schedule_cmd;
+        if sa.attempt > self.attempt {
+            // Lang already moved past what was recorded, ex: via a timer backoff
+            return Some(fresh_token);
+        }
+        match self.phase {
+            PersistedLaPhase::Queued => {
+                sa.attempt = self.attempt;
+                Some(self.task_token)
+            }
+            // The interrupted attempt may have had effects which must not happen twice
+            PersistedLaPhase::InFlight if sa.non_idempotent => None,
+            // Lang may still be running the interrupted attempt, so the next one needs its own
+            // token
+            PersistedLaPhase::InFlight => {
+                sa.attempt = self.attempt + 1;
+                Some(fresh_token)
+            }
+            PersistedLaPhase::BackingOff { retry_at } => {
+                sa.attempt = self.attempt + 1;
+                sa.start_delay = retry_at
+                    .duration_since(SystemTime::now())
+                    .ok()
+                    .filter(|d| !d.is_zero());
+                Some(self.task_token)
+            }
+        }
+    }
+}
+
+/// A change to apply to the [LaStateStore]. See [LocalActivityManager::persist].
+pub(super) enum LaStoreOp {
+    Record(ExecutingLAId, PersistedLa, u32),
+    Remove(ExecutingLAId),
+    Flush(oneshot::Sender<()>),
+}
+
+/// A [LaStateStore] which only lives as long as the process does. Activities survive the worker
+/// being replaced, but not the process restarting.
+#[derive(Default)]
+pub struct InMemoryLaStateStore {
+    state: Mutex<PersistedLaState>,
+}
+
+impl LaStateStore for InMemoryLaStateStore {
+    fn record(&self, id: &ExecutingLAId, state: PersistedLa, next_token_num: u32) {
+        let mut s = self.state.lock();
+        s.next_token_num = s.next_token_num.max(next_token_num);
+        s.activities.insert(id.clone(), state);
+    }
+
+    fn remove(&self, id: &ExecutingLAId) {
+        self.state.lock().activities.remove(id);
+    }
+
+    fn load(&self) -> PersistedLaState {
+        self.state.lock().clone()
+    }
+}
+
+impl LocalActivityManager {
+    /// Queues up a change to the activity's persisted state, if there is a [LaStateStore]. Called
+    /// under `dat`'s lock so the changes reach the store in the order they happened.
+    pub(super) fn persist(&self, id: &ExecutingLAId, la: PersistedLa, next_token_num: u32) {
+        if let Some(tx) = self.state_store_tx.as_ref() {
+            let _ = tx.send(LaStoreOp::Record(id.clone(), la, next_token_num));
+        }
+    }
+
+    /// Queues up removal of the activity's persisted state, if there is a [LaStateStore]
+    pub(super) fn unpersist(&self, id: &ExecutingLAId) {
+        if let Some(tx) = self.state_store_tx.as_ref() {
+            let _ = tx.send(LaStoreOp::Remove(id.clone()));
+        }
+    }
+
+    /// Resolves once every change to persisted state made before this was called has been applied
+    /// to the [LaStateStore]
+    pub(crate) async fn flush_state_store(&self) {
+        let Some(tx) = self.state_store_tx.as_ref() else {
+            return;
+        };
+        let (done_tx, done_rx) = oneshot::channel();
+        if tx.send(LaStoreOp::Flush(done_tx)).is_ok() {
+            let _ = done_rx.await;
+        }
+    }
+}
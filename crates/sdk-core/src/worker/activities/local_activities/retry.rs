@@ -0,0 +1,295 @@
+use super::{
+    ExecutingLAId, LAMData, LaEvent, LocalActivityError, LocalActivityExecutionResult,
+    LocalActivityInfo, LocalActivityManager, LocalBackoff, NewLocalAct, NewOrRetry, PersistedLa,
+    PersistedLaPhase, RetryingAttemptFailure,
+};
+use crate::{TaskToken, protosext::ValidScheduleLA, retry_logic::ValidatedRetryPolicy};
+use rand::Rng;
+use std::{
+    num::NonZero,
+    sync::{Arc, atomic::Ordering},
+    time::{Duration, Instant, SystemTime},
+};
+use temporalio_common::protos::temporal::api::{
+    common::v1::{Payload, RetryPolicy},
+    failure::v1::ApplicationFailureInfo,
+};
+
+/// Converts a backoff into the form lang receives it in
+pub(super) fn backoff_as_proto(
+    backoff: Duration,
+) -> Result<prost_types::Duration, LocalActivityError> {
+    backoff
+        .try_into()
+        .map_err(|_| LocalActivityError::BackoffOutOfRange(backoff))
+}
+
+/// The longest duration a proto duration may hold (about 10,000 years). Timer backoffs are never
+/// longer than this.
+pub(super) const MAX_PROTO_DURATION: Duration = Duration::from_secs(315_576_000_000);
+
+/// Computes how long to wait before retrying, given the attempt which just failed (starting at 1)
+/// and the activity's retry policy. Returning `None` stops retrying.
+pub type BackoffFn = Arc<dyn Fn(u32, &RetryPolicy) -> Option<Duration> + Send + Sync>;
+
+/// Spreads local retries out over a window after their backoff ends
+#[derive(Debug, Clone, Copy)]
+pub struct RetryDispatchJitter {
+    /// Extra waits are chosen uniformly between zero and this
+    pub window: Duration,
+    /// Seeds the random choices. Otherwise they are seeded from the OS.
+    pub seed: Option<u64>,
+}
+
+/// How the retry of a failed attempt waits out its backoff
+pub(super) enum RetryBackoff {
+    /// Lang waits it out with a timer, then schedules the next attempt itself. `None` if the
+    /// backoff can't be sent, in which case the failure is reported as final.
+    Timer(Option<prost_types::Duration>),
+    /// Core waits it out, then dispatches the next attempt. See
+    /// [LocalActivityManager::schedule_retry].
+    Local(Duration),
+}
+
+/// The next attempt at an activity, as sent for dispatch once its backoff has been waited out
+pub(super) struct PendingRetry {
+    pub(super) in_flight: NewLocalAct,
+    pub(super) failed_attempt: u32,
+    pub(super) heartbeat_details: Vec<Payload>,
+    /// Restarts the schedule-to-start window when the retry is sent
+    pub(super) reset_schedule_time: bool,
+}
+
+impl LocalActivityManager {
+    /// Replaces the retry policy of an outstanding local activity, so that the decision of whether
+    /// (and when) to retry its current attempt's failure uses the new policy. Errors if the activity
+    /// has no attempt currently executing, or the policy would be rejected by [Self::enqueue].
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn update_retry_policy(
+        &self,
+        id: &ExecutingLAId,
+        policy: RetryPolicy,
+    ) -> Result<(), LocalActivityError> {
+        let mut dlock = self.dat.lock();
+        let info = dlock
+            .outstanding_activity_tasks
+            .values_mut()
+            .find(|info| {
+                info.la_info.schedule_cmd.seq == id.seq_num
+                    && info.la_info.workflow_exec_info.run_id == id.run_id
+            })
+            .ok_or_else(|| LocalActivityError::NotOutstanding(id.clone()))?;
+        if let Some(err) = ValidatedRetryPolicy::malformed_reason(&policy) {
+            return Err(LocalActivityError::InvalidRetryPolicy(err));
+        }
+        debug!(id=?id, policy=?policy, "Updating local activity retry policy");
+        info.la_info.schedule_cmd.retry_policy =
+            ValidatedRetryPolicy::from_proto_with_defaults(policy);
+        Ok(())
+    }
+
+    /// How long to wait before retrying after the given attempt failed, if it should be retried
+    pub(super) fn retry_backoff(
+        &self,
+        policy: &ValidatedRetryPolicy,
+        attempt: u32,
+        application_failure: Option<&ApplicationFailureInfo>,
+    ) -> Option<Duration> {
+        let default_backoff = policy.should_retry(
+            attempt.try_into().unwrap_or(NonZero::<u32>::MIN),
+            application_failure,
+        )?;
+        match self.opts.backoff_fn.as_ref() {
+            Some(backoff_fn) => backoff_fn(attempt, &policy.clone().into()),
+            None => Some(default_backoff),
+        }
+    }
+
+    /// Whether the retry of an activity which has already been retried locally `local_retries`
+    /// times must wait out its backoff in a lang-side timer, rather than within core
+    pub(super) fn needs_timer_backoff(
+        &self,
+        sa: &ValidScheduleLA,
+        backoff: Duration,
+        local_retries: u32,
+    ) -> bool {
+        backoff > sa.local_retry_threshold
+            || sa.max_local_backoff.is_some_and(|max| backoff > max)
+            || self
+                .opts
+                .max_local_retries
+                .is_some_and(|max| local_retries >= max)
+    }
+
+    /// Decides how the retry of a failed attempt waits out `backoff`, applying the limits on
+    /// either kind of backoff
+    pub(super) fn backoff_for_retry(
+        &self,
+        id: &ExecutingLAId,
+        sa: &ValidScheduleLA,
+        backoff: Duration,
+        local_retries: u32,
+    ) -> RetryBackoff {
+        if self.needs_timer_backoff(sa, backoff, local_retries) {
+            RetryBackoff::Timer(self.timer_backoff(id, backoff))
+        } else {
+            RetryBackoff::Local(self.local_backoff(backoff))
+        }
+    }
+
+    /// Whether a failed attempt must not be retried, whatever its retry policy says.
+    /// `stopped_early` is whether the attempt timed out or was cut short.
+    pub(super) fn retries_forbidden(
+        &self,
+        id: &ExecutingLAId,
+        sa: &ValidScheduleLA,
+        lai: Option<&LocalActivityInfo>,
+        stopped_early: bool,
+    ) -> bool {
+        if lai.is_some_and(|lai| lai.retries_disabled) {
+            return true;
+        }
+        if sa.non_idempotent && stopped_early {
+            // Whatever the attempt did before it was stopped may have taken effect, so it isn't
+            // safe to run it again
+            debug!(
+                ?id,
+                "Not retrying non-idempotent local activity which was cut short"
+            );
+            return true;
+        }
+        false
+    }
+
+    /// Converts a backoff for lang to schedule a timer with, clamping it to what may be sent.
+    /// Returns `None` if it still can't be sent, in which case the failure must be reported as
+    /// final.
+    pub(super) fn timer_backoff(
+        &self,
+        id: &ExecutingLAId,
+        backoff: Duration,
+    ) -> Option<prost_types::Duration> {
+        let max = self
+            .opts
+            .max_timer_backoff
+            .map_or(MAX_PROTO_DURATION, |m| m.min(MAX_PROTO_DURATION));
+        if backoff > max {
+            // Any misconfigured retry policy is better served by a very long timer than by taking
+            // down the worker
+            warn!(
+                ?id,
+                "Local activity backoff of {backoff:?} is too long, clamping it to {max:?}"
+            );
+        }
+        // Reporting the failure as final beats panicking if the clamp is ever wrong
+        backoff_as_proto(backoff.min(max))
+            .inspect_err(|e| error!(?id, "{e}, giving up on retrying local activity"))
+            .ok()
+    }
+
+    /// Applies [LocalActivityManagerOptions::min_local_backoff] to a backoff which is about to be
+    /// waited out locally
+    pub(super) fn local_backoff(&self, backoff: Duration) -> Duration {
+        self.opts
+            .min_local_backoff
+            .map_or(backoff, |floor| backoff.max(floor))
+    }
+
+    /// Counts a failed attempt which will be retried, whether locally or after a timer backoff
+    pub(super) fn count_retry(&self, dat: &mut LAMData, run_id: &str) {
+        self.lifetime_counters
+            .retried
+            .fetch_add(1, Ordering::Relaxed);
+        dat.run_metrics_mut(run_id).retried += 1;
+    }
+
+    /// Bookkeeping for a failed attempt which is about to back off locally before being retried.
+    /// Returns the extra wait to add after the backoff. See
+    /// [LocalActivityManagerOptions::retry_dispatch_jitter].
+    pub(super) fn local_retry_scheduled(
+        &self,
+        dat: &mut LAMData,
+        id: &ExecutingLAId,
+        failed_attempt: u32,
+        result: &LocalActivityExecutionResult,
+        backoff: Duration,
+    ) -> Duration {
+        if let Some(tx) = self.opts.retrying_failures_tx.as_ref() {
+            // Nobody listening any more is no reason to interfere with retrying
+            let _ = tx.send(RetryingAttemptFailure {
+                id: id.clone(),
+                attempt: failed_attempt,
+                result: result.clone(),
+                backoff,
+            });
+        }
+        let jitter = self
+            .opts
+            .retry_dispatch_jitter
+            .as_ref()
+            .zip(dat.retry_jitter_rng.as_mut())
+            .map(|(j, rng)| j.window.mul_f64(rng.random()))
+            .unwrap_or_default();
+        self.emit(LaEvent::BackingOff {
+            id: id.clone(),
+            failed_attempt,
+            backoff: backoff + jitter,
+        });
+        self.count_retry(dat, &id.run_id);
+        jitter
+    }
+
+    /// Waits out a backoff within core, then sends the retry to be dispatched. The activity's
+    /// state is persisted as backing off under `task_token`. Returns the backoff for the caller to
+    /// track on the activity.
+    pub(super) fn schedule_retry(
+        &self,
+        dat: &mut LAMData,
+        id: &ExecutingLAId,
+        task_token: &TaskToken,
+        retry: PendingRetry,
+        result: &LocalActivityExecutionResult,
+        backoff: Duration,
+    ) -> LocalBackoff {
+        let PendingRetry {
+            mut in_flight,
+            failed_attempt,
+            heartbeat_details,
+            reset_schedule_time,
+        } = retry;
+        let jitter = self.local_retry_scheduled(dat, id, failed_attempt, result, backoff);
+        let req_tx = self.req_tx.clone();
+        let handle = self.spawner.spawn(async move {
+            tokio::time::sleep(backoff).await;
+            if !jitter.is_zero() {
+                tokio::time::sleep(jitter).await;
+            }
+            if reset_schedule_time {
+                in_flight.schedule_time = SystemTime::now();
+            }
+            req_tx.send(NewOrRetry::Retry {
+                in_flight,
+                attempt: failed_attempt + 1,
+                heartbeat_details,
+                attempt_scheduled_time: SystemTime::now(),
+            });
+        });
+        self.persist(
+            id,
+            PersistedLa {
+                task_token: task_token.clone(),
+                attempt: failed_attempt,
+                phase: PersistedLaPhase::BackingOff {
+                    retry_at: SystemTime::now() + backoff + jitter,
+                },
+            },
+            dat.next_tt_num,
+        );
+        LocalBackoff {
+            handle,
+            failed_attempt,
+            retry_at: Instant::now() + backoff + jitter,
+            started_at: tokio::time::Instant::now(),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use super::{ExecutingLAId, LAMData, LocalActivityManager, NewLocalAct};
+use std::collections::BTreeMap;
+
+/// Local activities of a run which must execute one at a time, in seq order
+#[derive(Default)]
+pub(super) struct SerializedRun {
+    /// The activity which currently has its turn. It keeps it while queued, executing, and backing
+    /// off between attempts, until it is resolved.
+    pub(super) active: Option<u32>,
+    /// Activities waiting for their turn, keyed by seq. They are already tracked (and their
+    /// schedule-to-close timeouts running), but haven't been sent to the dispatch queue.
+    pub(super) waiting: BTreeMap<u32, NewLocalAct>,
+}
+
+impl LocalActivityManager {
+    /// Makes the run's local activities execute one at a time, in seq order, regardless of the
+    /// order they are enqueued in or how many slots are free. An activity keeps its turn through
+    /// retries until it is resolved. Other runs are unaffected. Only applies to activities
+    /// enqueued after this is called, and lasts until the run's activities are all cancelled
+    /// (ex: on eviction or workflow completion).
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn serialize_run(&self, run_id: String) {
+        self.dat.lock().serialized_runs.entry(run_id).or_default();
+    }
+
+    /// If no activity of the serialized run has its turn, gives it to the lowest waiting seq
+    pub(super) fn advance_serialized_run(&self, dat: &mut LAMData, run_id: &str) {
+        let Some(sr) = dat.serialized_runs.get_mut(run_id) else {
+            return;
+        };
+        if sr.active.is_some() {
+            return;
+        }
+        while let Some((seq, act)) = sr.waiting.pop_first() {
+            let id = ExecutingLAId {
+                run_id: run_id.to_string(),
+                seq_num: seq,
+            };
+            // Anything no longer tracked was dealt with while it waited
+            if dat.la_info.contains_key(&id) {
+                sr.active = Some(seq);
+                self.submit_new(dat, &id, act);
+                return;
+            }
+        }
+    }
+
+    /// Stops serializing the run, sending anything still waiting for its turn along as usual
+    pub(super) fn stop_serializing_run(&self, run_id: &str) {
+        let mut dlock = self.dat.lock();
+        let dat = &mut *dlock;
+        let Some(sr) = dat.serialized_runs.remove(run_id) else {
+            return;
+        };
+        for (seq, act) in sr.waiting {
+            let id = ExecutingLAId {
+                run_id: run_id.to_string(),
+                seq_num: seq,
+            };
+            self.submit_new(dat, &id, act);
+        }
+    }
+}
@@ -0,0 +1,557 @@
+use super::{
+    CancelOrTimeout, EffectiveTimeouts, ExecutingLAId, LACompleteAction,
+    LocalActivityExecutionResult, LocalActivityManager, LocalActivityResolution, NewLocalAct,
+    NextPendingLAAction, TaskSpawner,
+};
+use crate::{TaskToken, protosext::LACloseTimeouts};
+use std::time::{Duration, Instant, SystemTime};
+use temporalio_common::protos::temporal::api::enums::v1::TimeoutType;
+use tokio::{sync::mpsc::UnboundedSender, task::JoinHandle, time::sleep};
+
+/// Describes the close timeouts of a local activity and whether their timers are running
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct TimeoutInfo {
+    /// The configured schedule-to-close timeout, if any
+    pub schedule_to_close: Option<Duration>,
+    /// True until the schedule-to-close timer fires, if there is one
+    pub schedule_to_close_active: bool,
+    /// The configured start-to-close timeout, if any
+    pub start_to_close: Option<Duration>,
+    /// True while an attempt is executing and its start-to-close timer hasn't fired
+    pub start_to_close_active: bool,
+}
+
+/// Why `Worker::extend_local_activity_start_to_close` refused an extension
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtendTimeoutError {
+    /// The activity isn't executing, or its attempt has no start-to-close timer left to extend
+    #[error("Local activity has no running start-to-close timeout")]
+    NoActiveStartToClose,
+    /// The extended start-to-close would outlast the schedule-to-close timeout, which can't move
+    #[error("Extended start-to-close timeout would exceed the schedule-to-close timeout")]
+    ExceedsScheduleToClose,
+}
+
+pub(crate) const DEFAULT_CLOCK_SKEW_TOLERANCE: Duration = Duration::from_secs(1);
+
+pub(super) struct TimeoutBag {
+    /// The schedule-to-close timeout as configured, for reporting
+    configured_sched_to_close: Option<Duration>,
+    /// The schedule-to-close timeout after subtracting time which had already passed
+    effective_sched_to_close: Option<Duration>,
+    /// Absent if there is no schedule-to-close timeout, or in replay mode
+    sched_to_close_handle: Option<JoinHandle<()>>,
+    /// Delivers the warning that the schedule-to-close timeout is about to fire, if requested
+    deadline_warning_handle: Option<JoinHandle<()>>,
+    /// Gives up on lang acknowledging a cancel of the current attempt. See
+    /// [LocalActivityManagerOptions::cancel_ack_timeout].
+    cancel_ack_handle: Option<JoinHandle<()>>,
+    /// What is delivered when the activity times out. Initially describes a schedule-to-close
+    /// timeout.
+    #[cfg(any(feature = "test-utilities", test))]
+    timeout_dat: CancelOrTimeout,
+    /// If true, no timers are run. See [LocalActivityManagerOptions::replay_mode].
+    replay: bool,
+    /// When the schedule-to-close timeout fires, if there is one
+    sched_to_close_deadline: Option<Instant>,
+    start_to_close_dur_and_dat: Option<(Duration, CancelOrTimeout)>,
+    start_to_close_handle: Option<JoinHandle<()>>,
+    /// How much the current attempt's start-to-close timeout has been extended by
+    start_to_close_extension: Duration,
+    started_at: Option<Instant>,
+    cancel_chan: TimeoutSink,
+    spawner: TaskSpawner,
+}
+
+/// Where timeout tasks deliver timeouts to
+#[derive(Clone)]
+pub(super) struct TimeoutSink(UnboundedSender<CancelOrTimeout>);
+
+impl TimeoutSink {
+    fn deliver(&self, dat: CancelOrTimeout) {
+        if self.0.send(dat).is_err() {
+            // Only possible once the manager itself is gone, so nobody cares about the timeout
+            debug!("Local activity timeout fired after its manager was dropped");
+        }
+    }
+
+    /// Returns false if the timeout couldn't be delivered
+    #[cfg(any(feature = "test-utilities", test))]
+    fn try_deliver(&self, dat: CancelOrTimeout) -> bool {
+        let delivered = self.0.send(dat).is_ok();
+        if !delivered {
+            warn!("Could not deliver local activity timeout, cancel channel is closed");
+        }
+        delivered
+    }
+}
+
+impl From<UnboundedSender<CancelOrTimeout>> for TimeoutSink {
+    fn from(tx: UnboundedSender<CancelOrTimeout>) -> Self {
+        TimeoutSink(tx)
+    }
+}
+
+impl TimeoutBag {
+    /// Create new timeout tasks for the provided local activity. This must be called as soon
+    /// as request to schedule it arrives. The schedule-to-close timeout is measured from
+    /// `sched_time`.
+    ///
+    /// Returns error in the event the activity is *already* timed out
+    #[allow(clippy::result_large_err)]
+    pub(super) fn new(
+        new_la: &NewLocalAct,
+        sched_time: SystemTime,
+        cancel_chan: TimeoutSink,
+        clock_skew_tolerance: Duration,
+        replay: bool,
+        spawner: TaskSpawner,
+    ) -> Result<TimeoutBag, LocalActivityResolution> {
+        let (schedule_to_close, start_to_close) = new_la
+            .schedule_cmd
+            .close_timeouts
+            .map(LACloseTimeouts::into_sched_and_start)
+            .unwrap_or_default();
+        let configured_sched_to_close = schedule_to_close;
+
+        let mut resolution = LocalActivityResolution {
+            seq: new_la.schedule_cmd.seq,
+            result: LocalActivityExecutionResult::timeout(TimeoutType::ScheduleToClose),
+            runtime: Default::default(),
+            attempt: new_la.schedule_cmd.attempt,
+            backoff: None,
+            original_schedule_time: new_la.schedule_cmd.original_schedule_time,
+            correlation_id: new_la.correlation_id.clone(),
+            effective_timeouts: Default::default(),
+        };
+        let elapsed = sched_time.elapsed().unwrap_or_else(|e| {
+            if e.duration() > clock_skew_tolerance {
+                warn!(skew=?e.duration(), seq=%new_la.schedule_cmd.seq,
+                      "Local activity schedule time is in the future, clocks may be skewed");
+            }
+            Duration::ZERO
+        });
+        // Remove any time already elapsed since the scheduling time
+        let schedule_to_close = schedule_to_close.map(|s2c| s2c.saturating_sub(elapsed));
+        let start_delay = new_la.schedule_cmd.start_delay.unwrap_or_default();
+        // An absolute deadline is already fixed in time, so the start delay eats into it
+        let until_deadline = new_la.schedule_cmd.deadline.map(|d| {
+            d.duration_since(SystemTime::now())
+                .unwrap_or_default()
+                .saturating_sub(start_delay)
+        });
+        let schedule_to_close = match (schedule_to_close, until_deadline) {
+            (Some(s2c), Some(until_deadline)) => Some(s2c.min(until_deadline)),
+            (s2c, until_deadline) => s2c.or(until_deadline),
+        };
+        let effective_sched_to_close = schedule_to_close;
+        resolution.effective_timeouts = EffectiveTimeouts {
+            schedule_to_close,
+            start_to_close,
+        };
+        // When replaying, history already says whether this timed out
+        if !replay
+            && let Some(ref s2c) = schedule_to_close
+            && s2c.is_zero()
+        {
+            return Err(resolution);
+        }
+        let schedule_to_close = schedule_to_close.map(|s2c| s2c + start_delay);
+        let timeout_dat = CancelOrTimeout::Timeout {
+            run_id: new_la.workflow_exec_info.run_id.clone(),
+            resolution,
+        };
+        let sched_to_close_deadline = schedule_to_close.map(|s2c| Instant::now() + s2c);
+        let start_to_close_dur_and_dat = start_to_close.map(|d| (d, timeout_dat.clone()));
+
+        let sched_to_close_handle = schedule_to_close.filter(|_| !replay).map(|timeout| {
+            let cancel_chan = cancel_chan.clone();
+            let dat = timeout_dat.clone();
+            spawner.spawn(async move {
+                sleep(timeout).await;
+                cancel_chan.deliver(dat);
+            })
+        });
+        Ok(TimeoutBag {
+            configured_sched_to_close,
+            effective_sched_to_close,
+            sched_to_close_handle,
+            deadline_warning_handle: None,
+            cancel_ack_handle: None,
+            #[cfg(any(feature = "test-utilities", test))]
+            timeout_dat,
+            replay,
+            sched_to_close_deadline,
+            start_to_close_dur_and_dat,
+            start_to_close_handle: None,
+            start_to_close_extension: Duration::ZERO,
+            started_at: None,
+            cancel_chan,
+            spawner,
+        })
+    }
+
+    /// Arranges for a warning to be delivered `lead` before the schedule-to-close timeout fires,
+    /// or right away if that's sooner. Does nothing without a running schedule-to-close timer.
+    pub(super) fn warn_before_deadline(&mut self, lead: Duration, id: ExecutingLAId) {
+        let Some(deadline) = self
+            .sched_to_close_deadline
+            .filter(|_| self.sched_to_close_handle.is_some())
+        else {
+            return;
+        };
+        let cchan = self.cancel_chan.clone();
+        self.deadline_warning_handle = Some(self.spawner.spawn(async move {
+            sleep(
+                deadline
+                    .saturating_duration_since(Instant::now())
+                    .saturating_sub(lead),
+            )
+            .await;
+            cchan.deliver(CancelOrTimeout::DeadlineApproaching(id));
+        }));
+    }
+
+    /// Must be called once lang has been sent a cancel for the current attempt. If the attempt
+    /// isn't completed within `window`, that is delivered so it can be resolved on lang's behalf.
+    pub(super) fn await_cancel_ack(&mut self, window: Duration, task_token: TaskToken) {
+        let cchan = self.cancel_chan.clone();
+        self.cancel_ack_handle = Some(self.spawner.spawn(async move {
+            sleep(window).await;
+            cchan.deliver(CancelOrTimeout::CancelAckTimeout(task_token));
+        }));
+    }
+
+    /// Must be called once the associated local activity has been started / dispatched to lang.
+    pub(super) fn mark_started(&mut self) {
+        let started_t = Instant::now();
+        self.started_at = Some(started_t);
+        self.start_to_close_extension = Duration::ZERO;
+        if self.replay {
+            return;
+        }
+        if let Some((start_to_close, dat)) = self.start_to_close_dur_and_dat.as_ref().cloned() {
+            self.start_to_close_handle =
+                Some(self.spawn_start_to_close(start_to_close, dat, started_t));
+        }
+    }
+
+    fn spawn_start_to_close(
+        &self,
+        fire_in: Duration,
+        dat: CancelOrTimeout,
+        started_t: Instant,
+    ) -> JoinHandle<()> {
+        let cchan = self.cancel_chan.clone();
+        self.spawner.spawn(async move {
+            sleep(fire_in).await;
+            cchan.deliver(Self::start_to_close_dat(dat, started_t));
+        })
+    }
+
+    /// Pushes the running start-to-close timer back by `additional`. Only lasts for the current
+    /// attempt.
+    #[cfg(any(feature = "test-utilities", test))]
+    fn extend_start_to_close(&mut self, additional: Duration) -> Result<(), ExtendTimeoutError> {
+        let (Some(started_t), Some((start_to_close, dat)), Some(handle)) = (
+            self.started_at,
+            self.start_to_close_dur_and_dat.as_ref(),
+            self.start_to_close_handle.as_ref(),
+        ) else {
+            return Err(ExtendTimeoutError::NoActiveStartToClose);
+        };
+        if handle.is_finished() {
+            return Err(ExtendTimeoutError::NoActiveStartToClose);
+        }
+        let extension = self.start_to_close_extension + additional;
+        let deadline = started_t + *start_to_close + extension;
+        if self
+            .sched_to_close_deadline
+            .is_some_and(|s2c_deadline| deadline > s2c_deadline)
+        {
+            return Err(ExtendTimeoutError::ExceedsScheduleToClose);
+        }
+        handle.abort();
+        let mut dat = dat.clone();
+        if let CancelOrTimeout::Timeout { resolution, .. } = &mut dat {
+            resolution.effective_timeouts.start_to_close = Some(*start_to_close + extension);
+        }
+        self.start_to_close_extension = extension;
+        self.start_to_close_handle = Some(self.spawn_start_to_close(
+            deadline.saturating_duration_since(Instant::now()),
+            dat,
+            started_t,
+        ));
+        Ok(())
+    }
+
+    pub(super) fn effective(&self) -> EffectiveTimeouts {
+        EffectiveTimeouts {
+            schedule_to_close: self.effective_sched_to_close,
+            start_to_close: self
+                .start_to_close_dur_and_dat
+                .as_ref()
+                .map(|(d, _)| *d + self.start_to_close_extension),
+        }
+    }
+
+    /// Must be called once the current attempt has completed, since its start-to-close timeout
+    /// no longer applies.
+    pub(super) fn attempt_finished(&mut self) {
+        if let Some(h) = self.start_to_close_handle.take() {
+            h.abort();
+        }
+        if let Some(h) = self.cancel_ack_handle.take() {
+            h.abort();
+        }
+    }
+
+    pub(super) fn info(&self) -> TimeoutInfo {
+        let start_to_close = self.start_to_close_dur_and_dat.as_ref().map(|(d, _)| *d);
+        TimeoutInfo {
+            schedule_to_close: self.configured_sched_to_close,
+            schedule_to_close_active: self
+                .sched_to_close_handle
+                .as_ref()
+                .is_some_and(|h| !h.is_finished()),
+            start_to_close,
+            start_to_close_active: self
+                .start_to_close_handle
+                .as_ref()
+                .is_some_and(|h| !h.is_finished()),
+        }
+    }
+
+    fn start_to_close_dat(mut dat: CancelOrTimeout, started_t: Instant) -> CancelOrTimeout {
+        if let CancelOrTimeout::Timeout { resolution, .. } = &mut dat {
+            resolution.result = LocalActivityExecutionResult::timeout(TimeoutType::StartToClose);
+            resolution.runtime = started_t.elapsed();
+        }
+        dat
+    }
+
+    /// Sends whichever timeout would fire first given the current state of the activity, without
+    /// waiting for it. `executing` says whether an attempt is currently running.
+    #[cfg(any(feature = "test-utilities", test))]
+    fn force_fire(&mut self, executing: bool) {
+        if executing && self.start_to_close_dur_and_dat.is_some() {
+            self.fire(TimeoutType::StartToClose);
+        } else if self.effective_sched_to_close.is_some() {
+            self.fire(TimeoutType::ScheduleToClose);
+        }
+    }
+
+    /// Sends a timeout of the given type right away, whether or not it is configured or its timer
+    /// is running. Once it is delivered, the real timer for that type is cancelled so it can't fire
+    /// a second time - the others keep running, since the activity may yet be retried. Returns
+    /// false for types which don't apply to local activities, or if it couldn't be delivered.
+    #[cfg(any(feature = "test-utilities", test))]
+    fn fire(&mut self, timeout_type: TimeoutType) -> bool {
+        let dat = match timeout_type {
+            TimeoutType::StartToClose => Self::start_to_close_dat(
+                self.timeout_dat.clone(),
+                self.started_at.unwrap_or_else(Instant::now),
+            ),
+            TimeoutType::ScheduleToClose => self.timeout_dat.clone(),
+            TimeoutType::ScheduleToStart => {
+                let mut dat = self.timeout_dat.clone();
+                if let CancelOrTimeout::Timeout { resolution, .. } = &mut dat {
+                    resolution.result = LocalActivityExecutionResult::timeout(timeout_type);
+                }
+                dat
+            }
+            _ => return false,
+        };
+        if !self.cancel_chan.try_deliver(dat) {
+            return false;
+        }
+        let fired = match timeout_type {
+            TimeoutType::StartToClose => [self.start_to_close_handle.as_ref(), None],
+            TimeoutType::ScheduleToClose => [
+                self.sched_to_close_handle.as_ref(),
+                self.deadline_warning_handle.as_ref(),
+            ],
+            _ => [None, None],
+        };
+        for h in fired.into_iter().flatten() {
+            h.abort();
+        }
+        true
+    }
+
+    fn abort_timers(&self) {
+        if let Some(h) = self.sched_to_close_handle.as_ref() {
+            h.abort();
+        }
+        if let Some(h) = self.deadline_warning_handle.as_ref() {
+            h.abort();
+        }
+        if let Some(h) = self.start_to_close_handle.as_ref() {
+            h.abort();
+        }
+        if let Some(h) = self.cancel_ack_handle.as_ref() {
+            h.abort();
+        }
+    }
+}
+
+impl Drop for TimeoutBag {
+    fn drop(&mut self) {
+        self.abort_timers();
+    }
+}
+
+impl LocalActivityManager {
+    /// Returns the configured close timeouts for the activity and whether their timers are
+    /// currently running
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn timeout_info(&self, id: &ExecutingLAId) -> Option<TimeoutInfo> {
+        self.dat
+            .lock()
+            .la_info
+            .get(id)
+            .and_then(|lai| lai.timeout_bag.as_ref())
+            .map(TimeoutBag::info)
+    }
+
+    /// Resolves an activity whose timeout fired, or fails its current attempt if it is a
+    /// start-to-close timeout that the retry policy allows retrying
+    pub(super) fn handle_timeout(
+        &self,
+        run_id: String,
+        mut resolution: LocalActivityResolution,
+    ) -> Option<NextPendingLAAction> {
+        let id = ExecutingLAId {
+            run_id,
+            seq_num: resolution.seq,
+        };
+        let mut dlock = self.dat.lock();
+        // No longer tracked means it already resolved, so there's nothing to time out
+        let lai = dlock.la_info.get(&id)?;
+        let report_timeouts = lai.report_timeouts;
+        let task_token = lai.task_token.clone();
+        // A start-to-close timeout only ever applies to the attempt it was started for, so one
+        // arriving after that attempt finished is stale
+        let applies_between_attempts =
+            resolution.result.get_timeout_type() != Some(TimeoutType::StartToClose);
+        let action = if !dlock.outstanding_activity_tasks.contains_key(&task_token)
+            && applies_between_attempts
+        {
+            // Queued or backing off, so there is no attempt to fail. It must be resolved here, or
+            // the next attempt would be dispatched as though the activity never timed out.
+            let lai = dlock.la_info.remove(&id)?;
+            if let Some(b) = lai.backing_off_task {
+                b.handle.abort();
+                resolution.attempt = b.failed_attempt;
+            }
+            resolution.correlation_id = lai.correlation_id;
+            self.activity_resolved(&mut dlock, &id, &resolution);
+            LACompleteAction::Report {
+                run_id: id.run_id,
+                resolution,
+                task: None,
+            }
+        } else {
+            drop(dlock);
+            match self.complete(&task_token, resolution.result) {
+                // The attempt finished before the timeout got here, so there is nothing to time
+                // out or cancel any more.
+                LACompleteAction::Untracked => return None,
+                action => action,
+            }
+        };
+        match action {
+            LACompleteAction::Report {
+                run_id,
+                mut resolution,
+                task,
+            } if !report_timeouts
+                && resolution.backoff.is_none()
+                && matches!(
+                    resolution.result.get_timeout_type(),
+                    Some(TimeoutType::StartToClose | TimeoutType::ScheduleToClose)
+                ) =>
+            {
+                debug!(
+                    seq = resolution.seq,
+                    "Not reporting local activity timeout to workflow"
+                );
+                resolution.result = self.unreported_timeout(resolution.result);
+                // Lang may still be running the attempt, so it must still be told to stop
+                Some(NextPendingLAAction::Autocomplete(
+                    LACompleteAction::Report {
+                        run_id,
+                        resolution,
+                        task,
+                    },
+                ))
+            }
+            action => Some(NextPendingLAAction::Autocomplete(action)),
+        }
+    }
+
+    /// Gives the executing attempt of the activity `additional` more time before its start-to-close
+    /// timeout fires, for activities which only find out how long they need once running. The
+    /// schedule-to-close timeout can't be extended, so extensions past it are rejected.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn extend_start_to_close(
+        &self,
+        task_token: &TaskToken,
+        additional: Duration,
+    ) -> Result<(), ExtendTimeoutError> {
+        let mut dlock = self.dat.lock();
+        let dat = &mut *dlock;
+        let info = dat
+            .outstanding_activity_tasks
+            .get(task_token)
+            .ok_or(ExtendTimeoutError::NoActiveStartToClose)?;
+        let id = ExecutingLAId {
+            run_id: info.la_info.workflow_exec_info.run_id.clone(),
+            seq_num: info.la_info.schedule_cmd.seq,
+        };
+        dat.la_info
+            .get_mut(&id)
+            .and_then(|lai| lai.timeout_bag.as_mut())
+            .ok_or(ExtendTimeoutError::NoActiveStartToClose)?
+            .extend_start_to_close(additional)
+    }
+
+    /// Times out the activity as though the given timer had fired. Used to feed in timeouts which
+    /// are already recorded in history when in [replay mode](LocalActivityManagerOptions::replay_mode),
+    /// so that they resolve exactly as they did live. Returns false if the activity isn't known or
+    /// the timeout type doesn't apply to it: start-to-close needs an executing attempt, and
+    /// schedule-to-start one which hasn't started yet.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn force_timeout(&self, id: &ExecutingLAId, timeout_type: TimeoutType) -> bool {
+        let mut dlock = self.dat.lock();
+        let dat = &mut *dlock;
+        let Some(lai) = dat.la_info.get_mut(id) else {
+            return false;
+        };
+        let executing = dat.outstanding_activity_tasks.contains_key(&lai.task_token);
+        let applies = match timeout_type {
+            TimeoutType::StartToClose => executing,
+            TimeoutType::ScheduleToStart => !executing,
+            _ => true,
+        };
+        applies
+            && lai
+                .timeout_bag
+                .as_mut()
+                .is_some_and(|tb| tb.fire(timeout_type))
+    }
+
+    /// Fires every pending timeout immediately, as though its timer had elapsed, and cancels the
+    /// real timers.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub(crate) fn force_timeouts(&self) {
+        let mut dlock = self.dat.lock();
+        let dat = &mut *dlock;
+        for lai in dat.la_info.values_mut() {
+            let executing = dat.outstanding_activity_tasks.contains_key(&lai.task_token);
+            if let Some(tb) = lai.timeout_bag.as_mut() {
+                tb.force_fire(executing);
+            }
+        }
+    }
+}
@@ -27,10 +27,19 @@ pub use tuner::{
 pub use tuner::TunerHolderOptionsBuilder;
 pub(crate) use tuner::{RealSysInfo, SystemResourceInfo};
 
-pub(crate) use activities::{
-    ExecutingLAId, LocalActRequest, LocalActivityExecutionResult, LocalActivityResolution,
-    NewLocalAct,
+pub use activities::{
+    AttemptDispatchHook, BackingOffLaDiagnostics, BackoffFn, CircuitBreakerOptions,
+    ContextPropagator, DefaultFailureClassifier, DispatchRateLimit, ExecutingLAId,
+    ExtendTimeoutError, FailureClassification, FailureClassifier, InMemoryLaStateStore,
+    LaDiagnostics, LaEvent, LaLifetimeStats, LaStateStore, LaTimeoutDiagnostics,
+    LocalActivityError, LocalActivityExecutionResult, LocalActivityManagerOptions, LocalExecutor,
+    NoopContextPropagator, OutstandingLaDiagnostics, PayloadRedactor, PersistedLa,
+    PersistedLaPhase, PersistedLaState, ResultInterceptor, RetryDispatchJitter,
+    RetryingAttemptFailure, RunLaMetrics, TimeoutInfo, UnknownTokenPolicy,
 };
+#[cfg(any(feature = "test-utilities", test))]
+pub use activities::{ChaosConfig, DispatchObserver, LocalActivityProgress};
+pub(crate) use activities::{LocalActRequest, LocalActivityResolution, NewLocalAct};
 pub(crate) use wft_poller::WFTPollerShared;
 
 #[allow(unreachable_pub)] // re-exported in test_help::integ_helpers
@@ -109,9 +118,13 @@ use {
         protosext::ValidPollWFTQResponse,
     },
     futures_util::stream::BoxStream,
-    temporalio_common::protos::temporal::api::workflowservice::v1::{
-        PollActivityTaskQueueResponse, PollNexusTaskQueueResponse,
+    temporalio_common::protos::temporal::api::{
+        common::v1::{Payload, RetryPolicy},
+        enums::v1::TimeoutType,
+        failure::v1::Failure,
+        workflowservice::v1::{PollActivityTaskQueueResponse, PollNexusTaskQueueResponse},
     },
+    tokio::sync::broadcast,
 };
 
 /// Defines per-worker configuration options
@@ -265,6 +278,11 @@ pub struct WorkerConfig {
     /// Skips the single worker+client+namespace+task_queue check
     #[builder(default = false)]
     pub skip_client_worker_set_check: bool,
+
+    /// Finer control over how local activities are run, ex: per activity type concurrency limits
+    /// and default close timeouts. Only used if local activities are enabled in `task_types`.
+    #[builder(default)]
+    pub local_activity_options: LocalActivityManagerOptions,
 }
 
 impl WorkerConfig {
@@ -318,6 +336,7 @@ impl<S: worker_config_builder::IsComplete> WorkerConfigBuilder<S> {
         config.workflow_task_poller_behavior.validate()?;
         config.activity_task_poller_behavior.validate()?;
         config.nexus_task_poller_behavior.validate()?;
+        config.local_activity_options.validate()?;
 
         if let Some(ref x) = config.max_worker_activities_per_second
             && (!x.is_normal() || x.is_sign_negative())
@@ -441,6 +460,7 @@ pub(crate) struct WorkerTelemetry {
 }
 
 impl WorkerTelemetry {
+    #[cfg(any(feature = "test-utilities", test))]
     pub(crate) fn from_meter(meter: TemporalMeter) -> Self {
         Self {
             temporal_metric_meter: Some(meter),
@@ -751,12 +771,16 @@ impl Worker {
 
         let (local_act_mgr, la_sink, hb_rx) = if config.task_types.enable_local_activities {
             let (hb_tx, hb_rx) = unbounded_channel();
-            let local_act_mgr = Arc::new(LocalActivityManager::new(
-                config.namespace.clone(),
-                la_permit_dealer.clone(),
-                hb_tx,
-                metrics.clone(),
-            ));
+            let local_act_mgr = Arc::new(
+                LocalActivityManager::new(
+                    config.namespace.clone(),
+                    la_permit_dealer.clone(),
+                    hb_tx,
+                    metrics.clone(),
+                    config.local_activity_options.clone(),
+                )
+                .map_err(anyhow::Error::msg)?,
+            );
             let la_sink = LAReqSink::new(local_act_mgr.clone());
             (Some(local_act_mgr), Some(la_sink), Some(hb_rx))
         } else {
@@ -965,7 +989,25 @@ impl Worker {
         // We need to wait for all local activities to finish so no more workflow task heartbeats
         // will be generated
         if let Some(la_mgr) = &self.local_act_mgr {
-            la_mgr.wait_all_outstanding_tasks_finished().await;
+            // Captured up front so there is something to go on if draining never finishes
+            trace!(
+                diagnostics = ?la_mgr.diagnostic_snapshot(),
+                "Local activities at start of shutdown drain"
+            );
+            // Anything abandoned was already logged when it was given up on
+            let summary = match self.config.local_activity_options.shutdown_grace_period {
+                Some(grace) => {
+                    let _ = la_mgr.shutdown_with_timeout(grace).await;
+                    la_mgr.shutdown_summary()
+                }
+                None => la_mgr.wait_all_outstanding_tasks_finished().await,
+            };
+            debug!(
+                completed = summary.completed_during_shutdown,
+                aborted_backoffs = ?summary.aborted_backoffs,
+                lifetime = ?la_mgr.lifetime_stats(),
+                "Local activities finished draining for shutdown"
+            );
         }
         // Wait for workflows to finish
         if let Some(workflows) = &self.workflows {
@@ -1172,14 +1214,302 @@ impl Worker {
     /// be optimal behavior for the user as we don't want to break activity execution due to badly
     /// configured heartbeat options.
     pub fn record_activity_heartbeat(&self, details: ActivityHeartbeat) {
-        if let Some(at_mgr) = self.at_task_mgr.as_ref() {
-            let tt = TaskToken(details.task_token.clone());
-            if let Err(e) = at_mgr.record_heartbeat(details) {
-                warn!(task_token = %tt, details = ?e, "Activity heartbeat failed.");
+        let tt = TaskToken(details.task_token.clone());
+        if tt.is_local_activity_task() {
+            if let Some(la_mgr) = &self.local_act_mgr {
+                la_mgr.record_heartbeat(&tt, details.details);
             }
+            return;
+        }
+        if let Some(at_mgr) = self.at_task_mgr.as_ref()
+            && let Err(e) = at_mgr.record_heartbeat(details)
+        {
+            warn!(task_token = %tt, details = ?e, "Activity heartbeat failed.");
+        }
+    }
+
+    /// Passes on intermediate output (ex: percent complete) from an executing local activity, to
+    /// be delivered to [LocalActivityManagerOptions::progress_tx] before the attempt's completion
+    /// is processed. Progress for local activities which aren't executing is ignored.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn record_local_activity_progress(&self, task_token: &TaskToken, payload: Payload) {
+        if let Some(la_mgr) = &self.local_act_mgr {
+            la_mgr.record_progress(task_token, payload);
+        }
+    }
+
+    /// Returns every local activity waiting to be retried within core (or to start, if it has a
+    /// start delay), along with how long until its next attempt is dispatched. Useful for working
+    /// out why a workflow appears stuck.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn local_activities_backing_off(&self) -> Vec<(ExecutingLAId, Duration)> {
+        self.local_act_mgr
+            .as_ref()
+            .map(|la_mgr| la_mgr.backing_off())
+            .unwrap_or_default()
+    }
+
+    /// Returns the close timeouts configured for a local activity and whether their timers are
+    /// currently running, for diagnosing timeout misconfiguration. Returns `None` if the activity
+    /// isn't being tracked.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn local_activity_timeout_info(&self, id: &ExecutingLAId) -> Option<TimeoutInfo> {
+        self.local_act_mgr
+            .as_ref()
+            .and_then(|la_mgr| la_mgr.timeout_info(id))
+    }
+
+    /// Captures everything the local activity manager is tracking: executing attempts, backoffs,
+    /// close timeouts, queue depth and free slots. Serializable, so it can be dumped as JSON into
+    /// bug reports. Returns `None` if local activities are disabled.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn local_activity_diagnostics(&self) -> Option<LaDiagnostics> {
+        self.local_act_mgr
+            .as_ref()
+            .map(|la_mgr| la_mgr.diagnostic_snapshot())
+    }
+
+    /// Cancels only the currently executing attempt of a local activity. Lang is sent a cancel as
+    /// usual, but once it completes the attempt as cancelled, that is treated as a retryable
+    /// failure and the activity's retry policy decides whether there is another attempt. Returns
+    /// false if the activity has no attempt executing.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn abort_local_activity_attempt(&self, id: &ExecutingLAId) -> bool {
+        self.local_act_mgr
+            .as_ref()
+            .is_some_and(|la_mgr| la_mgr.abort_attempt(id))
+    }
+
+    /// Makes the run's local activities execute one at a time, in the order the workflow scheduled
+    /// them, even if more slots are free. Only applies to activities scheduled after this is
+    /// called, and lasts until the run's local activities are all cancelled (ex: on eviction).
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn serialize_local_activities(&self, run_id: &str) {
+        if let Some(la_mgr) = &self.local_act_mgr {
+            la_mgr.serialize_run(run_id.to_string());
+        }
+    }
+
+    /// Returns how many local activities are ready to be dispatched but are waiting for a slot to
+    /// free up, for understanding slot contention
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn local_activities_waiting_for_slots(&self) -> usize {
+        self.local_act_mgr
+            .as_ref()
+            .map_or(0, |la_mgr| la_mgr.pending_waiters())
+    }
+
+    /// Returns how many local activities of each type are currently executing in lang
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn local_activities_in_flight_by_type(&self) -> HashMap<String, usize> {
+        self.local_act_mgr
+            .as_ref()
+            .map(|la_mgr| la_mgr.in_flight_by_type())
+            .unwrap_or_default()
+    }
+
+    /// Returns the local activity which has been executing in lang the longest, along with how
+    /// long it has been running
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn longest_running_local_activity(&self) -> Option<(ExecutingLAId, Duration)> {
+        self.local_act_mgr
+            .as_ref()
+            .and_then(|la_mgr| la_mgr.longest_running())
+    }
+
+    /// Cancels the [longest running](Self::longest_running_local_activity) local activity if it has
+    /// been running for more than `threshold`, returning its id if so. Meant to be called
+    /// periodically by a watchdog looking for stuck activities.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn cancel_local_activity_running_over(&self, threshold: Duration) -> Option<ExecutingLAId> {
+        let (id, immediate) = self
+            .local_act_mgr
+            .as_ref()?
+            .cancel_longest_running_over(threshold)?;
+        if let Some(res) = immediate {
+            self.notify_local_result(&id.run_id, LocalResolution::LocalActivity(res));
+        }
+        Some(id)
+    }
+
+    /// Gives the executing attempt of a local activity `additional` more time before its
+    /// start-to-close timeout fires, for activities which only find out how long they need once
+    /// running. The schedule-to-close timeout can't be extended, so extensions past it are
+    /// rejected.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn extend_local_activity_start_to_close(
+        &self,
+        task_token: &TaskToken,
+        additional: Duration,
+    ) -> Result<(), ExtendTimeoutError> {
+        self.local_act_mgr
+            .as_ref()
+            .ok_or(ExtendTimeoutError::NoActiveStartToClose)?
+            .extend_start_to_close(task_token, additional)
+    }
+
+    /// Times out a local activity as though the given timer had fired. Used to feed in timeouts
+    /// which are already recorded in history when running in
+    /// [replay mode](LocalActivityManagerOptions::replay_mode), so that they resolve exactly as they
+    /// did live. Returns false if the activity isn't known or the timeout type doesn't apply to
+    /// it: start-to-close needs an executing attempt, and schedule-to-start one which hasn't
+    /// started yet.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn force_local_activity_timeout(
+        &self,
+        id: &ExecutingLAId,
+        timeout_type: TimeoutType,
+    ) -> bool {
+        self.local_act_mgr
+            .as_ref()
+            .is_some_and(|la_mgr| la_mgr.force_timeout(id, timeout_type))
+    }
+
+    /// Returns a cancellation token scoped to the run's local activities. Cancelling it cancels all
+    /// of them, as though the workflow had: executing attempts are sent cancels, and anything
+    /// waiting to be dispatched is resolved as cancelled. Once the run has no local activities
+    /// left, or is evicted, a new token is handed out. Returns `None` if this worker doesn't run
+    /// local activities.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn local_activity_cancellation_token(&self, run_id: &str) -> Option<CancellationToken> {
+        self.local_act_mgr
+            .as_ref()
+            .map(|la_mgr| la_mgr.run_cancellation_token(run_id))
+    }
+
+    /// Break-glass resolution of a local activity lang will never complete, ex: because the
+    /// process running it crashed. The attempt is failed with the provided failure, and that is
+    /// reported to the workflow as final regardless of the activity's retry policy.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn force_fail_local_activity(&self, task_token: &TaskToken, failure: Failure) {
+        if let Some(la_mgr) = &self.local_act_mgr
+            && self
+                .handle_la_complete_action(la_mgr.force_fail(task_token, failure))
+                .is_some()
+        {
+            dbg_panic!("Force failing a local activity should never retry it");
         }
     }
 
+    /// Returns a receiver which always holds the number of local activities executing in lang, for
+    /// showing how far along draining them is during shutdown. Returns `None` if this worker
+    /// doesn't run local activities.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn local_activity_shutdown_progress(&self) -> Option<watch::Receiver<usize>> {
+        self.local_act_mgr
+            .as_ref()
+            .map(|la_mgr| la_mgr.shutdown_progress())
+    }
+
+    /// Cheaply checks whether polling for activity tasks could produce any local activity work:
+    /// local activities executing, backing off, or waiting to be dispatched, or cancels and
+    /// timeouts waiting to be delivered. May briefly say there's work when there isn't, but never
+    /// the reverse.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn has_pending_local_activity_work(&self) -> bool {
+        self.local_act_mgr
+            .as_ref()
+            .is_some_and(|la_mgr| la_mgr.has_pending_work())
+    }
+
+    /// Replaces the retry policy of an executing local activity, so that whether (and when) its
+    /// current attempt is retried if it fails is decided by the new policy. Errors if the activity
+    /// has no attempt executing, or the policy is invalid.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn update_local_activity_retry_policy(
+        &self,
+        id: &ExecutingLAId,
+        policy: RetryPolicy,
+    ) -> Result<(), LocalActivityError> {
+        self.local_act_mgr
+            .as_ref()
+            .ok_or_else(|| LocalActivityError::NotOutstanding(id.clone()))?
+            .update_retry_policy(id, policy)
+    }
+
+    /// Returns the local activities with a cancel that hasn't been handed to lang yet, for figuring
+    /// out why a cancellation seems stuck
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn pending_local_activity_cancels(&self) -> Vec<ExecutingLAId> {
+        self.local_act_mgr
+            .as_ref()
+            .map(|la_mgr| la_mgr.pending_cancels())
+            .unwrap_or_default()
+    }
+
+    /// Cancels the local activity whose current attempt was dispatched with the given task token,
+    /// for callers which kept the token rather than the activity's id. Lang is sent the cancel
+    /// like any other. Returns false if no executing attempt has the token.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn cancel_local_activity_by_token(&self, task_token: &TaskToken) -> bool {
+        self.local_act_mgr
+            .as_ref()
+            .is_some_and(|la_mgr| la_mgr.cancel_by_token(task_token))
+    }
+
+    /// Returns a receiver of every lifecycle event of every local activity from now on, for
+    /// metrics, logging, or UIs which want to follow activities through their whole lifecycle.
+    /// Receivers which fall too far behind miss the oldest events rather than slowing the worker
+    /// down. Returns `None` if this worker doesn't run local activities.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn subscribe_local_activity_events(&self) -> Option<broadcast::Receiver<LaEvent>> {
+        self.local_act_mgr
+            .as_ref()
+            .map(|la_mgr| la_mgr.subscribe_events())
+    }
+
+    /// Cancels a local activity and waits for it to be resolved, returning the result it resolved
+    /// with. Activities which are backing off resolve immediately. Returns `None` if the activity
+    /// isn't known, or stops being tracked without resolving (ex: its run is evicted).
+    #[cfg(any(feature = "test-utilities", test))]
+    pub async fn cancel_local_activity_and_wait(
+        &self,
+        id: ExecutingLAId,
+    ) -> Option<LocalActivityExecutionResult> {
+        let la_mgr = self.local_act_mgr.as_ref()?;
+        Some(la_mgr.cancel_and_wait(id).await?.result)
+    }
+
+    /// Returns aggregated local activity metrics for one workflow run, for debugging specific
+    /// executions. Counters are discarded once the run completes or is evicted, after which this
+    /// returns all zeroes.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn local_activity_run_metrics(&self, run_id: &str) -> RunLaMetrics {
+        self.local_act_mgr
+            .as_ref()
+            .map(|la_mgr| la_mgr.run_metrics(run_id))
+            .unwrap_or_default()
+    }
+
+    /// Returns cumulative counts of local activity outcomes since the worker started, for health
+    /// reporting. Unlike [Self::local_activity_run_metrics] these are never reset. All zeroes if
+    /// local activities are disabled.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn local_activity_lifetime_stats(&self) -> LaLifetimeStats {
+        self.local_act_mgr
+            .as_ref()
+            .map(|la_mgr| la_mgr.lifetime_stats())
+            .unwrap_or_default()
+    }
+
+    /// Returns a future which resolves with the final result of one local activity, for
+    /// orchestration code which wants to await just that activity. Retries aren't final, so it
+    /// keeps waiting through them. Resolves to `None` if the activity isn't known, or stops being
+    /// tracked without resolving (ex: its run is evicted).
+    ///
+    /// The waiter is registered when this is called, not when the future is first polled.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn local_activity_completion(
+        &self,
+        id: ExecutingLAId,
+    ) -> impl Future<Output = Option<LocalActivityExecutionResult>> + use<> {
+        let done = self
+            .local_act_mgr
+            .as_ref()
+            .map(|la_mgr| la_mgr.completion_future(id));
+        async move { Some(done?.await?.result) }
+    }
+
     /// Tell the worker that an activity has finished executing. May (and should) be freely called
     /// concurrently.
     #[instrument(skip(self, completion),
@@ -1205,7 +1535,7 @@ impl Worker {
         validate_activity_completion(&status)?;
         if task_token.is_local_activity_task() {
             let as_la_res: LocalActivityExecutionResult = status.try_into()?;
-            self.complete_local_act(task_token, as_la_res);
+            self.complete_local_act(task_token, as_la_res).await;
             return Ok(());
         }
 
@@ -1406,6 +1736,15 @@ impl Worker {
         self.worker_instance_key
     }
 
+    /// Immediately fire every pending local activity timeout, as though its timer had elapsed.
+    /// Lets tests exercise local activity timeouts without actually waiting for them.
+    #[cfg(any(feature = "test-utilities", test))]
+    pub fn force_local_activity_timeouts(&self) {
+        if let Some(la_mgr) = &self.local_act_mgr {
+            la_mgr.force_timeouts();
+        }
+    }
+
     /// Sets a function to be called at the end of each activation completion
     pub(crate) fn set_post_activate_hook(
         &mut self,
@@ -1414,10 +1753,17 @@ impl Worker {
         self.post_activate_hook = Some(Box::new(callback))
     }
 
-    fn complete_local_act(&self, task_token: TaskToken, la_res: LocalActivityExecutionResult) {
+    async fn complete_local_act(
+        &self,
+        task_token: TaskToken,
+        la_res: LocalActivityExecutionResult,
+    ) {
         if let Some(la_mgr) = &self.local_act_mgr
-            && self
-                .handle_la_complete_action(la_mgr.complete(&task_token, la_res))
+            && la_mgr
+                .complete_and_notify(&task_token, la_res, |action| async move {
+                    self.handle_la_complete_action(action)
+                })
+                .await
                 .is_some()
         {
             dbg_panic!("Should never be a task from direct completion");
@@ -1436,6 +1782,31 @@ impl Worker {
             }
             LACompleteAction::WillBeRetried(task) => task,
             LACompleteAction::Untracked => None,
+            LACompleteAction::StaleToken(id) => {
+                debug!(
+                    ?id,
+                    "Discarding late completion of a retried local activity attempt"
+                );
+                None
+            }
+            LACompleteAction::UnknownToken(task_token) => {
+                error!(task_token=%task_token,
+                       "Lang completed a local activity with a token core never issued, which \
+                        indicates a serious bug. Shutting down worker.");
+                self.initiate_shutdown();
+                self.abort_local_activities();
+                None
+            }
+        }
+    }
+
+    /// Resolves every local activity as cancelled without waiting on lang, so workflows aren't
+    /// left waiting on them once the worker has hit an error it can't recover from.
+    fn abort_local_activities(&self) {
+        if let Some(la_mgr) = &self.local_act_mgr {
+            for (run_id, res) in la_mgr.abort_all() {
+                self.notify_local_result(&run_id, LocalResolution::LocalActivity(res));
+            }
         }
     }
 
@@ -2182,14 +2553,31 @@ mod tests {
     use super::*;
     use crate::{
         advance_fut,
-        test_help::test_worker_cfg,
+        protosext::{LACloseTimeouts, ValidScheduleLA},
+        test_help::{MocksHolder, QueueResponse, mock_worker, test_worker_cfg},
         worker::{
             PollerBehavior,
             client::mocks::{mock_manual_worker_client, mock_worker_client},
         },
     };
     use futures_util::FutureExt;
-    use temporalio_common::protos::temporal::api::workflowservice::v1::PollActivityTaskQueueResponse;
+    use temporalio_common::protos::{
+        coresdk::activity_task::activity_task,
+        temporal::api::workflowservice::v1::{
+            PollActivityTaskQueueResponse, PollNexusTaskQueueResponse,
+        },
+    };
+
+    /// A local activity with nothing but its sequence number set
+    fn new_la(seq: u32) -> NewLocalAct {
+        NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
 
     #[tokio::test]
     async fn activity_timeouts_maintain_permit() {
@@ -2266,6 +2654,228 @@ mod tests {
         );
     }
 
+    #[test]
+    fn invalid_local_activity_options_fail_validation() {
+        let mut la_opts = LocalActivityManagerOptions::default();
+        la_opts
+            .max_concurrent_per_type
+            .insert("limited".to_string(), 0);
+        let err = test_worker_cfg()
+            .local_activity_options(la_opts)
+            .build()
+            .err()
+            .unwrap();
+        assert!(err.contains("'limited'"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn local_activity_options_reach_the_manager() {
+        let mut la_opts = LocalActivityManagerOptions::default();
+        la_opts.denied_activity_types.insert("denied".to_string());
+        let cfg = test_worker_cfg()
+            .local_activity_options(la_opts)
+            .build()
+            .unwrap();
+        let worker = Worker::new_test(cfg, mock_worker_client());
+        let res = worker
+            .local_act_mgr
+            .as_ref()
+            .unwrap()
+            .enqueue([NewLocalAct {
+                schedule_cmd: ValidScheduleLA {
+                    seq: 1,
+                    activity_type: "denied".to_string(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+            .into()]);
+        assert_matches!(
+            res.immediate_resolutions.as_slice(),
+            [LocalActivityResolution {
+                result: LocalActivityExecutionResult::Failed(_),
+                ..
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn watchdog_can_cancel_long_running_local_activity() {
+        let worker = Worker::new_test(test_worker_cfg().build().unwrap(), mock_worker_client());
+        let la_mgr = worker.local_act_mgr.clone().unwrap();
+        la_mgr.enqueue([new_la(1).into()]);
+        la_mgr.next_pending().await.unwrap();
+        let (id, _) = worker.longest_running_local_activity().unwrap();
+        assert_eq!(id.seq_num, 1);
+
+        assert!(
+            worker
+                .cancel_local_activity_running_over(Duration::from_secs(60))
+                .is_none()
+        );
+        assert_eq!(
+            worker.cancel_local_activity_running_over(Duration::ZERO),
+            Some(id)
+        );
+        // Lang is told to cancel the attempt
+        assert_matches!(
+            la_mgr.next_pending().await.unwrap(),
+            NextPendingLAAction::Dispatch(ActivityTask {
+                variant: Some(activity_task::Variant::Cancel(_)),
+                ..
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn shutdown_hands_queued_cancels_to_lang_first() {
+        let mut mocks = MocksHolder::from_client_with_custom(
+            mock_worker_client(),
+            Some(stream::empty()),
+            None::<Vec<QueueResponse<PollActivityTaskQueueResponse>>>,
+            None::<Vec<QueueResponse<PollNexusTaskQueueResponse>>>,
+        );
+        mocks.worker_cfg(|w| {
+            w.task_types = WorkerTaskTypes {
+                enable_workflows: true,
+                enable_local_activities: true,
+                enable_remote_activities: false,
+                enable_nexus: false,
+            };
+        });
+        let worker = mock_worker(mocks);
+        let la_mgr = worker.local_act_mgr.clone().unwrap();
+        la_mgr.enqueue([new_la(1).into()]);
+        let task = la_mgr.next_pending().await.unwrap();
+        let tt = assert_matches!(task, NextPendingLAAction::Dispatch(t) => TaskToken(t.task_token));
+        assert!(worker.cancel_local_activity_by_token(&tt));
+        la_mgr.enqueue([new_la(2).into()]);
+
+        let lang = async {
+            // The cancel was flushed when shutdown started, so it comes before the new request
+            let cancel = la_mgr.next_pending().await.unwrap();
+            assert_matches!(
+                cancel,
+                NextPendingLAAction::Dispatch(ActivityTask {
+                    task_token,
+                    variant: Some(activity_task::Variant::Cancel(_)),
+                    ..
+                }) if task_token == tt.0
+            );
+            la_mgr.complete(&tt, LocalActivityExecutionResult::empty_cancel());
+        };
+        let workflows = async {
+            assert_matches!(
+                worker.poll_workflow_activation().await.unwrap_err(),
+                PollError::ShutDown
+            );
+        };
+        tokio::join!(worker.shutdown(), lang, workflows);
+        assert_eq!(la_mgr.num_outstanding(), 0);
+    }
+
+    #[tokio::test]
+    async fn local_activity_diagnostics_serialize_through_worker() {
+        let worker = Worker::new_test(test_worker_cfg().build().unwrap(), mock_worker_client());
+        let la_mgr = worker.local_act_mgr.clone().unwrap();
+        la_mgr.enqueue([NewLocalAct {
+            schedule_cmd: ValidScheduleLA {
+                seq: 1,
+                activity_type: "diag".to_string(),
+                close_timeouts: Some(LACloseTimeouts::StartOnly(Duration::from_secs(60))),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+        .into()]);
+        la_mgr.next_pending().await.unwrap();
+
+        let snapshot = serde_json::to_value(worker.local_activity_diagnostics().unwrap()).unwrap();
+        let mut keys: Vec<_> = snapshot.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "available_slots",
+                "backing_off",
+                "outstanding",
+                "queue_depth",
+                "timeouts"
+            ]
+        );
+        assert_eq!(snapshot["outstanding"][0]["id"]["seq_num"], 1);
+        assert_eq!(snapshot["outstanding"][0]["activity_type"], "diag");
+        assert_eq!(snapshot["outstanding"][0]["attempt"], 1);
+        assert!(snapshot["backing_off"].as_array().unwrap().is_empty());
+        // Timeout info is flattened next to the id
+        assert_eq!(snapshot["timeouts"][0]["id"]["seq_num"], 1);
+        assert_eq!(snapshot["timeouts"][0]["start_to_close_active"], true);
+        assert_eq!(snapshot["queue_depth"], 0);
+    }
+
+    #[tokio::test]
+    async fn stuck_local_activity_can_be_force_failed() {
+        let worker = Worker::new_test(test_worker_cfg().build().unwrap(), mock_worker_client());
+        let la_mgr = worker.local_act_mgr.clone().unwrap();
+        la_mgr.enqueue([new_la(1).into()]);
+        let task = la_mgr.next_pending().await.unwrap();
+        let tt = assert_matches!(task, NextPendingLAAction::Dispatch(t) => TaskToken(t.task_token));
+        worker.force_fail_local_activity(&tt, Failure::default());
+        assert_eq!(la_mgr.num_outstanding(), 0);
+    }
+
+    #[tokio::test]
+    async fn local_activity_lifetime_stats_available_from_worker() {
+        let worker = Worker::new_test(test_worker_cfg().build().unwrap(), mock_worker_client());
+        assert_eq!(
+            worker.local_activity_lifetime_stats(),
+            LaLifetimeStats::default()
+        );
+        let la_mgr = worker.local_act_mgr.clone().unwrap();
+        la_mgr.enqueue([new_la(1).into()]);
+        let task = la_mgr.next_pending().await.unwrap();
+        let tt = assert_matches!(task, NextPendingLAAction::Dispatch(t) => TaskToken(t.task_token));
+        la_mgr.complete(
+            &tt,
+            LocalActivityExecutionResult::Completed(Default::default()),
+        );
+        assert_eq!(
+            worker.local_activity_lifetime_stats(),
+            LaLifetimeStats {
+                dispatched: 1,
+                completed: 1,
+                failed_terminal: 0,
+                retried: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_local_activity_token_can_shut_down_worker() {
+        let cfg = test_worker_cfg()
+            .local_activity_options(LocalActivityManagerOptions {
+                unknown_token_policy: UnknownTokenPolicy::ErrorUnknown,
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        let worker = Worker::new_test(cfg, mock_worker_client());
+        let la_mgr = worker.local_act_mgr.clone().unwrap();
+        la_mgr.enqueue([new_la(1).into()]);
+        la_mgr.next_pending().await.unwrap();
+        assert_eq!(la_mgr.num_outstanding(), 1);
+        worker
+            .complete_activity_task(ActivityTaskCompletion {
+                task_token: TaskToken::new_local_activity_token(7_u32.to_le_bytes()).0,
+                result: Some(ActivityExecutionResult::ok(Default::default())),
+            })
+            .await
+            .unwrap();
+        assert!(worker.shutdown_token.is_cancelled());
+        // Lang may never finish the attempt, so it's resolved rather than waited on
+        assert_eq!(la_mgr.num_outstanding(), 0);
+    }
+
     fn default_versioning_strategy() -> WorkerVersioningStrategy {
         WorkerVersioningStrategy::None {
             build_id: String::new(),
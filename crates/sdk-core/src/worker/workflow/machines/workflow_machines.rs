@@ -363,6 +363,7 @@ impl WorkflowMachines {
                 attempt,
                 backoff,
                 original_schedule_time,
+                ..
             }) => {
                 let act_id = CommandID::LocalActivity(seq);
                 let mk = self.get_machine_key(act_id)?;
@@ -388,8 +389,17 @@ impl WorkflowMachines {
 
     /// Drain all queued local activities that need executing or cancellation
     pub(crate) fn drain_queued_local_activities(&mut self) -> Vec<LocalActRequest> {
-        self.local_activity_data
-            .take_all_reqs(&self.workflow_type, &self.workflow_id, &self.run_id)
+        let build_id = self
+            .current_wft_deployment_info
+            .as_ref()
+            .map(|v| v.build_id.as_str())
+            .filter(|b| !b.is_empty());
+        self.local_activity_data.take_all_reqs(
+            &self.workflow_type,
+            &self.workflow_id,
+            &self.run_id,
+            build_id,
+        )
     }
 
     /// Returns the number of local activities we know we need to execute but have not yet finished
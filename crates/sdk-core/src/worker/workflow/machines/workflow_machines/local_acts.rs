@@ -46,16 +46,20 @@ impl LocalActivityData {
         wf_type: &str,
         wf_id: &str,
         run_id: &str,
+        build_id: Option<&str>,
     ) -> Vec<LocalActRequest> {
         if self.am_terminating {
-            return vec![LocalActRequest::CancelAllInRun(run_id.to_string())];
+            return vec![LocalActRequest::CancelForWorkflowCompletion(
+                run_id.to_string(),
+            )];
         }
 
         self.cancel_requests
             .drain(..)
             .map(LocalActRequest::Cancel)
-            .chain(self.new_requests.drain(..).map(|sa| {
+            .chain(self.new_requests.drain(..).map(|mut sa| {
                 self.executing.insert(sa.seq);
+                let correlation_id = sa.correlation_id.take();
                 LocalActRequest::New(NewLocalAct {
                     schedule_time: SystemTime::now(),
                     schedule_cmd: sa,
@@ -64,6 +68,8 @@ impl LocalActivityData {
                         workflow_id: wf_id.to_string(),
                         run_id: run_id.to_string(),
                     },
+                    correlation_id,
+                    build_id: build_id.map(ToString::to_string),
                 })
             }))
             .collect()
@@ -98,3 +104,33 @@ impl LocalActivityData {
         self.am_terminating = true;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prost_dur;
+    use temporalio_common::protos::coresdk::workflow_commands::ScheduleLocalActivity;
+
+    #[test]
+    fn correlation_id_is_taken_from_the_command() {
+        let mut data = LocalActivityData::default();
+        data.enqueue(
+            ValidScheduleLA::from_schedule_la(
+                ScheduleLocalActivity {
+                    seq: 1,
+                    activity_id: "1".to_string(),
+                    start_to_close_timeout: Some(prost_dur!(from_secs(10))),
+                    correlation_id: "trace-1".to_string(),
+                    ..Default::default()
+                },
+                None,
+            )
+            .unwrap(),
+        );
+        let reqs = data.take_all_reqs("wf_type", "wf_id", "run_id", None);
+        let [LocalActRequest::New(act)] = reqs.as_slice() else {
+            panic!("Expected a single new local activity request");
+        };
+        assert_eq!(act.correlation_id.as_deref(), Some("trace-1"));
+    }
+}
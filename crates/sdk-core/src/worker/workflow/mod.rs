@@ -1624,7 +1624,11 @@ impl LocalActivityRequestSink for LAReqSink {
         if reqs.is_empty() {
             return vec![];
         }
-        self.lam.enqueue(reqs)
+        let res = self.lam.enqueue(reqs);
+        for (id, reason) in res.dropped {
+            debug!(?id, ?reason, "Local activity was not scheduled again");
+        }
+        res.immediate_resolutions
     }
 }
 
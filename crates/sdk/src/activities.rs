@@ -108,6 +108,8 @@ impl ActivityContext {
             retry_policy,
             is_local,
             priority,
+            non_idempotent,
+            first_attempt,
         } = task;
         let deadline = calculate_deadline(
             scheduled_time.as_ref(),
@@ -140,6 +142,8 @@ impl ActivityContext {
                     retry_policy,
                     is_local,
                     priority: priority.map(Into::into).unwrap_or_default(),
+                    non_idempotent,
+                    first_attempt,
                 },
             },
             input,
@@ -220,6 +224,12 @@ pub struct ActivityInfo {
     pub is_local: bool,
     /// Priority of this activity. If unset uses [Priority::default].
     pub priority: Priority,
+    /// Whether this local activity was scheduled as non-idempotent, meaning it will not be retried
+    /// after being cancelled or timing out, and must not be assumed safe to run again.
+    pub non_idempotent: bool,
+    /// True only for the very first attempt of this activity, including when it's a local
+    /// activity whose retries were scheduled with an explicit attempt number.
+    pub first_attempt: bool,
 }
 
 /// Returned as errors from activity functions.